@@ -3,27 +3,146 @@
 layout(location = 0) in vec3 fragNormal;
 layout(location = 1) in vec3 fragVert;
 layout(location = 2) in vec2 fragTexCoord;
+layout(location = 3) in vec4 fragColor;
+layout(location = 4) in vec2 fragTexCoord1;
+#ifdef HAS_TANGENT
+layout(location = 5) in vec4 fragTangent;
+#endif
 
 layout(set = 0, binding = 1) uniform Locals {
     mat4 view;
     mat3 normalView;
 };
 
-layout(set = 0, binding = 2) uniform Light {
-    vec3 position;
-    vec3 intensities; //a.k.a the color of the light
-} light;
+// Must match MAX_LIGHTS in renderer/lights.rs.
+#define MAX_LIGHTS 16
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_SPOT 2
+
+struct Light {
+    vec4 position; // w is the kind
+    vec4 direction; // w is the range, 0 for unlimited
+    vec4 color; // premultiplied by intensity
+    vec4 cone; // cos(outer angle), 1 / (cos(inner angle) - cos(outer angle))
+    vec4 shadow; // first shadow map layer, layer count (0 for no shadows)
+};
+
+layout(set = 0, binding = 2) uniform Lights {
+    uvec4 lightCount;
+    Light lights[MAX_LIGHTS];
+};
 
 layout(set = 0, binding = 3) uniform texture2D textureColor;
 layout(set = 0, binding = 4) uniform sampler samplerColor;
+// Occlusion and lightmap both use the second UV set.
+layout(set = 0, binding = 5) uniform texture2D textureOcclusion;
+layout(set = 0, binding = 6) uniform texture2D textureLightmap;
+
+layout(set = 0, binding = 7) uniform Material {
+    vec4 baseColor; // multiplies the base color texture
+    vec4 emissive; // w is 1 for unlit materials
+    vec4 baseColorTransform[2];
+    vec4 occlusionTransform[2];
+    vec4 normalTransform[2];
+    vec4 normalScale; // x only
+} material;
+
+// Must match MAX_SHADOW_LAYERS in renderer/shadows.rs.
+#define MAX_SHADOW_LAYERS 8
+
+layout(set = 0, binding = 8) uniform texture2DArray shadowMaps;
+layout(set = 0, binding = 9) uniform samplerShadow shadowSampler;
+layout(set = 0, binding = 10) uniform Shadows {
+    mat4 shadowMatrices[MAX_SHADOW_LAYERS];
+    vec4 cascadeSplits; // view distance at the far end of each cascade
+    vec4 shadowParams; // depth bias, texel size
+};
+
+// Tangent space, sampled with the base color's UV set and its own transform. Only read by layouts
+// with tangents.
+layout(set = 0, binding = 11) uniform texture2D textureNormal;
 
 layout(location = 0) out vec4 color;
 
+// Directional lights pick the cascade covering the fragment's view distance. The 3x3 PCF kernel
+// gets further smoothed by the sampler's linear filtering.
+float shadowFactor(Light light, vec3 position, float viewDistance) {
+    int layerCount = int(light.shadow.y);
+    if (layerCount == 0) {
+        return 1.0;
+    }
+    int cascade = 0;
+    while (cascade < layerCount - 1 && viewDistance > cascadeSplits[cascade]) {
+        cascade++;
+    }
+    int layer = int(light.shadow.x) + cascade;
+    vec4 clip = shadowMatrices[layer] * vec4(position, 1.0);
+    vec3 coord = clip.xyz / clip.w;
+    vec2 uv = coord.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || coord.z > 1.0) {
+        return 1.0;
+    }
+    float depth = coord.z - shadowParams.x;
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(x, y) * shadowParams.y;
+            lit += texture(sampler2DArrayShadow(shadowMaps, shadowSampler), vec4(uv + offset, layer, depth));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Punctual light falloff as described by KHR_lights_punctual.
+vec3 directLight(Light light, vec3 normal, vec3 position) {
+    uint kind = uint(light.position.w);
+    if (kind == LIGHT_DIRECTIONAL) {
+        return max(dot(normal, -light.direction.xyz), 0.0) * light.color.rgb;
+    }
+    vec3 offset = light.position.xyz - position;
+    float distanceSquared = max(dot(offset, offset), 0.0001);
+    vec3 toLight = offset * inversesqrt(distanceSquared);
+    float attenuation = 1.0 / distanceSquared;
+    float range = light.direction.w;
+    if (range > 0.0) {
+        float ratio = distanceSquared / (range * range);
+        attenuation *= clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    }
+    if (kind == LIGHT_SPOT) {
+        float cone = clamp((dot(-toLight, light.direction.xyz) - light.cone.x) * light.cone.y, 0.0, 1.0);
+        attenuation *= cone * cone;
+    }
+    return max(dot(normal, toLight), 0.0) * attenuation * light.color.rgb;
+}
+
+vec2 transformUv(vec4 transform[2], vec2 uv) {
+    vec3 point = vec3(uv, 1.0);
+    return vec2(dot(transform[0].xyz, point), dot(transform[1].xyz, point));
+}
+
 void main() {
-    vec3 normal = normalize(normalView * fragNormal);
-    vec3 fragPosition = vec3(view * vec4(fragVert, 1));
-    vec3 surfaceToLight = light.position - fragPosition;
-    float brightness = clamp(dot(normal, surfaceToLight) / (length(surfaceToLight) * length(normal)), 0, 1);
-    vec4 surfaceColor = texture(sampler2D(textureColor, samplerColor), fragTexCoord);
-    color = vec4(brightness * light.intensities * surfaceColor.rgb, surfaceColor.a);
+    vec3 normal = normalize(fragNormal);
+#ifdef HAS_TANGENT
+    vec3 tangent = normalize(fragTangent.xyz - normal * dot(normal, fragTangent.xyz));
+    vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+    vec2 normalUv = transformUv(material.normalTransform, fragTexCoord);
+    vec3 mapped = texture(sampler2D(textureNormal, samplerColor), normalUv).xyz * 2.0 - 1.0;
+    mapped.xy *= material.normalScale.x;
+    normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+#endif
+    vec2 baseUv = transformUv(material.baseColorTransform, fragTexCoord);
+    vec4 surfaceColor = material.baseColor * fragColor * texture(sampler2D(textureColor, samplerColor), baseUv);
+    if (material.emissive.w > 0.5) {
+        color = surfaceColor;
+        return;
+    }
+    vec2 occlusionUv = transformUv(material.occlusionTransform, fragTexCoord1);
+    float occlusion = texture(sampler2D(textureOcclusion, samplerColor), occlusionUv).r;
+    vec3 bakedLight = texture(sampler2D(textureLightmap, samplerColor), fragTexCoord1).rgb;
+    float viewDistance = -(view * vec4(fragVert, 1.0)).z;
+    vec3 lighting = bakedLight;
+    for (uint i = 0; i < min(lightCount.x, uint(MAX_LIGHTS)); i++) {
+        lighting += shadowFactor(lights[i], fragVert, viewDistance) * directLight(lights[i], normal, fragVert);
+    }
+    color = vec4(occlusion * lighting * surfaceColor.rgb + material.emissive.rgb, surfaceColor.a);
 }
@@ -1,25 +1,123 @@
 #version 450
 
-layout(location = 0) in vec3 position;
-layout(location = 1) in vec3 normal;
-layout(location = 2) in vec2 texCoord;
+// Attribute locations and the HAS_* flags come from the mesh's vertex layout.
+layout(location = POSITION_LOCATION) in vec3 position;
+#ifdef HAS_NORMAL
+layout(location = NORMAL_LOCATION) in vec3 normal;
+#endif
+#ifdef HAS_TEX_COORD0
+layout(location = TEX_COORD0_LOCATION) in vec2 texCoord;
+#endif
+#ifdef HAS_TEX_COORD1
+layout(location = TEX_COORD1_LOCATION) in vec2 texCoord1;
+#endif
+#ifdef HAS_COLOR
+layout(location = COLOR_LOCATION) in vec4 color;
+#endif
+#ifdef HAS_TANGENT
+layout(location = TANGENT_LOCATION) in vec4 tangent;
+#endif
+#if defined(HAS_JOINTS) && defined(HAS_WEIGHTS)
+layout(location = JOINTS_LOCATION) in uvec4 joints;
+layout(location = WEIGHTS_LOCATION) in vec4 weights;
+#endif
 layout(location = 3) in mat4 model;
 // layout(location = 4, 5, 6) in use by model
+// Inverse transpose of mat3(model), see Instance in model.rs.
+layout(location = 12) in mat3 normalMatrix;
+// layout(location = 13, 14) in use by normalMatrix
 
 layout(set = 0, binding = 0) uniform Locals {
     mat4 projection_view;
 };
 
+layout(set = 1, binding = 0) uniform Deformation {
+    uint jointCount;
+    uint targetCount;
+    uint vertexCount;
+};
+
+layout(std430, set = 1, binding = 1) buffer JointPalette {
+    mat4 jointMatrices[];
+};
+
+struct MorphDelta {
+    vec4 position;
+    vec4 normal;
+};
+
+layout(std430, set = 1, binding = 2) buffer MorphDeltas {
+    MorphDelta morphDeltas[];
+};
+
+layout(std430, set = 1, binding = 3) buffer MorphWeights {
+    float morphWeights[];
+};
+
 layout(location = 0) out vec3 fragNormal;
 layout(location = 1) out vec3 fragVert;
 layout(location = 2) out vec2 fragTexCoord;
+layout(location = 3) out vec4 fragColor;
+layout(location = 4) out vec2 fragTexCoord1;
+#ifdef HAS_TANGENT
+layout(location = 5) out vec4 fragTangent;
+#endif
 
 void main() {
+#ifdef HAS_NORMAL
+    vec3 morphedNormal = normal;
+#else
+    vec3 morphedNormal = vec3(0.0, 0.0, 1.0);
+#endif
+#ifdef HAS_TEX_COORD0
+    vec2 baseTexCoord = texCoord;
+#else
+    vec2 baseTexCoord = vec2(0.0);
+#endif
+    vec3 morphedPosition = position;
+    for (uint i = 0; i < targetCount; i++) {
+        float weight = morphWeights[uint(gl_InstanceIndex) * targetCount + i];
+        if (weight != 0.0) {
+            MorphDelta delta = morphDeltas[i * vertexCount + uint(gl_VertexIndex)];
+            morphedPosition += weight * delta.position.xyz;
+            morphedNormal += weight * delta.normal.xyz;
+        }
+    }
+
+    mat4 skin = mat4(1.0);
+#if defined(HAS_JOINTS) && defined(HAS_WEIGHTS)
+    if (jointCount > 0) {
+        uint base = uint(gl_InstanceIndex) * jointCount;
+        // Out of range joints are rejected at load time, but a stray index must never read
+        // another instance's palette.
+        uvec4 joint = min(joints, uvec4(jointCount - 1));
+        skin = weights.x * jointMatrices[base + joint.x]
+            + weights.y * jointMatrices[base + joint.y]
+            + weights.z * jointMatrices[base + joint.z]
+            + weights.w * jointMatrices[base + joint.w];
+    }
+#endif
+    vec4 skinnedPosition = skin * vec4(morphedPosition, 1.0);
+
     mat4 mvp = projection_view * model;
-    gl_Position = mvp * vec4(position, 1.0);
-    // convert from -1,1 Z to 0,1
+    // The camera's projection already maps depth to 0..1.
+    gl_Position = mvp * skinnedPosition;
 
-    fragTexCoord = texCoord;
-    fragNormal = normal;
-    fragVert = position;
+    fragTexCoord = baseTexCoord;
+#ifdef HAS_COLOR
+    fragColor = color;
+#else
+    fragColor = vec4(1.0);
+#endif
+#ifdef HAS_TEX_COORD1
+    fragTexCoord1 = texCoord1;
+#else
+    fragTexCoord1 = baseTexCoord;
+#endif
+    // Lighting happens in world space.
+    fragNormal = normalMatrix * mat3(skin) * morphedNormal;
+#ifdef HAS_TANGENT
+    fragTangent = vec4(mat3(model) * mat3(skin) * tangent.xyz, tangent.w);
+#endif
+    fragVert = (model * skinnedPosition).xyz;
 }
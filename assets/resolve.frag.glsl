@@ -0,0 +1,23 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform texture2D sceneColor;
+layout(set = 0, binding = 1) uniform sampler sceneSampler;
+layout(set = 0, binding = 2) uniform Resolve {
+    // xy is the number of scene texels per frame pixel along each axis.
+    uvec4 sampleGrid;
+};
+
+// Every frame pixel averages the block of scene texels it covers.
+void main() {
+    ivec2 grid = ivec2(sampleGrid.xy);
+    ivec2 origin = ivec2(gl_FragCoord.xy) * grid;
+    vec4 sum = vec4(0.0);
+    for (int y = 0; y < grid.y; y++) {
+        for (int x = 0; x < grid.x; x++) {
+            sum += texelFetch(sampler2D(sceneColor, sceneSampler), origin + ivec2(x, y), 0);
+        }
+    }
+    outColor = sum / float(grid.x * grid.y);
+}
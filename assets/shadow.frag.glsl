@@ -0,0 +1,5 @@
+#version 450
+
+// Shadow maps only need depth, which the rasterizer writes on its own.
+void main() {
+}
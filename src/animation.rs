@@ -0,0 +1,488 @@
+use cgmath::{InnerSpace, Quaternion, Vector3, VectorSpace};
+use hashbrown::hash_map::HashMap;
+
+use crate::model::Model;
+use crate::skin::{JointTransform, Pose, Skin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelProperty {
+    Translation,
+    Rotation,
+    Scale,
+    MorphWeights,
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub node: usize,
+    pub property: ChannelProperty,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+    pub components: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeSample {
+    pub translation: Option<Vector3<f32>>,
+    pub rotation: Option<Quaternion<f32>>,
+    pub scale: Option<Vector3<f32>>,
+    pub weights: Option<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnimationSample {
+    pub nodes: HashMap<usize, NodeSample>,
+}
+
+impl Channel {
+    fn keyframe_value(&self, key: usize) -> &[f32] {
+        let n = self.components;
+        match self.interpolation {
+            Interpolation::CubicSpline => &self.values[(key * 3 + 1) * n..(key * 3 + 2) * n],
+            _ => &self.values[key * n..(key + 1) * n],
+        }
+    }
+
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let n = self.components;
+        let key_count = self.times.len();
+        if key_count == 0 {
+            return vec![0.0; n];
+        }
+
+        let value = |key: usize| self.keyframe_value(key);
+
+        if time <= self.times[0] {
+            return value(0).to_vec();
+        }
+        if time >= self.times[key_count - 1] {
+            return value(key_count - 1).to_vec();
+        }
+
+        let next = self.times.iter().position(|&t| t > time).unwrap_or(key_count - 1);
+        let prev = next - 1;
+        let delta = self.times[next] - self.times[prev];
+        let t = if delta > 0.0 { (time - self.times[prev]) / delta } else { 0.0 };
+
+        match self.interpolation {
+            Interpolation::Step => value(prev).to_vec(),
+            Interpolation::Linear => {
+                if self.property == ChannelProperty::Rotation {
+                    let a = quaternion(value(prev));
+                    let b = quaternion(value(next));
+                    let b = if a.dot(b) < 0.0 { -b } else { b };
+                    let q = a.slerp(b, t).normalize();
+                    vec![q.v.x, q.v.y, q.v.z, q.s]
+                } else {
+                    value(prev).iter()
+                        .zip(value(next).iter())
+                        .map(|(a, b)| a + (b - a) * t)
+                        .collect()
+                }
+            }
+            Interpolation::CubicSpline => {
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let h10 = t3 - 2.0 * t2 + t;
+                let h01 = -2.0 * t3 + 3.0 * t2;
+                let h11 = t3 - t2;
+
+                let out_tangent = &self.values[(prev * 3 + 2) * n..(prev * 3 + 3) * n];
+                let in_tangent = &self.values[next * 3 * n..(next * 3 + 1) * n];
+                let mut result: Vec<f32> = (0..n)
+                    .map(|i| {
+                        h00 * value(prev)[i]
+                            + h10 * delta * out_tangent[i]
+                            + h01 * value(next)[i]
+                            + h11 * delta * in_tangent[i]
+                    })
+                    .collect();
+                if self.property == ChannelProperty::Rotation {
+                    let q = quaternion(&result).normalize();
+                    result = vec![q.v.x, q.v.y, q.v.z, q.s];
+                }
+                result
+            }
+        }
+    }
+}
+
+impl AnimationClip {
+    pub fn sample(&self, time: f32) -> AnimationSample {
+        let mut sample = AnimationSample::default();
+        for channel in &self.channels {
+            let values = channel.sample(time);
+            let node = sample.nodes.entry(channel.node).or_insert_with(NodeSample::default);
+            match channel.property {
+                ChannelProperty::Translation => node.translation = Some(Vector3::new(values[0], values[1], values[2])),
+                ChannelProperty::Rotation => node.rotation = Some(quaternion(&values)),
+                ChannelProperty::Scale => node.scale = Some(Vector3::new(values[0], values[1], values[2])),
+                ChannelProperty::MorphWeights => node.weights = Some(values),
+            }
+        }
+        sample
+    }
+}
+
+impl AnimationSample {
+    pub fn blend(&self, other: &AnimationSample, weight: f32) -> AnimationSample {
+        let mut nodes = HashMap::new();
+        for (&node, a) in &self.nodes {
+            let blended = match other.nodes.get(&node) {
+                Some(b) => a.blend(b, weight),
+                None => a.clone(),
+            };
+            nodes.insert(node, blended);
+        }
+        for (&node, b) in &other.nodes {
+            if !nodes.contains_key(&node) {
+                nodes.insert(node, b.clone());
+            }
+        }
+        AnimationSample { nodes }
+    }
+
+    pub fn apply_to_pose(&self, skin: &Skin, pose: &mut Pose) {
+        for (joint, local) in skin.joints.iter().zip(pose.local.iter_mut()) {
+            if let Some(node) = self.nodes.get(&joint.node) {
+                if let Some(translation) = node.translation {
+                    local.translation = translation;
+                }
+                if let Some(rotation) = node.rotation {
+                    local.rotation = rotation;
+                }
+                if let Some(scale) = node.scale {
+                    local.scale = scale;
+                }
+            }
+        }
+    }
+
+    pub fn apply_weights(&self, node: usize, weights: &mut Vec<f32>) {
+        if let Some(sampled) = self.nodes.get(&node).and_then(|node| node.weights.as_ref()) {
+            weights.clear();
+            weights.extend_from_slice(sampled);
+        }
+    }
+
+    // The node's animated transform is applied inside the model's placement, so instances keep
+    // their own position. Channels the clip doesn't animate keep their last value.
+    pub fn apply_to_model(&self, node: usize, model: &mut Model) {
+        if let Some(node) = self.nodes.get(&node) {
+            let mut transform = model.node_transform().unwrap_or_else(JointTransform::identity);
+            if let Some(translation) = node.translation {
+                transform.translation = translation;
+            }
+            if let Some(rotation) = node.rotation {
+                transform.rotation = rotation;
+            }
+            if let Some(scale) = node.scale {
+                transform.scale = scale;
+            }
+            model.set_node_transform(transform);
+        }
+    }
+}
+
+impl NodeSample {
+    fn blend(&self, other: &NodeSample, weight: f32) -> NodeSample {
+        NodeSample {
+            translation: blend_option(self.translation, other.translation, |a, b| a.lerp(b, weight)),
+            rotation: blend_option(self.rotation, other.rotation, |a, b| {
+                let b = if a.dot(b) < 0.0 { -b } else { b };
+                a.nlerp(b, weight)
+            }),
+            scale: blend_option(self.scale, other.scale, |a, b| a.lerp(b, weight)),
+            weights: match (&self.weights, &other.weights) {
+                (Some(a), Some(b)) => Some(a.iter().zip(b.iter()).map(|(a, b)| a + (b - a) * weight).collect()),
+                (a, b) => a.clone().or_else(|| b.clone()),
+            },
+        }
+    }
+}
+
+fn blend_option<T: Copy>(a: Option<T>, b: Option<T>, blend: impl Fn(T, T) -> T) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(blend(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn quaternion(values: &[f32]) -> Quaternion<f32> {
+    Quaternion::new(values[3], values[0], values[1], values[2])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackMode {
+    Loop,
+    Clamp,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClipState {
+    clip: usize,
+    time: f32,
+}
+
+impl ClipState {
+    fn advance(&mut self, delta: f32, duration: f32, mode: PlaybackMode) {
+        self.time += delta;
+        match mode {
+            PlaybackMode::Loop if duration > 0.0 => {
+                self.time %= duration;
+                if self.time < 0.0 {
+                    self.time += duration;
+                }
+            }
+            PlaybackMode::Loop => self.time = 0.0,
+            PlaybackMode::Clamp => self.time = self.time.max(0.0).min(duration),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+    primary: ClipState,
+    secondary: Option<ClipState>,
+    blend_weight: f32,
+    fade_speed: f32,
+    pub mode: PlaybackMode,
+    pub speed: f32,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: usize, mode: PlaybackMode) -> AnimationPlayer {
+        AnimationPlayer {
+            primary: ClipState { clip, time: 0.0 },
+            secondary: None,
+            blend_weight: 0.0,
+            fade_speed: 0.0,
+            mode,
+            speed: 1.0,
+        }
+    }
+
+    pub fn play(&mut self, clip: usize) {
+        self.primary = ClipState { clip, time: 0.0 };
+        self.secondary = None;
+        self.blend_weight = 0.0;
+        self.fade_speed = 0.0;
+    }
+
+    pub fn blend_with(&mut self, clip: usize, weight: f32) {
+        match self.secondary {
+            Some(ref state) if state.clip == clip => (),
+            _ => self.secondary = Some(ClipState { clip, time: 0.0 }),
+        }
+        self.blend_weight = weight.max(0.0).min(1.0);
+        self.fade_speed = 0.0;
+    }
+
+    pub fn cross_fade(&mut self, clip: usize, duration: f32) {
+        if duration <= 0.0 {
+            self.play(clip);
+            return;
+        }
+        self.blend_with(clip, 0.0);
+        self.fade_speed = 1.0 / duration;
+    }
+
+    // The clip that plays once a cross-fade finishes.
+    pub fn clip(&self) -> usize {
+        match self.secondary {
+            Some(secondary) if self.fade_speed > 0.0 => secondary.clip,
+            _ => self.primary.clip,
+        }
+    }
+
+    // Clip indices aren't checked when they are set, so states whose clip is missing stay where
+    // they are and sample returns None without its primary clip.
+    pub fn advance(&mut self, delta: f32, clips: &[AnimationClip]) {
+        let delta = delta * self.speed;
+        let mode = self.mode;
+        let advance = |state: &mut ClipState| {
+            if let Some(clip) = clips.get(state.clip) {
+                state.advance(delta, clip.duration, mode);
+            }
+        };
+        advance(&mut self.primary);
+        if let Some(ref mut secondary) = self.secondary {
+            advance(secondary);
+        }
+
+        if self.fade_speed > 0.0 {
+            self.blend_weight += delta.abs() * self.fade_speed;
+            if self.blend_weight >= 1.0 {
+                if let Some(secondary) = self.secondary.take() {
+                    self.primary = secondary;
+                }
+                self.blend_weight = 0.0;
+                self.fade_speed = 0.0;
+            }
+        }
+    }
+
+    pub fn sample(&self, clips: &[AnimationClip]) -> Option<AnimationSample> {
+        let primary = clips.get(self.primary.clip)?.sample(self.primary.time);
+        let secondary = self.secondary.and_then(|state| Some((clips.get(state.clip)?, state.time)));
+        match secondary {
+            Some((clip, time)) if self.blend_weight > 0.0 => Some(primary.blend(&clip.sample(time), self.blend_weight)),
+            _ => Some(primary),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(property: ChannelProperty, interpolation: Interpolation, times: &[f32], values: &[f32]) -> Channel {
+        let keys = match interpolation {
+            Interpolation::CubicSpline => times.len() * 3,
+            _ => times.len(),
+        };
+        Channel {
+            node: 0,
+            property,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+            components: values.len() / keys,
+        }
+    }
+
+    // A clip that moves node 0 along x.
+    fn clip(interpolation: Interpolation, times: &[f32], xs: &[f32]) -> AnimationClip {
+        let values: Vec<f32> = xs.iter().flat_map(|&x| vec![x, 0.0, 0.0]).collect();
+        AnimationClip {
+            name: "move".into(),
+            duration: *times.last().unwrap(),
+            channels: vec![channel(ChannelProperty::Translation, interpolation, times, &values)],
+        }
+    }
+
+    fn x(sample: &AnimationSample) -> f32 {
+        sample.nodes[&0].translation.unwrap().x
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn step_holds_each_key_until_the_next() {
+        let clip = clip(Interpolation::Step, &[0.0, 1.0, 2.0], &[1.0, 2.0, 3.0]);
+        let xs: Vec<f32> = [-1.0, 0.0, 0.5, 0.99, 1.0, 1.5, 2.0, 3.0].iter().map(|&t| x(&clip.sample(t))).collect();
+        assert_eq!(xs, vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn linear_interpolates_and_slerps_rotations() {
+        let clip = clip(Interpolation::Linear, &[0.0, 2.0, 3.0], &[0.0, 4.0, 2.0]);
+        assert!(approx(x(&clip.sample(0.5)), 1.0));
+        assert!(approx(x(&clip.sample(2.5)), 3.0));
+        assert_eq!(x(&clip.sample(5.0)), 2.0);
+
+        // Half way between the identity and a half turn around z is a quarter turn.
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let values = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+        let rotation = channel(ChannelProperty::Rotation, Interpolation::Linear, &[0.0, 1.0], &values);
+        let q = rotation.sample(0.5);
+        assert!(approx(q[2], half) && approx(q[3], half), "{:?}", q);
+        // A negated quarter turn is still a quarter turn, so half way is an eighth of a turn.
+        let values = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -half, -half];
+        let rotation = channel(ChannelProperty::Rotation, Interpolation::Linear, &[0.0, 1.0], &values);
+        let q = rotation.sample(0.5);
+        let eighth = std::f32::consts::PI / 8.0;
+        assert!(approx(q[2], eighth.sin()) && approx(q[3], eighth.cos()), "{:?}", q);
+    }
+
+    #[test]
+    fn cubic_spline_follows_the_tangents() {
+        // Keys are stored as in-tangent, value, out-tangent.
+        let cubic = |times: &[f32], values: &[f32]| {
+            channel(ChannelProperty::Translation, Interpolation::CubicSpline, times, values)
+        };
+        let flat = cubic(&[0.0, 1.0], &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        assert!(approx(flat.sample(0.5)[0], 0.5));
+        assert!(approx(flat.sample(0.25)[0], 0.15625));
+        assert_eq!(flat.sample(1.0), vec![1.0]);
+
+        // An out-tangent of 2 adds h10 * 2 = 0.25 half way, and the tangents scale with the key spacing.
+        let steep = cubic(&[0.0, 1.0], &[0.0, 0.0, 2.0, 0.0, 1.0, 0.0]);
+        assert!(approx(steep.sample(0.5)[0], 0.75));
+        let slow = cubic(&[0.0, 2.0], &[0.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+        assert!(approx(slow.sample(1.0)[0], 0.75));
+    }
+
+    #[test]
+    fn loop_wraps_and_clamp_holds_the_ends() {
+        let clips = [clip(Interpolation::Linear, &[0.0, 2.0], &[0.0, 2.0])];
+        let mut player = AnimationPlayer::new(0, PlaybackMode::Loop);
+        player.advance(2.5, &clips);
+        assert!(approx(x(&player.sample(&clips).unwrap()), 0.5));
+        player.advance(-1.0, &clips);
+        assert!(approx(x(&player.sample(&clips).unwrap()), 1.5));
+
+        let mut player = AnimationPlayer::new(0, PlaybackMode::Clamp);
+        player.advance(2.5, &clips);
+        assert_eq!(x(&player.sample(&clips).unwrap()), 2.0);
+        player.advance(-5.0, &clips);
+        assert_eq!(x(&player.sample(&clips).unwrap()), 0.0);
+    }
+
+    #[test]
+    fn blends_and_cross_fades_between_clips() {
+        let clips = [
+            clip(Interpolation::Step, &[0.0, 10.0], &[0.0, 0.0]),
+            clip(Interpolation::Step, &[0.0, 10.0], &[4.0, 4.0]),
+        ];
+        let mut player = AnimationPlayer::new(0, PlaybackMode::Loop);
+        player.blend_with(1, 0.25);
+        assert!(approx(x(&player.sample(&clips).unwrap()), 1.0));
+        assert_eq!(player.clip(), 0);
+
+        player.cross_fade(1, 2.0);
+        assert_eq!(player.clip(), 1);
+        player.advance(1.0, &clips);
+        assert!(approx(x(&player.sample(&clips).unwrap()), 2.0));
+        player.advance(1.0, &clips);
+        assert_eq!(x(&player.sample(&clips).unwrap()), 4.0);
+        assert_eq!(player.clip(), 1);
+    }
+
+    #[test]
+    fn skips_missing_clips() {
+        let clips = [clip(Interpolation::Linear, &[0.0, 2.0], &[0.0, 2.0])];
+        let mut player = AnimationPlayer::new(3, PlaybackMode::Loop);
+        player.advance(1.0, &[]);
+        assert!(player.sample(&[]).is_none());
+        player.advance(1.0, &clips);
+        assert!(player.sample(&clips).is_none());
+
+        // A missing clip to blend or fade to leaves the current clip playing on its own.
+        player.play(0);
+        player.blend_with(5, 0.5);
+        player.advance(1.0, &clips);
+        assert!(approx(x(&player.sample(&clips).unwrap()), 1.0));
+        player.cross_fade(7, 0.5);
+        player.advance(0.5, &clips);
+        assert!(player.sample(&clips).is_none());
+    }
+}
@@ -30,23 +30,33 @@ impl From<ShaderCompilationError> for AssetError {
 pub struct Assets {
     pub models: AssetStore<ModelData>,
     pub shaders: AssetStore<Vec<u8>>,
+    pub shader_sources: AssetStore<String>,
 }
 
 impl Assets {
     pub fn load() -> Result<Assets, AssetError> {
-        let cube = ModelData::load("assets/cube.glb")?;
-        let mut models = AssetStore::new();
-        models.insert("cube", cube);
+        let mut assets = Assets::load_shaders()?;
+        assets.models.insert("cube", ModelData::load("assets/cube.glb")?);
+        Ok(assets)
+    }
 
-        let vertex_shader = load_shader("assets/cube.vert.glsl", ShaderKind::Vertex)?;
-        let fragment_shader = load_shader("assets/cube.frag.glsl", ShaderKind::Fragment)?;
+    // Only what the renderer needs, for tools that render their own models.
+    pub fn load_shaders() -> Result<Assets, AssetError> {
         let mut shaders = AssetStore::new();
-        shaders.insert("vertex", vertex_shader);
-        shaders.insert("fragment", fragment_shader);
+        shaders.insert("shadow_fragment", load_shader("assets/shadow.frag.glsl", ShaderKind::Fragment)?);
+        shaders.insert("resolve_vertex", load_shader("assets/resolve.vert.glsl", ShaderKind::Vertex)?);
+        shaders.insert("resolve_fragment", load_shader("assets/resolve.frag.glsl", ShaderKind::Fragment)?);
+
+        // The main vertex and fragment shaders are compiled by the renderer for each vertex layout
+        // it draws.
+        let mut shader_sources = AssetStore::new();
+        shader_sources.insert("vertex", ::std::fs::read_to_string("assets/cube.vert.glsl").map_err(ShaderCompilationError::from)?);
+        shader_sources.insert("fragment", ::std::fs::read_to_string("assets/cube.frag.glsl").map_err(ShaderCompilationError::from)?);
 
         Ok(Assets {
-            models,
+            models: AssetStore::new(),
             shaders,
+            shader_sources,
         })
     }
 }
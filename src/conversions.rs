@@ -25,6 +25,22 @@ impl AsBytes for Matrix3<f32> {
     }
 }
 
+impl AsBytes for [f32; 4] {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            ::std::slice::from_raw_parts(self.as_ptr() as *const u8, 4 * 4)
+        }
+    }
+}
+
+impl AsBytes for [f32] {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            ::std::slice::from_raw_parts(self.as_ptr() as *const u8, self.len() * 4)
+        }
+    }
+}
+
 pub struct GpuBuffer {
     pub len: u32,
     buffer: wgpu::Buffer,
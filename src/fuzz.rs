@@ -0,0 +1,291 @@
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use crate::model_data::{ModelData, ModelLoadError};
+
+const CORPUS_DIR: &str = "assets/corpus";
+const DEFAULT_ITERATIONS: usize = 2000;
+const INTERESTING_NUMBERS: &[u32] = &[0, 1, 3, 4, 255, 65535, 65536, 0x7FFF_FFFF, 0x8000_0000, 0xFFFF_FFFF];
+const INTERESTING_TEXT: &[&str] = &["0", "-1", "1", "3", "4", "255", "65536", "4294967295", "99999999999", "1e39"];
+
+// Loads every file in the corpus, then loads random mutations of them. Loading may fail, but never panic.
+pub fn run(args: &[String]) {
+    let iterations = match args.first().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(iterations)) => iterations,
+        None => DEFAULT_ITERATIONS,
+        Some(Err(_)) => {
+            eprintln!("usage: voids fuzz-models [iterations]");
+            std::process::exit(2);
+        }
+    };
+
+    let seeds = corpus();
+    if seeds.is_empty() {
+        eprintln!("{} has no .gltf or .glb files", CORPUS_DIR);
+        std::process::exit(2);
+    }
+
+    panic::set_hook(Box::new(|_| ()));
+    let mut panics = Vec::new();
+    for seed in &seeds {
+        match load(seed) {
+            Ok(Ok(_)) => println!("{}: loaded", seed.display()),
+            Ok(Err(err)) => println!("{}: {}", seed.display(), err),
+            Err(message) => panics.push((seed.clone(), message)),
+        }
+    }
+
+    let output = std::env::temp_dir().join("voids-fuzz");
+    panics.extend(load_mutations(&seeds, iterations, &mut Random(0x9E37_79B9_7F4A_7C15), &output));
+    let _ = panic::take_hook();
+
+    for (path, message) in &panics {
+        eprintln!("{} panicked: {}", path.display(), message);
+    }
+    println!("{} corpus files and {} mutations loaded, {} panics", seeds.len(), iterations, panics.len());
+    if !panics.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn corpus() -> Vec<PathBuf> {
+    let mut seeds: Vec<PathBuf> = fs::read_dir(CORPUS_DIR)
+        .expect("could not read the corpus directory")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| extension(path).is_some())
+        .collect();
+    seeds.sort();
+    seeds
+}
+
+// Loads random mutations of the seeds from the output directory. Mutations that panicked are
+// kept there and returned with their panic messages.
+fn load_mutations(seeds: &[PathBuf], iterations: usize, random: &mut Random, output: &Path) -> Vec<(PathBuf, String)> {
+    fs::create_dir_all(output).expect("could not create the fuzzing directory");
+    let mut panics = Vec::new();
+    for iteration in 0..iterations {
+        let seed = &seeds[random.below(seeds.len())];
+        let bytes = mutate(random, &fs::read(seed).expect("could not read a corpus file"));
+        let mutant = output.join(format!("mutant-{}.{}", iteration, extension(seed).unwrap()));
+        fs::write(&mutant, &bytes).expect("could not write a mutated file");
+        match load(&mutant) {
+            Err(message) => panics.push((mutant, message)),
+            Ok(_) => fs::remove_file(&mutant).unwrap_or(()),
+        }
+    }
+    panics
+}
+
+fn load(path: &Path) -> Result<Result<ModelData, ModelLoadError>, String> {
+    let path = path.to_string_lossy().into_owned();
+    panic::catch_unwind(|| ModelData::load_uncached(&path)).map_err(|payload| {
+        payload.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "<unknown>".to_string())
+    })
+}
+
+fn extension(path: &Path) -> Option<&'static str> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("gltf") => Some("gltf"),
+        Some("glb") => Some("glb"),
+        _ => None,
+    }
+}
+
+fn mutate(random: &mut Random, bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    for _ in 0..1 + random.below(4) {
+        if bytes.is_empty() {
+            break;
+        }
+        let offset = random.below(bytes.len());
+        match random.below(5) {
+            0 => bytes[offset] ^= 1 << random.below(8),
+            1 => {
+                let value = INTERESTING_NUMBERS[random.below(INTERESTING_NUMBERS.len())].to_le_bytes();
+                let offset = offset & !3;
+                for (byte, &value) in bytes[offset..].iter_mut().zip(value.iter()) {
+                    *byte = value;
+                }
+            }
+            2 => bytes.truncate(offset),
+            3 => {
+                let end = (offset + random.below(64)).min(bytes.len());
+                let copy = bytes[offset..end].to_vec();
+                let at = random.below(bytes.len());
+                bytes.splice(at..at, copy);
+            }
+            _ => {
+                // Replace a number in the JSON with one that is likely to be out of range.
+                let start = match bytes[offset..].iter().position(u8::is_ascii_digit) {
+                    Some(start) => offset + start,
+                    None => continue,
+                };
+                let end = bytes[start..].iter().position(|byte| !byte.is_ascii_digit()).map_or(bytes.len(), |end| start + end);
+                let text = INTERESTING_TEXT[random.below(INTERESTING_TEXT.len())];
+                bytes.splice(start..end, text.bytes());
+            }
+        }
+    }
+    bytes
+}
+
+// xorshift64*, so runs are reproducible without pulling in a random number crate.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 33) as usize % bound.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &[&str] = &[
+        "extensions.gltf",
+        "lights.gltf",
+        "meshopt-filters.gltf",
+        "meshopt-sphere.gltf",
+        "normal-map-generated-tangents.gltf",
+        "normal-map.gltf",
+        "skinned-animated.gltf",
+        "triangle.glb",
+        "triangle.gltf",
+    ];
+
+    // Whether a corpus file failed for the reason it was written to exercise.
+    fn is_expected_error(name: &str, err: &ModelLoadError) -> bool {
+        use crate::model_data::ModelLoadError::*;
+        use gltf::accessor::{DataType, Dimensions};
+        use gltf::mesh::{Mode, Semantic};
+
+        let document = |path: &str, prefix: &str| path.starts_with(prefix);
+        match (name, err) {
+            ("absolute-uri.gltf", InvalidUri { uri, .. }) => uri == "/etc/passwd",
+            ("accessor-out-of-range.gltf", AccessorOutOfRange { accessor }) => *accessor == 2,
+            ("animation-missing-target.gltf", InvalidDocument { path, .. })
+            | ("animation-unknown-path.gltf", InvalidDocument { path, .. }) => document(path, "animations[0]"),
+            ("animation-unsorted-times.gltf", InvalidAnimationChannel { .. }) => true,
+            ("bad-data-uri.gltf", InvalidUri { reason, .. }) => *reason == "data URI is not valid base64",
+            ("camera-missing-projection.gltf", InvalidDocument { path, .. }) => document(path, "cameras[0]"),
+            ("dds-oversized.gltf", ImageTooLarge { .. }) => true,
+            ("dds-zero-size.gltf", InvalidTextureContainer { .. }) => true,
+            ("glb-chunk-overflow.glb", InvalidGlb { reason }) => *reason == "chunk extends past the end of the file",
+            ("glb-length-too-long.glb", InvalidGlb { reason })
+            | ("glb-length-underflow.glb", InvalidGlb { reason }) => {
+                *reason == "header length doesn't match the file size"
+            }
+            ("glb-missing-bin.glb", InvalidBuffer { reason, .. }) => *reason == "binary chunk is missing",
+            ("glb-truncated-header.glb", InvalidGlb { reason }) => *reason == "file is too short for a GLB header",
+            ("glb-wrong-version.glb", InvalidGlb { reason }) => *reason == "only GLB version 2 is supported",
+            ("image-without-mime.gltf", InvalidDocument { path, .. })
+            | ("image-without-source.gltf", InvalidDocument { path, .. }) => document(path, "images[0]"),
+            ("index-count.gltf", InvalidIndexCount { count, .. }) => *count == 2,
+            ("index-out-of-range.gltf", IndexOutOfRange { index, vertex_count, .. }) => {
+                *index == 7 && *vertex_count == 3
+            }
+            ("invalid-texture-transform.gltf", InvalidDocument { path, .. }) => path.ends_with("KHR_texture_transform"),
+            ("inverse-bind-count.gltf", InvalidSkin { .. }) => true,
+            ("light-missing.gltf", InvalidLight { light, reason }) => {
+                *light == 3 && *reason == "node refers to a missing light"
+            }
+            ("light-wide-cone.gltf", InvalidLight { light, reason }) => {
+                *light == 0 && *reason == "spot outer cone angle is wider than pi / 2"
+            }
+            ("joint-out-of-range.gltf", JointOutOfRange { joint, joint_count, .. }) => *joint == 1 && *joint_count == 1,
+            ("ktx2-oversized.gltf", ImageTooLarge { .. }) => true,
+            ("ktx2-zero-size.gltf", InvalidTextureContainer { .. }) => true,
+            ("meshopt-bad-filter.gltf", InvalidCompressedView { view, reason }) => {
+                *view == 0 && reason.starts_with("filter isn't valid")
+            }
+            ("meshopt-bad-mode.gltf", InvalidCompressedView { view, reason }) => {
+                *view == 1 && *reason == "unknown compression mode"
+            }
+            ("meshopt-bad-stride.gltf", InvalidCompressedView { view, .. })
+            | ("meshopt-count-mismatch.gltf", InvalidCompressedView { view, .. })
+            | ("meshopt-missing-buffer.gltf", InvalidCompressedView { view, .. })
+            | ("meshopt-truncated.gltf", InvalidCompressedView { view, .. }) => *view == 0,
+            ("meshopt-triangles-truncated.gltf", InvalidCompressedView { view, .. })
+            | ("meshopt-wrong-codec.gltf", InvalidCompressedView { view, .. }) => *view == 1,
+            ("misaligned-accessor.gltf", MisalignedAccessor { accessor }) => *accessor == 1,
+            ("mismatched-counts.gltf", MismatchedAttributeCount { semantic, expected, actual, .. }) => {
+                *semantic == Semantic::Normals && *expected == 3 && *actual == 2
+            }
+            ("missing-file-uri.gltf", Io(err)) => err.kind() == std::io::ErrorKind::NotFound,
+            ("missing-position-accessor.gltf", InvalidDocument { path, .. }) => document(path, "meshes[0]"),
+            ("node-cycle.gltf", InvalidNodeHierarchy { .. })
+            | ("node-self-parent.gltf", InvalidNodeHierarchy { .. })
+            | ("node-two-parents.gltf", InvalidNodeHierarchy { .. }) => true,
+            ("normal-map-tangent-type.gltf", UnexpectedAccessorType { dimensions, data_type, .. }) => {
+                *dimensions == Dimensions::Vec3 && *data_type == DataType::F32
+            }
+            ("normal-map-tex-coord.gltf", UnsupportedNormalTexCoord { tex_coord, .. }) => *tex_coord == 1,
+            ("oversized-image.gltf", ImageTooLarge { width, .. }) => *width == 65536,
+            ("quantized-without-extension.gltf", UnexpectedAccessorType { data_type, .. }) => {
+                *data_type == DataType::U16
+            }
+            ("short-buffer.gltf", InvalidBuffer { buffer, .. }) => *buffer == 0,
+            ("sparse-accessor.gltf", InvalidAccessor { reason, .. }) => *reason == "sparse accessors are not supported",
+            ("stride-too-small.gltf", InvalidAccessor { reason, .. }) => {
+                *reason == "byte stride is smaller than an element"
+            }
+            ("triangle-strip.gltf", UnsupportedPrimitiveMode { mode, .. }) => *mode == Mode::TriangleStrip,
+            ("unknown-image-format.gltf", UnsupportedImageFormat { mime_type }) => mime_type == "image/gif",
+            ("unsupported-required-extension.gltf", UnsupportedExtension { extension }) => {
+                extension == "KHR_draco_mesh_compression"
+            }
+            ("view-out-of-range.gltf", BufferViewOutOfRange { view }) => *view == 1,
+            ("wrong-accessor-type.gltf", UnexpectedAccessorType { dimensions, .. }) => *dimensions == Dimensions::Vec2,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn corpus_files_load_or_fail_as_intended() {
+        let seeds = corpus();
+        assert!(!seeds.is_empty(), "{} has no .gltf or .glb files", CORPUS_DIR);
+        let failures: Vec<String> = seeds
+            .iter()
+            .filter_map(|seed| {
+                let name = seed.file_name().unwrap().to_string_lossy();
+                match load(seed) {
+                    Ok(Ok(_)) if VALID.contains(&&*name) => None,
+                    Ok(Ok(_)) => Some(format!("{} loaded", name)),
+                    Ok(Err(err)) if VALID.contains(&&*name) => Some(format!("{} failed: {}", name, err)),
+                    Ok(Err(err)) if is_expected_error(&name, &err) => None,
+                    Ok(Err(err)) => Some(format!("{} failed with an unexpected error: {:?}", name, err)),
+                    Err(message) => Some(format!("{} panicked: {}", name, message)),
+                }
+            })
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn mutations_load_without_panicking() {
+        let output = std::env::temp_dir().join("voids-fuzz-test");
+        let panics = load_mutations(&corpus(), 300, &mut Random(0x2545_F491_4F6C_DD1D), &output);
+        let panics: Vec<String> =
+            panics.iter().map(|(path, message)| format!("{} panicked: {}", path.display(), message)).collect();
+        assert!(panics.is_empty(), "{}", panics.join("\n"));
+    }
+
+    #[test]
+    fn rejects_texture_containers_with_bad_extents() {
+        for name in &["dds-oversized.gltf", "dds-zero-size.gltf", "ktx2-oversized.gltf", "ktx2-zero-size.gltf"] {
+            let path = Path::new(CORPUS_DIR).join(name);
+            match load(&path) {
+                Ok(Err(_)) => (),
+                Ok(Ok(_)) => panic!("{} loaded", name),
+                Err(message) => panic!("{} panicked: {}", name, message),
+            }
+        }
+    }
+}
@@ -2,10 +2,19 @@ use wgpu::winit::{
     ElementState, Event, EventsLoop, KeyboardInput, VirtualKeyCode, Window, WindowEvent,
 };
 
+use crate::animation::{AnimationPlayer, PlaybackMode};
 use crate::assets::Assets;
-use crate::renderer::Renderer;
-use cgmath::{Vector3, Zero};
+use crate::renderer::{GraphicsSettings, Light, LightId, Renderer, ShadowSettings, SAMPLE_COUNTS};
+use cgmath::{Rad, Vector3, Zero};
 use crate::model::Model;
+use std::path::Path;
+use std::time::Instant;
+
+// Seconds it takes to cross-fade into the next animation clip.
+const ANIMATION_FADE: f32 = 0.3;
+const FLASHLIGHT_RANGE: f32 = 20.0;
+// G steps the first morph target's weight from 0 to 1 and back to 0.
+const MORPH_STEP: f32 = 0.25;
 
 pub fn run(title: &str, assets: &Assets) {
     let instance = wgpu::Instance::new();
@@ -35,11 +44,22 @@ pub fn run(title: &str, assets: &Assets) {
     };
     let mut swap_chain = device.create_swap_chain(&surface, &sc_desc);
 
-    let mut renderer = Renderer::init(&sc_desc, &mut device, assets);
-    renderer.add_model_group(&mut device, "cube", assets.models.find("cube").unwrap());
-    renderer.add_model("cube", Model::new(Vector3::new(0.0, 0.0, 0.0)));
-    renderer.add_model("cube", Model::new(Vector3::new(0.0, 2.0, 0.0)));
+    let mut renderer =
+        Renderer::init(&sc_desc, &mut device, assets, GraphicsSettings::default(), ShadowSettings::default()).unwrap();
+    let cube = assets.models.find("cube").unwrap();
+    renderer.add_model_group(&mut device, "cube", cube).unwrap();
+    renderer.start_from_scene(&mut device, cube);
+    for &position in &[Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 2.0, 0.0)] {
+        let mut model = Model::new(position);
+        if !cube.animations.is_empty() {
+            model.play_animation(AnimationPlayer::new(0, PlaybackMode::Loop));
+        }
+        renderer.add_model("cube", model);
+    }
 
+    // Spot light that follows the camera, toggled with L. K toggles its shadows.
+    let mut flashlight: Option<LightId> = None;
+    let mut last_frame = Instant::now();
     let mut running = true;
     while running {
         events_loop.poll_events(|event| match event {
@@ -82,10 +102,81 @@ pub fn run(title: &str, assets: &Assets) {
                         VirtualKeyCode::D => movement.x += 0.1,
                         VirtualKeyCode::R => movement.z += 0.1,
                         VirtualKeyCode::F => movement.z -= 0.1,
+                        VirtualKeyCode::F12 => match renderer.export_scene(Path::new("scene.glb"), |name| assets.models.find(name)) {
+                            Ok(()) => println!("Exported the scene to scene.glb"),
+                            Err(err) => eprintln!("Could not export the scene: {}", err),
+                        },
+                        VirtualKeyCode::N if !cube.animations.is_empty() => {
+                            for model in renderer.models_mut("cube") {
+                                if let Some(player) = model.animation_mut() {
+                                    let next = (player.clip() + 1) % cube.animations.len();
+                                    player.cross_fade(next, ANIMATION_FADE);
+                                }
+                            }
+                        }
+                        VirtualKeyCode::P => {
+                            for model in renderer.models_mut("cube") {
+                                if let Some(player) = model.animation_mut() {
+                                    player.mode = match player.mode {
+                                        PlaybackMode::Loop => PlaybackMode::Clamp,
+                                        PlaybackMode::Clamp => PlaybackMode::Loop,
+                                    };
+                                }
+                            }
+                        }
+                        VirtualKeyCode::L => match flashlight.take() {
+                            Some(id) => {
+                                renderer.remove_light(id);
+                            }
+                            None => {
+                                let light = Light::spot(
+                                    renderer.camera_position(),
+                                    renderer.camera_direction(),
+                                    Rad(0.2),
+                                    Rad(0.4),
+                                    [1.0, 0.95, 0.8],
+                                    50.0,
+                                ).with_range(FLASHLIGHT_RANGE);
+                                match renderer.add_light(light) {
+                                    Ok(id) => flashlight = Some(id),
+                                    Err(err) => eprintln!("Could not turn on the flashlight: {}", err),
+                                }
+                            }
+                        },
+                        VirtualKeyCode::K => {
+                            if let Some((id, light)) = flashlight.and_then(|id| renderer.light(id).map(|light| (id, *light))) {
+                                if let Err(err) = renderer.update_light(id, light.with_shadows(!light.casts_shadows)) {
+                                    eprintln!("Could not update the flashlight: {}", err);
+                                }
+                            }
+                        }
+                        VirtualKeyCode::G if !cube.morph_targets.is_empty() => {
+                            for model in renderer.models_mut("cube") {
+                                let mut weights = model.weights().to_vec();
+                                weights.resize(cube.morph_targets.len(), 0.0);
+                                weights[0] = (weights[0] + MORPH_STEP) % (1.0 + MORPH_STEP);
+                                model.set_weights(&weights);
+                            }
+                        }
+                        VirtualKeyCode::M => {
+                            let mut settings = *renderer.graphics_settings();
+                            let next = SAMPLE_COUNTS.iter().position(|&count| count == settings.samples).map_or(0, |i| i + 1);
+                            settings.samples = SAMPLE_COUNTS[next % SAMPLE_COUNTS.len()];
+                            match renderer.set_graphics_settings(&mut device, &sc_desc, settings) {
+                                Ok(()) => println!("Anti-aliasing samples: {}", settings.samples),
+                                Err(err) => eprintln!("Could not change the graphics settings: {}", err),
+                            }
+                        }
                         _ => (),
                     }
                     if !movement.is_zero() {
                         renderer.move_camera(&mut device, movement);
+                        if let Some((id, light)) = flashlight.and_then(|id| renderer.light(id).map(|light| (id, *light))) {
+                            let light = Light { position: renderer.camera_position(), direction: renderer.camera_direction(), ..light };
+                            if let Err(err) = renderer.update_light(id, light) {
+                                eprintln!("Could not move the flashlight: {}", err);
+                            }
+                        }
                     }
                 }
                 _ => {}
@@ -93,7 +184,14 @@ pub fn run(title: &str, assets: &Assets) {
             _ => (),
         });
 
+        let now = Instant::now();
+        let elapsed = now - last_frame;
+        last_frame = now;
+        renderer.update(elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9);
+
         let frame = swap_chain.get_next_texture();
-        renderer.render(&frame, &mut device);
+        if let Err(err) = renderer.render(&frame.view, &mut device) {
+            eprintln!("Could not render the frame: {}", err);
+        }
     }
 }
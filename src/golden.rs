@@ -0,0 +1,334 @@
+use cgmath::{Deg, Matrix4, Point3, Quaternion, Rad, Rotation3, SquareMatrix, Vector3};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::assets::Assets;
+use crate::headless::{self, Image};
+use crate::model::Model;
+use crate::model_data::{primitives, LightKind, ModelData, ModelLoadError, Projection, SceneCamera, SceneLight, Texture};
+use crate::renderer::GraphicsSettings;
+
+const REFERENCE_DIR: &str = "assets/golden";
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+// A pixel differs when any of its channels is further than this from the reference. Drivers
+// round lighting and blending a little differently, so exact matches are too strict.
+const CHANNEL_TOLERANCE: u8 = 8;
+// A scene fails when more than this share of its pixels differ, which leaves room for a few
+// edge pixels that rasterize differently.
+const MAX_DIFFERING_PIXELS: f32 = 0.002;
+
+struct GoldenScene {
+    name: &'static str,
+    build: fn() -> Result<Vec<(ModelData, Vec<Model>)>, ModelLoadError>,
+}
+
+const SCENES: &[GoldenScene] = &[
+    GoldenScene { name: "directional-shadow", build: directional_shadow },
+    GoldenScene { name: "point-lights", build: point_lights },
+    GoldenScene { name: "spot-light", build: spot_light },
+    GoldenScene { name: "transforms", build: transforms },
+];
+
+// Renders the reference scenes and compares them to the stored images. `bless` replaces the
+// stored images with the new renders instead.
+pub fn run(args: &[String]) {
+    let (bless, names) = parse_command(args);
+    let scenes: Vec<&GoldenScene> = if names.is_empty() {
+        SCENES.iter().collect()
+    } else {
+        names.iter()
+            .map(|name| SCENES.iter().find(|scene| scene.name == name).unwrap_or_else(|| {
+                let known: Vec<_> = SCENES.iter().map(|scene| scene.name).collect();
+                eprintln!("Unknown scene {}, expected one of: {}", name, known.join(", "));
+                std::process::exit(2);
+            }))
+            .collect()
+    };
+
+    match check(&scenes, bless) {
+        Ok(0) => (),
+        Ok(failures) => {
+            eprintln!("{} scenes differ from their references", failures);
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Returns whether to bless and the scene names. Without a command, the arguments are scene
+// names to check.
+fn parse_command(args: &[String]) -> (bool, &[String]) {
+    match args.split_first() {
+        Some((command, names)) if command == "check" => (false, names),
+        Some((command, names)) if command == "bless" => (true, names),
+        _ => (false, args),
+    }
+}
+
+// Returns the number of scenes that differ from their references. Scenes that can't be built or
+// rendered are errors.
+fn check(scenes: &[&GoldenScene], bless: bool) -> Result<usize, String> {
+    let assets = Assets::load_shaders().map_err(|err| format!("Could not load the shaders: {}", err))?;
+    let mut device = headless::create_device();
+    let output = std::env::temp_dir().join("voids-golden");
+    fs::create_dir_all(&output).expect("could not create the output directory");
+    if bless {
+        fs::create_dir_all(REFERENCE_DIR).expect("could not create the reference directory");
+    }
+
+    let mut failures = 0;
+    for scene in scenes {
+        let built = (scene.build)().map_err(|err| format!("Could not build {}: {}", scene.name, err))?;
+        let (model_data, instances): (Vec<_>, Vec<_>) = built.into_iter().unzip();
+        let models = model_data.iter().zip(instances).collect();
+        // Anti-aliasing keeps edges from flickering between drivers.
+        let settings = GraphicsSettings { samples: 4, ..GraphicsSettings::default() };
+        let image = headless::render_models(&mut device, &assets, models, WIDTH, HEIGHT, settings)
+            .map_err(|err| format!("Could not render {}: {}", scene.name, err))?;
+
+        let reference = reference_path(scene.name);
+        if bless {
+            image.save_png(&reference).expect("could not write the reference image");
+            println!("{}: blessed {}", scene.name, reference.display());
+            continue;
+        }
+
+        let result = match load_reference(&reference) {
+            Some(expected) => compare(&image, &expected),
+            None => Err(format!("no reference image at {}, run `voids golden bless {}`", reference.display(), scene.name)),
+        };
+        match result {
+            Ok(summary) => println!("{}: ok ({})", scene.name, summary),
+            Err(reason) => {
+                failures += 1;
+                let actual = output.join(format!("{}.actual.png", scene.name));
+                image.save_png(&actual).expect("could not write the rendered image");
+                let mut written = vec![actual];
+                if let Some(expected) = load_reference(&reference) {
+                    if let Some(diff) = diff_image(&image, &expected) {
+                        let path = output.join(format!("{}.diff.png", scene.name));
+                        diff.save_png(&path).expect("could not write the diff image");
+                        written.push(path);
+                    }
+                }
+                let written: Vec<_> = written.iter().map(|path| path.display().to_string()).collect();
+                eprintln!("{}: FAILED, {}. Wrote {}", scene.name, reason, written.join(" and "));
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(REFERENCE_DIR).join(format!("{}.png", name))
+}
+
+// References are written by save_png, so anything but 8-bit RGBA is treated as missing.
+pub fn load_reference(path: &Path) -> Option<Image> {
+    let decoder = png::Decoder::new(File::open(path).ok()?);
+    let (info, mut reader) = decoder.read_info().ok()?;
+    if info.color_type != png::ColorType::RGBA || info.bit_depth != png::BitDepth::Eight {
+        return None;
+    }
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).ok()?;
+    Some(Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+// The largest channel difference of every pixel.
+fn pixel_differences<'a>(actual: &'a Image, expected: &'a Image) -> impl Iterator<Item = u8> + 'a {
+    actual.pixels.chunks(4).zip(expected.pixels.chunks(4)).map(|(a, e)| {
+        a.iter().zip(e).map(|(a, e)| a.max(e) - a.min(e)).max().unwrap_or(0)
+    })
+}
+
+fn compare(actual: &Image, expected: &Image) -> Result<String, String> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        return Err(format!(
+            "the reference is {}x{} but the render is {}x{}",
+            expected.width, expected.height, actual.width, actual.height
+        ));
+    }
+    let (mut differing, mut largest) = (0, 0);
+    for difference in pixel_differences(actual, expected) {
+        largest = largest.max(difference);
+        if difference > CHANNEL_TOLERANCE {
+            differing += 1;
+        }
+    }
+    let total = actual.width * actual.height;
+    let summary = format!("{} of {} pixels differ, largest channel difference {}", differing, total, largest);
+    if differing as f32 > total as f32 * MAX_DIFFERING_PIXELS {
+        Err(summary)
+    } else {
+        Ok(summary)
+    }
+}
+
+// Differing pixels are red, brighter the more they differ, over a faded gray copy of the
+// reference.
+fn diff_image(actual: &Image, expected: &Image) -> Option<Image> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        return None;
+    }
+    let pixels = pixel_differences(actual, expected)
+        .zip(expected.pixels.chunks(4))
+        .flat_map(|(difference, e)| {
+            if difference > CHANNEL_TOLERANCE {
+                vec![128 + difference / 2, 0, 0, 255]
+            } else {
+                let gray = ((e[0] as u16 + e[1] as u16 + e[2] as u16) / 12) as u8;
+                vec![gray, gray, gray, 255]
+            }
+        })
+        .collect();
+    Some(Image {
+        width: actual.width,
+        height: actual.height,
+        pixels,
+    })
+}
+
+fn painted(mut model: ModelData, color: [f32; 4]) -> ModelData {
+    model.material.base_color = color;
+    model.texture = Texture::solid([1.0; 4]);
+    model
+}
+
+// glTF cameras and lights look down -Z, and the world is Z up.
+fn looking_at(eye: Point3<f32>, target: Point3<f32>) -> Matrix4<f32> {
+    Matrix4::look_at(eye, target, Vector3::unit_z()).invert().unwrap()
+}
+
+fn camera(eye: Point3<f32>, target: Point3<f32>) -> SceneCamera {
+    SceneCamera {
+        name: "golden".to_string(),
+        projection: Projection::Perspective {
+            yfov: Deg(45.0).into(),
+            aspect_ratio: None,
+            znear: 0.5,
+            zfar: Some(30.0),
+        },
+        transform: looking_at(eye, target),
+    }
+}
+
+fn light(kind: LightKind, eye: Point3<f32>, target: Point3<f32>, color: [f32; 3], intensity: f32, range: Option<f32>) -> SceneLight {
+    SceneLight {
+        name: "golden".to_string(),
+        kind,
+        color,
+        intensity,
+        range,
+        transform: looking_at(eye, target),
+    }
+}
+
+// A ground plane that also carries the scene's camera and lights.
+fn stage(camera: SceneCamera, lights: Vec<SceneLight>) -> Result<(ModelData, Vec<Model>), ModelLoadError> {
+    let mut ground = painted(primitives::plane(8.0, 8.0, 1, 1)?, [0.8, 0.8, 0.8, 1.0]);
+    ground.cameras.push(camera);
+    ground.lights = lights;
+    Ok((ground, vec![Model::new(Vector3::new(0.0, 0.0, 0.0))]))
+}
+
+fn directional_shadow() -> Result<Vec<(ModelData, Vec<Model>)>, ModelLoadError> {
+    let sun = light(LightKind::Directional, Point3::new(3.0, -2.0, 5.0), Point3::new(0.0, 0.0, 0.0), [1.0, 0.95, 0.9], 3.0, None);
+    Ok(vec![
+        stage(camera(Point3::new(4.0, -5.0, 4.0), Point3::new(0.0, 0.0, 0.5)), vec![sun])?,
+        (
+            painted(primitives::cuboid(Vector3::new(1.0, 1.0, 1.0))?, [0.9, 0.3, 0.2, 1.0]),
+            vec![Model::new(Vector3::new(0.0, 0.0, 0.5)), Model::new(Vector3::new(-1.5, 1.0, 0.5))],
+        ),
+    ])
+}
+
+fn point_lights() -> Result<Vec<(ModelData, Vec<Model>)>, ModelLoadError> {
+    let lights = vec![
+        light(LightKind::Point, Point3::new(-1.5, -1.0, 1.5), Point3::new(0.0, 0.0, 0.0), [1.0, 0.2, 0.2], 8.0, Some(6.0)),
+        light(LightKind::Point, Point3::new(1.5, -1.0, 1.5), Point3::new(0.0, 0.0, 0.0), [0.2, 0.4, 1.0], 8.0, None),
+    ];
+    Ok(vec![
+        stage(camera(Point3::new(0.0, -6.0, 3.0), Point3::new(0.0, 0.0, 0.5)), lights)?,
+        (primitives::uv_sphere(0.7, 32, 16)?, vec![Model::new(Vector3::new(-1.0, 0.0, 0.7))]),
+        (primitives::icosphere(0.7, 3)?, vec![Model::new(Vector3::new(1.0, 0.0, 0.7))]),
+    ])
+}
+
+fn spot_light() -> Result<Vec<(ModelData, Vec<Model>)>, ModelLoadError> {
+    let cone = LightKind::Spot { inner_cone_angle: Rad(0.3), outer_cone_angle: Rad(0.5) };
+    let spot = light(cone, Point3::new(0.0, -2.0, 4.0), Point3::new(0.0, 0.0, 0.0), [1.0, 1.0, 0.8], 40.0, None);
+    let mut torus = Model::new(Vector3::new(0.0, 0.0, 0.8));
+    torus.set_rotation(Quaternion::from_angle_x(Deg(60.0)));
+    Ok(vec![
+        stage(camera(Point3::new(3.0, -5.0, 3.0), Point3::new(0.0, 0.0, 0.5)), vec![spot])?,
+        (painted(primitives::torus(0.6, 0.2, 32, 16)?, [0.3, 0.8, 0.4, 1.0]), vec![torus]),
+    ])
+}
+
+// Rotated, scaled and translated instances, so a broken model matrix shows up as moved shapes.
+fn transforms() -> Result<Vec<(ModelData, Vec<Model>)>, ModelLoadError> {
+    let sun = light(LightKind::Directional, Point3::new(-2.0, -3.0, 4.0), Point3::new(0.0, 0.0, 0.0), [1.0; 3], 2.5, None);
+    let instance = |position: Vector3<f32>, rotation: Quaternion<f32>, scale: f32| {
+        let mut model = Model::new(position);
+        model.set_rotation(rotation);
+        model.set_scale(scale);
+        model
+    };
+    Ok(vec![
+        stage(camera(Point3::new(0.0, -7.0, 4.0), Point3::new(0.0, 0.0, 0.5)), vec![sun])?,
+        (
+            painted(primitives::cylinder(0.4, 1.2, 24)?, [0.2, 0.5, 0.9, 1.0]),
+            vec![instance(Vector3::new(-2.0, 0.0, 0.6), Quaternion::from_angle_y(Deg(0.0)), 1.0)],
+        ),
+        (
+            painted(primitives::cone(0.5, 1.0, 24)?, [0.9, 0.7, 0.1, 1.0]),
+            vec![instance(Vector3::new(0.0, 0.0, 0.75), Quaternion::from_angle_y(Deg(90.0)), 1.5)],
+        ),
+        (
+            painted(primitives::capsule(0.3, 1.0, 24, 6)?, [0.7, 0.2, 0.8, 1.0]),
+            vec![instance(Vector3::new(2.0, 0.5, 0.5), Quaternion::from_angle_x(Deg(90.0)) * Quaternion::from_angle_z(Deg(30.0)), 0.75)],
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn scene_names_without_a_command_are_checked() {
+        let args = strings(&["spot-light", "transforms"]);
+        assert_eq!(parse_command(&args), (false, &args[..]));
+        assert_eq!(parse_command(&[]), (false, &[][..]));
+    }
+
+    #[test]
+    fn commands_come_before_the_scene_names() {
+        let args = strings(&["check", "spot-light"]);
+        assert_eq!(parse_command(&args), (false, &args[1..]));
+        let args = strings(&["bless"]);
+        assert_eq!(parse_command(&args), (true, &args[1..]));
+    }
+
+    // Needs a GPU adapter, so it only runs with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn scenes_match_their_references() {
+        let scenes: Vec<&GoldenScene> = SCENES.iter().collect();
+        assert_eq!(check(&scenes, false), Ok(0));
+    }
+}
@@ -0,0 +1,170 @@
+use cgmath::Vector3;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::assets::Assets;
+use crate::model::{Model, ModelGroupError};
+use crate::model_data::ModelData;
+use crate::renderer::{GraphicsSettings, GraphicsSettingsError, OffscreenTarget, ReadbackError, Renderer, ShadowSettings};
+use crate::shader::ShaderCompilationError;
+
+#[derive(Debug, Error)]
+pub enum HeadlessError {
+    #[error(display = "could not create the renderer")]
+    InvalidSettings(#[error(cause)] GraphicsSettingsError),
+    #[error(display = "could not create the model's pipeline")]
+    PipelineFailed(#[error(cause)] ShaderCompilationError),
+    #[error(display = "could not render the frame")]
+    RenderFailed(#[error(cause)] ModelGroupError),
+    #[error(display = "could not read the rendered image back")]
+    ReadbackFailed(#[error(cause)] ReadbackError),
+    #[error(display = "could not write the image file")]
+    FileError(#[error(cause)] std::io::Error),
+    #[error(display = "could not encode the image")]
+    ImageEncodeFailed(#[error(cause)] png::EncodingError),
+}
+
+impl From<GraphicsSettingsError> for HeadlessError {
+    fn from(err: GraphicsSettingsError) -> HeadlessError {
+        HeadlessError::InvalidSettings(err)
+    }
+}
+
+impl From<ShaderCompilationError> for HeadlessError {
+    fn from(err: ShaderCompilationError) -> HeadlessError {
+        HeadlessError::PipelineFailed(err)
+    }
+}
+
+impl From<ModelGroupError> for HeadlessError {
+    fn from(err: ModelGroupError) -> HeadlessError {
+        HeadlessError::RenderFailed(err)
+    }
+}
+
+impl From<ReadbackError> for HeadlessError {
+    fn from(err: ReadbackError) -> HeadlessError {
+        HeadlessError::ReadbackFailed(err)
+    }
+}
+
+impl From<std::io::Error> for HeadlessError {
+    fn from(err: std::io::Error) -> HeadlessError {
+        HeadlessError::FileError(err)
+    }
+}
+
+impl From<png::EncodingError> for HeadlessError {
+    fn from(err: png::EncodingError) -> HeadlessError {
+        HeadlessError::ImageEncodeFailed(err)
+    }
+}
+
+// Tightly packed RGBA8 pixels, top row first.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn save_png(&self, path: &Path) -> Result<(), HeadlessError> {
+        use png::HasParameters;
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+// No window or surface is created, so this also works on machines without a display or GPU as
+// long as a software driver is installed, e.g. lavapipe or SwiftShader for Vulkan.
+pub fn create_device() -> wgpu::Device {
+    let instance = wgpu::Instance::new();
+    let adapter = instance.get_adapter(&wgpu::AdapterDescriptor {
+        power_preference: wgpu::PowerPreference::Default,
+    });
+    adapter.create_device(&wgpu::DeviceDescriptor {
+        extensions: wgpu::Extensions {
+            anisotropic_filtering: false,
+        },
+    })
+}
+
+// Renders one frame of the model at the origin, seen from its first camera if it has one.
+pub fn render_model(
+    device: &mut wgpu::Device,
+    assets: &Assets,
+    model: &ModelData,
+    width: u32,
+    height: u32,
+    settings: GraphicsSettings,
+) -> Result<Image, HeadlessError> {
+    let instances = vec![Model::new(Vector3::new(0.0, 0.0, 0.0))];
+    render_models(device, assets, vec![(model, instances)], width, height, settings)
+}
+
+// Renders one frame with every model drawn at its instances. The cameras and lights of the first
+// model set up the view, like start_from_scene does for a game.
+pub fn render_models(
+    device: &mut wgpu::Device,
+    assets: &Assets,
+    models: Vec<(&ModelData, Vec<Model>)>,
+    width: u32,
+    height: u32,
+    settings: GraphicsSettings,
+) -> Result<Image, HeadlessError> {
+    let target = OffscreenTarget::new(device, width, height);
+    let mut renderer = Renderer::init(target.descriptor(), device, assets, settings, ShadowSettings::default())?;
+    if let Some((scene, _)) = models.first() {
+        renderer.start_from_scene(device, scene);
+    }
+    for (index, (model_data, instances)) in models.into_iter().enumerate() {
+        let group_name = format!("model-{}", index);
+        renderer.add_model_group(device, &group_name, model_data)?;
+        for instance in instances {
+            renderer.add_model(&group_name, instance);
+        }
+    }
+    renderer.render(target.view(), device)?;
+    Ok(Image {
+        width,
+        height,
+        pixels: target.read_pixels(device)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::load_reference;
+    use crate::model_data::primitives;
+
+    #[test]
+    fn saved_images_load_back_as_references() {
+        let image = Image {
+            width: 3,
+            height: 2,
+            pixels: (0..24).map(|byte| byte * 10).collect(),
+        };
+        let path = std::env::temp_dir().join("voids-headless-round-trip.png");
+        image.save_png(&path).unwrap();
+        let loaded = load_reference(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.pixels, image.pixels);
+    }
+
+    // Needs a GPU adapter, so it only runs with `cargo test -- --ignored`. The width isn't a
+    // multiple of 64 pixels, so the readback has to drop row padding.
+    #[test]
+    #[ignore]
+    fn renders_a_model_at_the_requested_size() {
+        let assets = Assets::load_shaders().unwrap();
+        let mut device = create_device();
+        let model = primitives::cuboid(Vector3::new(1.0, 1.0, 1.0)).unwrap();
+        let image = render_model(&mut device, &assets, &model, 50, 30, GraphicsSettings::default()).unwrap();
+        assert_eq!((image.width, image.height), (50, 30));
+        assert_eq!(image.pixels.len(), 4 * 50 * 30);
+    }
+}
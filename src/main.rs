@@ -1,20 +1,192 @@
 #[macro_use]
 extern crate err_derive;
-#[macro_use]
 extern crate itertools;
 
-use model_data::ModelData;
+use cgmath::{Matrix4, Vector3};
+use model_data::{primitives, BlockFormat, GltfExporter, ModelData};
 use crate::assets::Assets;
+use crate::renderer::GraphicsSettings;
+use std::path::Path;
 
 mod assets;
+mod fuzz;
 mod game;
+mod golden;
+mod headless;
 mod renderer;
 mod shader;
 mod model_data;
 mod model;
 mod conversions;
+mod skin;
+mod animation;
 
 fn main() {
-    let assets = Assets::load().unwrap();
-    game::run("Voids", &assets);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("convert-texture") => convert_texture(&args[1..]),
+        Some("fuzz-models") => fuzz::run(&args[1..]),
+        Some("export-model") => export_model(&args[1..]),
+        Some("export-primitives") => export_primitives(&args[1..]),
+        Some("optimize-model") => optimize_model(&args[1..]),
+        Some("render-model") => render_model(&args[1..]),
+        Some("golden") => golden::run(&args[1..]),
+        _ => {
+            let assets = Assets::load().unwrap();
+            game::run("Voids", &assets);
+        }
+    }
+}
+
+fn convert_texture(args: &[String]) {
+    let linear = args.iter().any(|arg| arg == "--linear");
+    let args: Vec<&String> = args.iter().filter(|arg| *arg != "--linear").collect();
+    let format = match args.get(2) {
+        Some(name) => BlockFormat::from_name(name),
+        None => Some(BlockFormat::Bc7),
+    };
+    let (input, output, format) = match (args.get(0), args.get(1), format) {
+        (Some(input), Some(output), Some(format)) if args.len() <= 3 => (input, output, format),
+        _ => {
+            eprintln!("usage: voids convert-texture <input.png> <output.dds|output.ktx2> [bc1|bc3|bc4|bc5|bc7] [--linear]");
+            std::process::exit(2);
+        }
+    };
+
+    match model_data::convert_png(Path::new(input), Path::new(output), format, !linear) {
+        Ok(texture) => println!(
+            "Wrote {} ({:?}, {}x{})",
+            output, texture.format, texture.width, texture.height
+        ),
+        Err(err) => {
+            eprintln!("Could not convert {}: {}", input, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Exports a model to .glb and loads it back to check that nothing was lost on the way.
+fn export_model(args: &[String]) {
+    let (input, output) = match args {
+        [input, output] => (input, output),
+        _ => {
+            eprintln!("usage: voids export-model <input.gltf|input.glb|input.obj> <output.glb>");
+            std::process::exit(2);
+        }
+    };
+
+    let model = ModelData::load_uncached(input).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", input, err);
+        std::process::exit(1);
+    });
+    let name = Path::new(input).file_stem().map_or("model".into(), |stem| stem.to_string_lossy());
+    if let Err(err) = model_data::export_model(&model, &name, Path::new(output)) {
+        eprintln!("Could not export {}: {}", output, err);
+        std::process::exit(1);
+    }
+    round_trip(&model, output);
+}
+
+// Prints what the optimizations applied to loaded models do to the vertex cache efficiency.
+fn optimize_model(args: &[String]) {
+    let input = match args {
+        [input] => input,
+        _ => {
+            eprintln!("usage: voids optimize-model <input.gltf|input.glb|input.obj>");
+            std::process::exit(2);
+        }
+    };
+
+    let mut model = ModelData::load_uncached(input).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", input, err);
+        std::process::exit(1);
+    });
+    println!("Optimized {}, {}", input, model.optimize());
+}
+
+// Exports every generated primitive side by side, mostly to inspect them in other tools.
+fn export_primitives(args: &[String]) {
+    let output = match args {
+        [output] => output,
+        _ => {
+            eprintln!("usage: voids export-primitives <output.glb>");
+            std::process::exit(2);
+        }
+    };
+
+    let shapes = vec![
+        ("cuboid", primitives::cuboid(Vector3::new(1.0, 1.0, 1.0))),
+        ("uv_sphere", primitives::uv_sphere(0.5, 24, 12)),
+        ("icosphere", primitives::icosphere(0.5, 2)),
+        ("plane", primitives::plane(1.0, 1.0, 4, 4)),
+        ("cylinder", primitives::cylinder(0.5, 1.0, 24)),
+        ("cone", primitives::cone(0.5, 1.0, 24)),
+        ("torus", primitives::torus(0.4, 0.1, 24, 12)),
+        ("capsule", primitives::capsule(0.3, 1.0, 24, 6)),
+    ];
+    let mut exporter = GltfExporter::new();
+    for (i, (name, shape)) in shapes.iter().enumerate() {
+        let shape = shape.as_ref().unwrap_or_else(|err| {
+            eprintln!("Could not generate {}: {}", name, err);
+            std::process::exit(1);
+        });
+        let transform = Matrix4::from_translation(Vector3::new(i as f32 * 1.5, 0.0, 0.0));
+        if let Err(err) = exporter.add_model(name, shape, &[transform]) {
+            eprintln!("Could not export {}: {}", name, err);
+            std::process::exit(1);
+        }
+    }
+    if let Err(err) = exporter.save(Path::new(output)) {
+        eprintln!("Could not write {}: {}", output, err);
+        std::process::exit(1);
+    }
+    println!("Wrote {} primitives to {}", shapes.len(), output);
+}
+
+// Renders a model without opening a window, for screenshots and thumbnails.
+fn render_model(args: &[String]) {
+    let number = |index: usize, default: u32| args.get(index).map_or(Some(default), |arg| arg.parse().ok());
+    let (input, output, width, height, samples) = match (args.get(0), args.get(1), number(2, 512), number(3, 512), number(4, 1)) {
+        (Some(input), Some(output), Some(width), Some(height), Some(samples))
+            if args.len() != 3 && args.len() <= 5 && width > 0 && height > 0 => (input, output, width, height, samples),
+        _ => {
+            eprintln!("usage: voids render-model <input.gltf|input.glb|input.obj> <output.png> [width height [samples]]");
+            std::process::exit(2);
+        }
+    };
+    let settings = GraphicsSettings { samples, ..GraphicsSettings::default() };
+    if let Err(reason) = settings.check(width, height) {
+        eprintln!("Invalid graphics settings: {}", reason);
+        std::process::exit(2);
+    }
+
+    let assets = Assets::load_shaders().unwrap();
+    let model = ModelData::load(input).unwrap_or_else(|err| {
+        eprintln!("Could not load {}: {}", input, err);
+        std::process::exit(1);
+    });
+    let mut device = headless::create_device();
+    let image = headless::render_model(&mut device, &assets, &model, width, height, settings)
+        .and_then(|image| image.save_png(Path::new(output)));
+    match image {
+        Ok(()) => println!("Rendered {} to {} ({}x{}, {} samples)", input, output, width, height, samples),
+        Err(err) => {
+            eprintln!("Could not render {}: {}", input, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn round_trip(model: &ModelData, output: &str) {
+    let reloaded = ModelData::load_uncached(output).unwrap_or_else(|err| {
+        eprintln!("Could not load the exported {}: {}", output, err);
+        std::process::exit(1);
+    });
+    let differences = model_data::differences(model, &reloaded);
+    if differences.is_empty() {
+        println!("Wrote {}, it loads back identically", output);
+    } else {
+        eprintln!("Wrote {}, but these differ after loading it back: {}", output, differences.join(", "));
+        std::process::exit(1);
+    }
 }
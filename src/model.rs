@@ -1,15 +1,121 @@
-use crate::conversions::{GpuBuffer};
-use cgmath::{Decomposed, Deg, Matrix4, Quaternion, Rotation3, Vector3, Vector4};
+use crate::conversions::{AsBytes, GpuBuffer};
+use crate::skin::{JointTransform, Pose, Skin};
+use crate::animation::{AnimationClip, AnimationPlayer};
+use crate::model_data::{Bounds, ModelData, VertexLayout, INSTANCE_MATRIX_LOCATION, INSTANCE_NORMAL_MATRIX_LOCATION};
+use cgmath::{Decomposed, Deg, InnerSpace, Matrix, Matrix3, Matrix4, Point3, Quaternion, Rotation3, SquareMatrix, Vector3, Vector4};
 use std::mem::size_of;
+use std::ops::Range;
 use wgpu::BufferUsageFlags;
 
+#[derive(Debug, Error)]
+pub enum ModelGroupError {
+    #[error(display = "model group {} was drawn before its buffers were updated", group)]
+    BuffersNotUpdated {
+        group: String,
+    },
+}
+
 pub struct ModelGroup {
     pub name: String,
     pub index_buf: GpuBuffer,
     pub vertex_buf: GpuBuffer,
+    pub layout: VertexLayout,
     pub bind_group: wgpu::BindGroup,
     pub models: Vec<Model>,
+    pub deformation: Deformation,
+    pub lods: Vec<LodRange>,
+    pub bounds: Bounds,
+    draw_order: Vec<usize>,
+    // Batches of instances that share a LOD, keyed by whether they're culled and the LOD.
+    lod_batches: Vec<((bool, usize), Range<u32>)>,
     mvp_buffer: Option<GpuBuffer>,
+    deformation_buffers: Option<DeformationBuffers>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LodRange {
+    pub indices: Range<u32>,
+    pub screen_size: f32,
+}
+
+impl LodRange {
+    pub fn from_model_data(model_data: &ModelData) -> Vec<LodRange> {
+        let mut start = model_data.indices.len() as u32;
+        let mut ranges = vec![LodRange {
+            indices: 0..start,
+            screen_size: ::std::f32::INFINITY,
+        }];
+        for lod in &model_data.lods {
+            let end = start + lod.indices.len() as u32;
+            ranges.push(LodRange {
+                indices: start..end,
+                screen_size: lod.screen_size,
+            });
+            start = end;
+        }
+        ranges
+    }
+}
+
+pub struct Deformation {
+    pub skin: Option<Skin>,
+    pub animations: Vec<AnimationClip>,
+    pub target_count: u32,
+    pub vertex_count: u32,
+    pub default_weights: Vec<f32>,
+    pub mesh_node: Option<usize>,
+    morph_deltas: GpuBuffer,
+}
+
+impl Deformation {
+    pub fn new(device: &wgpu::Device, model_data: &ModelData) -> Deformation {
+        let mut deltas: Vec<[f32; 4]> = Vec::with_capacity(model_data.morph_targets.len() * model_data.vertices.len() * 2);
+        for target in &model_data.morph_targets {
+            for (position, normal) in target.position_deltas.iter().zip(target.normal_deltas.iter()) {
+                deltas.push([position.x, position.y, position.z, 0.0]);
+                deltas.push([normal.x, normal.y, normal.z, 0.0]);
+            }
+        }
+        if deltas.is_empty() {
+            deltas.push([0.0; 4]);
+        }
+
+        let delta_bytes: Vec<&[u8]> = deltas.iter().map(|delta| delta.as_bytes()).collect();
+        Deformation {
+            skin: model_data.skin.clone(),
+            animations: model_data.animations.clone(),
+            target_count: model_data.morph_targets.len() as u32,
+            vertex_count: model_data.vertices.len() as u32,
+            default_weights: model_data.default_weights.clone(),
+            mesh_node: model_data.mesh_node,
+            morph_deltas: GpuBuffer::from_byte_slices(device, BufferUsageFlags::STORAGE, &delta_bytes),
+        }
+    }
+}
+
+// Per-mesh data the renderer needs next to the GPU buffers: how to deform it, where its LODs
+// are in the index buffer and its rest-pose bounds.
+pub struct GroupGeometry {
+    pub deformation: Deformation,
+    pub lods: Vec<LodRange>,
+    pub bounds: Bounds,
+}
+
+impl GroupGeometry {
+    pub fn new(device: &wgpu::Device, model_data: &ModelData) -> GroupGeometry {
+        GroupGeometry {
+            deformation: Deformation::new(device, model_data),
+            lods: LodRange::from_model_data(model_data),
+            bounds: model_data.bounds,
+        }
+    }
+}
+
+struct DeformationBuffers {
+    _counts: GpuBuffer,
+    _palette: GpuBuffer,
+    _weights: GpuBuffer,
+    bind_group: wgpu::BindGroup,
 }
 
 impl ModelGroup {
@@ -17,70 +123,261 @@ impl ModelGroup {
         name: impl Into<String>,
         index_buf: GpuBuffer,
         vertex_buf: GpuBuffer,
+        layout: VertexLayout,
         bind_group: wgpu::BindGroup,
+        geometry: GroupGeometry,
     ) -> ModelGroup {
         ModelGroup {
             name: name.into(),
             index_buf,
             vertex_buf,
+            layout,
             bind_group,
             models: Vec::new(),
+            deformation: geometry.deformation,
+            lods: geometry.lods,
+            bounds: geometry.bounds,
+            draw_order: Vec::new(),
+            lod_batches: Vec::new(),
             mvp_buffer: None,
+            deformation_buffers: None,
         }
     }
 
-    pub fn add_model(&mut self, model: Model) {
+    pub fn add_model(&mut self, mut model: Model) {
+        if model.weights.is_empty() {
+            model.weights = self.deformation.default_weights.clone();
+        }
         self.models.push(model);
     }
 
+    pub fn advance_animations(&mut self, delta: f32) {
+        let deformation = &self.deformation;
+        for model in self.models.iter_mut() {
+            let sample = match model.animation.as_mut() {
+                Some(player) => {
+                    player.advance(delta, &deformation.animations);
+                    match player.sample(&deformation.animations) {
+                        Some(sample) => sample,
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
+
+            if let Some(skin) = &deformation.skin {
+                let mut pose = model.pose.take().unwrap_or_else(|| skin.bind_pose());
+                sample.apply_to_pose(skin, &mut pose);
+                model.pose = Some(pose);
+            }
+
+            // Skinned meshes ignore their node's transform, the joints place them instead.
+            if let Some(node) = deformation.mesh_node {
+                if deformation.skin.is_none() {
+                    sample.apply_to_model(node, model);
+                }
+                sample.apply_weights(node, &mut model.weights);
+            }
+        }
+    }
+
+    pub fn select_lod(&self, bounds: &Bounds, eye: Point3<f32>, projection_scale: f32) -> usize {
+        let sphere = bounds.sphere;
+        if sphere.contains(eye) {
+            return 0;
+        }
+
+        let distance = (sphere.center - eye).magnitude();
+        let screen_size = sphere.radius * projection_scale / distance;
+        self.lods.iter().rposition(|lod| screen_size < lod.screen_size).unwrap_or(0)
+    }
+
+    pub fn world_bounds(&self) -> impl Iterator<Item = Bounds> + '_ {
+        self.models.iter().map(move |model| model.world_bounds(&self.bounds))
+    }
+
+    // Every model is drawn into the shadow maps, so models just outside the view still cast
+    // shadows into it. Models whose bounds miss the frustum's are only left out of the main pass,
+    // they're placed after the visible ones in the draw order. Skinned models are never culled,
+    // their joints can move the mesh outside the bind pose bounds.
+    pub fn update_lods(&mut self, eye: Point3<f32>, projection_scale: f32, frustum: &Bounds) {
+        let cull = self.deformation.skin.is_none();
+        let selected: Vec<(bool, usize)> = self.world_bounds()
+            .map(|bounds| {
+                let culled = cull && !frustum.intersects(&bounds);
+                (culled, self.select_lod(&bounds, eye, projection_scale))
+            })
+            .collect();
+
+        self.draw_order = (0..self.models.len()).collect();
+        self.draw_order.sort_by_key(|&i| selected[i]);
+
+        self.lod_batches.clear();
+        for (position, &i) in self.draw_order.iter().enumerate() {
+            let position = position as u32;
+            match self.lod_batches.last_mut() {
+                Some((batch, instances)) if *batch == selected[i] => instances.end = position + 1,
+                _ => self.lod_batches.push((selected[i], position..position + 1)),
+            }
+        }
+    }
+
+    // Index and instance ranges of the models the camera sees.
+    pub fn lod_batches(&self) -> impl Iterator<Item = (Range<u32>, Range<u32>)> + '_ {
+        self.lod_batches.iter()
+            .filter(|((culled, _), _)| !culled)
+            .map(move |((_, lod), instances)| (self.lods[*lod].indices.clone(), instances.clone()))
+    }
+
+    // Index and instance ranges of every model, culled or not.
+    pub fn shadow_batches(&self) -> impl Iterator<Item = (Range<u32>, Range<u32>)> + '_ {
+        self.lod_batches.iter()
+            .map(move |((_, lod), instances)| (self.lods[*lod].indices.clone(), instances.clone()))
+    }
+
     pub fn update_mvp_buffer(&mut self, device: &wgpu::Device) {
-         self.mvp_buffer = Some(GpuBuffer::from_transformed_slice(
+        let models = &self.models;
+        self.mvp_buffer = Some(GpuBuffer::from_transformed_slice(
             device,
             BufferUsageFlags::TRANSFER_SRC,
-            &self.models,
-            |model| model.model_matrix(),
+            &self.draw_order,
+            |&i| Instance::new(models[i].model_matrix()),
         ));
     }
 
-    pub fn mvp_buffer(&self) -> &wgpu::Buffer {
+    pub fn mvp_buffer(&self) -> Result<&wgpu::Buffer, ModelGroupError> {
         match &self.mvp_buffer {
-            Some(buf) => buf.buffer(),
-            None => unimplemented!(),
+            Some(buf) => Ok(buf.buffer()),
+            None => Err(ModelGroupError::BuffersNotUpdated { group: self.name.clone() }),
+        }
+    }
+
+    pub fn update_deformation_buffers(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        let deformation = &self.deformation;
+        let (joint_count, palette) = match &deformation.skin {
+            Some(skin) => {
+                let bind_pose = skin.bind_pose();
+                let palette: Vec<Matrix4<f32>> = self.draw_order.iter()
+                    .map(|&i| &self.models[i])
+                    .flat_map(|model| skin.joint_matrices(model.pose().unwrap_or(&bind_pose)))
+                    .collect();
+                (skin.joint_count() as u32, palette)
+            }
+            None => (0, vec![Matrix4::identity()]),
+        };
+
+        let target_count = deformation.target_count as usize;
+        let mut weights: Vec<f32> = self.draw_order.iter()
+            .map(|&i| &self.models[i])
+            .flat_map(|model| (0..target_count).map(move |i| model.weights.get(i).cloned().unwrap_or(0.0)))
+            .collect();
+        if weights.is_empty() {
+            weights.push(0.0);
+        }
+
+        let counts = GpuBuffer::from_single(
+            device,
+            BufferUsageFlags::UNIFORM,
+            [joint_count, deformation.target_count, deformation.vertex_count, 0],
+        );
+        let palette_bytes: Vec<&[u8]> = palette.iter().map(|matrix| matrix.as_bytes()).collect();
+        let palette = GpuBuffer::from_byte_slices(device, BufferUsageFlags::STORAGE, &palette_bytes);
+        let weights = GpuBuffer::from_byte_slices(device, BufferUsageFlags::STORAGE, &[weights.as_bytes()]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                counts.binding(0),
+                palette.binding(1),
+                deformation.morph_deltas.binding(2),
+                weights.binding(3),
+            ],
+        });
+
+        self.deformation_buffers = Some(DeformationBuffers {
+            _counts: counts,
+            _palette: palette,
+            _weights: weights,
+            bind_group,
+        });
+    }
+
+    pub fn deformation_bind_group(&self) -> Result<&wgpu::BindGroup, ModelGroupError> {
+        match &self.deformation_buffers {
+            Some(buffers) => Ok(&buffers.bind_group),
+            None => Err(ModelGroupError::BuffersNotUpdated { group: self.name.clone() }),
         }
     }
 
     pub fn buffer_descriptor() -> wgpu::VertexBufferDescriptor<'static> {
         wgpu::VertexBufferDescriptor {
-            stride: size_of::<Matrix4<f32>>() as u32,
+            stride: size_of::<Instance>() as u32,
             step_mode: wgpu::InputStepMode::Instance,
             attributes: &[
                 wgpu::VertexAttributeDescriptor {
-                    attribute_index: 3,
+                    attribute_index: INSTANCE_MATRIX_LOCATION,
                     format: wgpu::VertexFormat::Float4,
                     offset: 0,
                 },
                 wgpu::VertexAttributeDescriptor {
-                    attribute_index: 4,
+                    attribute_index: INSTANCE_MATRIX_LOCATION + 1,
                     format: wgpu::VertexFormat::Float4,
                     offset: (size_of::<f32>() * 4) as u32,
                 },
                 wgpu::VertexAttributeDescriptor {
-                    attribute_index: 5,
+                    attribute_index: INSTANCE_MATRIX_LOCATION + 2,
                     format: wgpu::VertexFormat::Float4,
                     offset: (size_of::<f32>() * 4 * 2) as u32,
                 },
                 wgpu::VertexAttributeDescriptor {
-                    attribute_index: 6,
+                    attribute_index: INSTANCE_MATRIX_LOCATION + 3,
                     format: wgpu::VertexFormat::Float4,
                     offset: (size_of::<f32>() * 4 * 3) as u32,
                 },
+                wgpu::VertexAttributeDescriptor {
+                    attribute_index: INSTANCE_NORMAL_MATRIX_LOCATION,
+                    format: wgpu::VertexFormat::Float3,
+                    offset: size_of::<Matrix4<f32>>() as u32,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    attribute_index: INSTANCE_NORMAL_MATRIX_LOCATION + 1,
+                    format: wgpu::VertexFormat::Float3,
+                    offset: (size_of::<Matrix4<f32>>() + size_of::<f32>() * 3) as u32,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    attribute_index: INSTANCE_NORMAL_MATRIX_LOCATION + 2,
+                    format: wgpu::VertexFormat::Float3,
+                    offset: (size_of::<Matrix4<f32>>() + size_of::<f32>() * 3 * 2) as u32,
+                },
             ],
         }
     }
 }
 
+// Per instance vertex data. Normals are transformed by the inverse transpose of the model
+// matrix's 3x3 part, which keeps them perpendicular to the surface under non-uniform scale.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Instance {
+    model: Matrix4<f32>,
+    normal: Matrix3<f32>,
+}
+
+impl Instance {
+    fn new(model: Matrix4<f32>) -> Instance {
+        let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        // A model scaled to nothing has no visible surface to light.
+        let normal = linear.invert().map_or_else(Matrix3::identity, |inverse| inverse.transpose());
+        Instance { model, normal }
+    }
+}
+
 pub struct Model {
     transform: Decomposed<Vector3<f32>, Quaternion<f32>>,
+    // The mesh node's animated transform, relative to the placement above.
+    node_transform: Option<JointTransform>,
+    pose: Option<Pose>,
+    animation: Option<AnimationPlayer>,
+    weights: Vec<f32>,
 }
 
 impl Model {
@@ -91,6 +388,10 @@ impl Model {
                 rot: Quaternion::from_angle_y(Deg(0.0f32)),
                 disp: position,
             },
+            node_transform: None,
+            pose: None,
+            animation: None,
+            weights: Vec::new(),
         }
     }
 
@@ -98,7 +399,72 @@ impl Model {
         self.transform.disp += movement;
     }
 
+    pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
+        self.transform.rot = rotation;
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.transform.scale = scale;
+    }
+
+    pub fn node_transform(&self) -> Option<JointTransform> {
+        self.node_transform
+    }
+
+    pub fn set_node_transform(&mut self, transform: JointTransform) {
+        self.node_transform = Some(transform);
+    }
+
     pub fn model_matrix(&self) -> Matrix4<f32> {
-        self.transform.into()
+        let placement: Matrix4<f32> = self.transform.into();
+        match self.node_transform {
+            Some(node) => placement * node.matrix(),
+            None => placement,
+        }
+    }
+
+    pub fn world_bounds(&self, local: &Bounds) -> Bounds {
+        local.transform(&self.model_matrix())
+    }
+
+    pub fn pose(&self) -> Option<&Pose> {
+        self.pose.as_ref()
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    pub fn set_weights(&mut self, weights: &[f32]) {
+        self.weights = weights.to_vec();
+    }
+
+    pub fn play_animation(&mut self, player: AnimationPlayer) {
+        self.animation = Some(player);
+    }
+
+    pub fn animation_mut(&mut self) -> Option<&mut AnimationPlayer> {
+        self.animation.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector3};
+    use std::mem::size_of;
+    use super::Instance;
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let model = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0);
+        let instance = Instance::new(model);
+        // A surface along the x = y diagonal.
+        let along = (model * Vector3::new(1.0, 1.0, 0.0).extend(0.0)).truncate();
+        let normal = instance.normal * Vector3::new(1.0, -1.0, 0.0);
+        assert!(normal.dot(along).abs() < 1e-6, "{:?}", normal);
+        assert_eq!(Instance::new(Matrix4::from_scale(0.0)).normal, Matrix3::identity());
+        // The vertex buffer descriptor reads the normal matrix right after the model matrix.
+        assert_eq!(size_of::<Instance>(), size_of::<Matrix4<f32>>() + size_of::<Matrix3<f32>>());
     }
 }
@@ -1,5 +1,38 @@
-use cgmath::{Vector3, Point2};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point2, Point3, Quaternion, SquareMatrix, Transform, Vector3};
 use itertools::izip;
+use crate::skin::{Joint, JointTransform, Skin};
+use crate::animation::{AnimationClip, Channel, ChannelProperty, Interpolation};
+use std::path::Path;
+
+mod accessor;
+mod bc;
+mod bounds;
+mod cache;
+mod container;
+mod export;
+mod extensions;
+mod layout;
+mod meshopt;
+mod obj;
+mod optimize;
+pub mod primitives;
+mod scene;
+mod source;
+mod simplify;
+mod tangents;
+
+pub use self::bc::BlockFormat;
+pub use self::bounds::Bounds;
+pub use self::cache::CacheError;
+pub use self::container::{convert_png, CompressedTexture};
+pub use self::export::{differences, export_model, ExportError, GltfExporter};
+pub use self::extensions::TextureTransform;
+pub use self::layout::{Attribute, VertexLayout, VertexLayoutError, INSTANCE_MATRIX_LOCATION, INSTANCE_NORMAL_MATRIX_LOCATION};
+pub use self::scene::{LightKind, Projection, SceneCamera, SceneLight};
+pub use self::simplify::{Lod, DEFAULT_LODS};
+use self::source::GltfSource;
+
+pub const MAX_TEXTURE_SIZE: u32 = 8192;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -7,32 +40,25 @@ pub struct Vertex {
     position: Vector3<f32>,
     normal: Vector3<f32>,
     tex_coord: Point2<f32>,
+    joints: [u16; 4],
+    weights: [f32; 4],
+    color: [f32; 4],
+    tex_coord1: Point2<f32>,
+    // xyz is the tangent, w the handedness of the bitangent.
+    tangent: [f32; 4],
 }
 
 impl Vertex {
-    pub fn buffer_descriptor() -> wgpu::VertexBufferDescriptor<'static> {
-        use std::mem::size_of;
-
-        wgpu::VertexBufferDescriptor {
-            stride: size_of::<Vertex>() as u32,
-            step_mode: wgpu::InputStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttributeDescriptor {
-                    attribute_index: 0,
-                    format: wgpu::VertexFormat::Float3,
-                    offset: 0,
-                },
-                wgpu::VertexAttributeDescriptor {
-                    attribute_index: 1,
-                    format: wgpu::VertexFormat::Float3,
-                    offset: size_of::<Vector3<f32>>() as u32,
-                },
-                wgpu::VertexAttributeDescriptor {
-                    attribute_index: 2,
-                    format: wgpu::VertexFormat::Float2,
-                    offset: (size_of::<Vector3<f32>>() * 2) as u32,
-                },
-            ],
+    pub fn new(position: Vector3<f32>, normal: Vector3<f32>, tex_coord: Point2<f32>) -> Vertex {
+        Vertex {
+            position,
+            normal,
+            tex_coord,
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            color: [1.0; 4],
+            tex_coord1: tex_coord,
+            tangent: [1.0, 0.0, 0.0, 1.0],
         }
     }
 }
@@ -41,7 +67,55 @@ impl Vertex {
 pub struct ModelData {
     pub indices: Vec<u16>,
     pub vertices: Vec<Vertex>,
+    pub layout: VertexLayout,
+    pub material: Material,
     pub texture: Texture,
+    pub occlusion: Option<Texture>,
+    pub lightmap: Option<Texture>,
+    // Tangent space normals, sampled with the base color's UV set.
+    pub normal_map: Option<Texture>,
+    pub skin: Option<Skin>,
+    pub animations: Vec<AnimationClip>,
+    pub morph_targets: Vec<MorphTarget>,
+    pub default_weights: Vec<f32>,
+    pub mesh_node: Option<usize>,
+    pub lods: Vec<Lod>,
+    pub bounds: Bounds,
+    pub cameras: Vec<SceneCamera>,
+    pub lights: Vec<SceneLight>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    pub position_deltas: Vec<Vector3<f32>>,
+    pub normal_deltas: Vec<Vector3<f32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub base_color: [f32; 4],
+    pub emissive: [f32; 3],
+    pub unlit: bool,
+    pub base_color_transform: TextureTransform,
+    pub occlusion_transform: TextureTransform,
+    pub normal_transform: TextureTransform,
+    pub normal_scale: f32,
+}
+
+impl Material {
+    pub fn new(name: String, base_color: [f32; 4]) -> Material {
+        Material {
+            name,
+            base_color,
+            emissive: [0.0; 3],
+            unlit: false,
+            base_color_transform: TextureTransform::IDENTITY,
+            occlusion_transform: TextureTransform::IDENTITY,
+            normal_transform: TextureTransform::IDENTITY,
+            normal_scale: 1.0,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -61,71 +135,590 @@ impl Texture {
     }
 }
 
+impl Texture {
+    pub fn load_file(path: &Path) -> Result<Texture, ModelLoadError> {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_ref().map(String::as_str) {
+            Some("png") => load_png(&::std::fs::read(path)?),
+            Some("dds") | Some("ktx2") => Ok(CompressedTexture::load(path)?.decode()),
+            _ => Err(ModelLoadError::UnsupportedImageFormat { mime_type: path.display().to_string() }),
+        }
+    }
+
+    pub fn solid(color: [f32; 4]) -> Texture {
+        Texture {
+            pixels: color.iter().map(|c| (c.max(0.0).min(1.0) * 255.0).round() as u8).collect(),
+            width: 1,
+            height: 1,
+        }
+    }
+}
+
 impl ModelData {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u16>, material: Material, texture: Texture) -> ModelData {
+        let mut model_data = ModelData {
+            indices,
+            vertices,
+            layout: VertexLayout::default(),
+            material,
+            texture,
+            occlusion: None,
+            lightmap: None,
+            normal_map: None,
+            skin: None,
+            animations: Vec::new(),
+            morph_targets: Vec::new(),
+            default_weights: Vec::new(),
+            mesh_node: None,
+            lods: Vec::new(),
+            bounds: Bounds::empty(),
+            cameras: Vec::new(),
+            lights: Vec::new(),
+        };
+        model_data.bounds = model_data.compute_bounds();
+        model_data
+    }
+
+    // Loads a model for rendering, optimized and with the default LODs. load_uncached returns the
+    // file's data as it is.
     pub fn load(path: &str) -> Result<ModelData, ModelLoadError> {
+        let mut model_data = match model_extension(path).as_ref().map(String::as_str) {
+            Some("gltf") | Some("glb") => ModelData::load_cached(path)?,
+            Some("obj") => obj::load(path)?,
+            _ => return Err(ModelLoadError::UnsupportedModelFormat { path: path.into() }),
+        };
+        model_data.optimize();
+        model_data.generate_lods(&DEFAULT_LODS);
+        Ok(model_data)
+    }
+
+    // Models the cache can't store are loaded without it. The cache is only an optimization, so
+    // failing to write it, for example to a read-only install directory, doesn't fail the load.
+    fn load_cached(path: &str) -> Result<ModelData, ModelLoadError> {
+        let source = Path::new(path);
+        if cache::is_fresh(source) {
+            if let Ok(model_data) = cache::read(source) {
+                return Ok(model_data);
+            }
+        }
+
+        let model_data = ModelData::load_gltf(path)?;
+        match cache::write(source, &model_data) {
+            Ok(()) | Err(CacheError::Unsupported) => (),
+            Err(err) => eprintln!("Could not write mesh cache for {}: {}", path, err),
+        }
+        Ok(model_data)
+    }
+
+    pub fn load_uncached(path: &str) -> Result<ModelData, ModelLoadError> {
+        match model_extension(path).as_ref().map(String::as_str) {
+            Some("gltf") | Some("glb") => ModelData::load_gltf(path),
+            Some("obj") => obj::load(path),
+            _ => Err(ModelLoadError::UnsupportedModelFormat { path: path.into() }),
+        }
+    }
+
+    fn load_gltf(path: &str) -> Result<ModelData, ModelLoadError> {
         use self::ModelLoadError::*;
         use gltf::mesh::Semantic;
 
-        let (document, buffers, _) = gltf::import(path)?;
+        let source = GltfSource::load(Path::new(path))?;
+        let document = &source.document;
 
         let mesh_doc = document.meshes().nth(0).ok_or_else(|| NoMesh { path: path.into() })?;
         let primitive = mesh_doc.primitives().nth(0).ok_or_else(|| NoPrimitives {
             mesh: mesh_name(&mesh_doc),
             path: path.into(),
         })?;
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(UnsupportedPrimitiveMode { mesh: mesh_name(&mesh_doc), mode: primitive.mode() });
+        }
+
+        let material_doc = primitive.material();
+        let material_extensions = extensions::material_extensions(&source.json, material_doc.index())?;
+        let emissive = material_doc.emissive_factor();
+        let material = Material {
+            name: material_doc.name().unwrap_or("<unknown>").to_string(),
+            base_color: material_doc.pbr_metallic_roughness().base_color_factor(),
+            emissive: [
+                emissive[0] * material_extensions.emissive_strength,
+                emissive[1] * material_extensions.emissive_strength,
+                emissive[2] * material_extensions.emissive_strength,
+            ],
+            unlit: material_extensions.unlit,
+            base_color_transform: material_extensions.base_color_transform,
+            occlusion_transform: material_extensions.occlusion_transform,
+            normal_transform: material_extensions.normal_transform,
+            normal_scale: material_doc.normal_texture().map_or(1.0, |normal| normal.scale()),
+        };
 
-        let base_color_texture = primitive.material()
+        let base_color_texture = material_doc
             .pbr_metallic_roughness()
             .base_color_texture()
             .ok_or_else(|| NoBaseColorTexture {
                 mesh: mesh_name(&mesh_doc),
             })?;
 
-        let texture: Texture = match base_color_texture.texture().source().source() {
-            gltf::image::Source::View { view, mime_type } => {
-                if mime_type != "image/png" {
-                    return Err(UnsupportedImageFormat { mime_type: mime_type.to_string() });
+        let texture = load_texture(&source, &base_color_texture.texture())?;
+
+        // Occlusion is sampled with the second UV set, so its strength is baked into the texture.
+        let occlusion = match material_doc.occlusion_texture() {
+            Some(occlusion) => {
+                let mut texture = load_texture(&source, &occlusion.texture())?;
+                let strength = occlusion.strength().max(0.0).min(1.0);
+                for pixel in texture.pixels.chunks_mut(4) {
+                    let value = 255.0 - strength * (255.0 - pixel[0] as f32);
+                    pixel[0] = value.round() as u8;
+                    pixel[1] = pixel[0];
+                    pixel[2] = pixel[0];
                 }
-                let bytes = access_bytes(&buffers, &view);
-                load_png(bytes)?
+                Some(texture)
             }
-            gltf::image::Source::Uri { uri, mime_type } => {
-                unimplemented!("Can't load external files: {}", uri);
+            None => None,
+        };
+
+        let lightmap = match material_extensions.lightmap_texture.and_then(|index| document.textures().nth(index)) {
+            Some(lightmap) => Some(load_texture(&source, &lightmap)?),
+            None => None,
+        };
+
+        let normal_map = match material_doc.normal_texture() {
+            Some(normal) => {
+                let base_tex_coord = material_extensions.base_color_tex_coord.unwrap_or_else(|| base_color_texture.tex_coord());
+                let normal_tex_coord = material_extensions.normal_tex_coord.unwrap_or_else(|| normal.tex_coord());
+                if normal_tex_coord != base_tex_coord {
+                    return Err(UnsupportedNormalTexCoord { mesh: mesh_name(&mesh_doc), tex_coord: normal_tex_coord });
+                }
+                Some(load_texture(&source, &normal.texture())?)
             }
+            None => None,
         };
 
-        let tex_coord_bytes = attribute_bytes(&buffers, &mesh_doc, &primitive, Semantic::TexCoords(base_color_texture.tex_coord()))?;
-        let tex_coords = bytes_to_point2(tex_coord_bytes);
+        // KHR_mesh_quantization allows integer positions, normals and UVs.
+        let quantized = document.extensions_used().any(|extension| extension == extensions::MESH_QUANTIZATION);
+        let (vertex_types, tex_coord_types) = match quantized {
+            true => (accessor::SIGNED, accessor::SIGNED),
+            false => (accessor::FLOAT, accessor::UNSIGNED),
+        };
 
-        let indices_doc = primitive.indices().ok_or_else(|| NoIndices { mesh: mesh_name(&mesh_doc) })?;
-        let index_bytes = access_bytes(&buffers, &indices_doc.view());
-        let indices = bytes_to_u16(index_bytes);
+        let positions_doc = attribute(&mesh_doc, &primitive, Semantic::Positions)?;
+        let positions = accessor::read_vec3(&source, &positions_doc, vertex_types)?;
+        let vertex_count = positions.len();
+        if vertex_count > u16::max_value() as usize + 1 {
+            return Err(TooManyVertices { count: vertex_count });
+        }
+        let check_count = |semantic: Semantic, count: usize| {
+            if count == vertex_count {
+                Ok(())
+            } else {
+                Err(MismatchedAttributeCount { mesh: mesh_name(&mesh_doc), semantic, expected: vertex_count, actual: count })
+            }
+        };
+
+        let normals = accessor::read_vec3(&source, &attribute(&mesh_doc, &primitive, Semantic::Normals)?, vertex_types)?;
+        check_count(Semantic::Normals, normals.len())?;
+
+        let tex_coord = material_extensions.base_color_tex_coord.unwrap_or_else(|| base_color_texture.tex_coord());
+        let tex_coord_semantic = Semantic::TexCoords(tex_coord);
+        let tex_coords = accessor::read_vec2(&source, &attribute(&mesh_doc, &primitive, tex_coord_semantic.clone())?, tex_coord_types)?;
+        check_count(tex_coord_semantic, tex_coords.len())?;
+
+        let joints = match primitive.get(&Semantic::Joints(0)) {
+            Some(joints) => {
+                let joints = accessor::read_joints(&source, &joints)?;
+                check_count(Semantic::Joints(0), joints.len())?;
+                joints
+            }
+            None => vec![[0; 4]; vertex_count],
+        };
+        let weights = match primitive.get(&Semantic::Weights(0)) {
+            Some(weights) => {
+                let weights = accessor::read_vec4(&source, &weights, accessor::UNSIGNED)?;
+                check_count(Semantic::Weights(0), weights.len())?;
+                weights
+            }
+            None => vec![[1.0, 0.0, 0.0, 0.0]; vertex_count],
+        };
+        let colors = match primitive.get(&Semantic::Colors(0)) {
+            Some(colors) => {
+                let colors = accessor::read_colors(&source, &colors)?;
+                check_count(Semantic::Colors(0), colors.len())?;
+                Some(colors)
+            }
+            None => None,
+        };
+        let tex_coords1 = match primitive.get(&Semantic::TexCoords(1)) {
+            Some(tex_coords) => {
+                let tex_coords = accessor::read_vec2(&source, &tex_coords, tex_coord_types)?;
+                check_count(Semantic::TexCoords(1), tex_coords.len())?;
+                Some(tex_coords)
+            }
+            None => None,
+        };
+
+        // Tangents are only worth their space in the vertex stream when there's a normal map.
+        let tangents = match primitive.get(&Semantic::Tangents) {
+            Some(tangents) if normal_map.is_some() => {
+                let tangents = accessor::read_vec4(&source, &tangents, vertex_types)?;
+                check_count(Semantic::Tangents, tangents.len())?;
+                Some(tangents)
+            }
+            _ => None,
+        };
 
-        let position_bytes = attribute_bytes(&buffers, &mesh_doc, &primitive, Semantic::Positions)?;
-        let positions = bytes_to_vector3(position_bytes);
+        let mut attributes = vec![Attribute::Position, Attribute::Normal, Attribute::TexCoord0];
+        if normal_map.is_some() {
+            attributes.push(Attribute::Tangent);
+        }
+        if colors.is_some() {
+            attributes.push(Attribute::Color);
+        }
+        if tex_coords1.is_some() {
+            attributes.push(Attribute::TexCoord1);
+        }
 
-        let normal_bytes = attribute_bytes(&buffers, &mesh_doc, &primitive, Semantic::Normals)?;
-        let normals = bytes_to_vector3(normal_bytes);
+        let indices_doc = primitive.indices().ok_or_else(|| NoIndices { mesh: mesh_name(&mesh_doc) })?;
+        let indices = accessor::read_indices(&source, &indices_doc)?;
+        if indices.len() % 3 != 0 {
+            return Err(InvalidIndexCount { mesh: mesh_name(&mesh_doc), count: indices.len() });
+        }
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertex_count) {
+            return Err(IndexOutOfRange { mesh: mesh_name(&mesh_doc), index, vertex_count });
+        }
 
-        let vertices: Vec<Vertex> = izip!(positions.into_iter(), normals.into_iter(), tex_coords.into_iter())
-            .map(|(&position, &normal, &tex_coord)| Vertex {
-                position,
-                normal,
-                tex_coord,
+        let mut vertices: Vec<Vertex> = izip!(
+            positions.into_iter(),
+            normals.into_iter(),
+            tex_coords.into_iter(),
+            joints.into_iter(),
+            weights.into_iter()
+        )
+            .enumerate()
+            .map(|(i, (position, normal, tex_coord, joints, weights))| Vertex {
+                position: position.into(),
+                normal: normal.into(),
+                tex_coord: tex_coord.into(),
+                joints,
+                weights,
+                color: colors.as_ref().map(|colors| colors[i]).unwrap_or([1.0; 4]),
+                tex_coord1: tex_coords1.as_ref().map(|tex_coords| tex_coords[i]).unwrap_or(tex_coord).into(),
+                tangent: tangents.as_ref().map(|tangents| tangents[i]).unwrap_or([1.0, 0.0, 0.0, 1.0]),
             }).collect();
 
-        Ok(ModelData {
-            indices: indices.to_vec(),
+        let mut morph_targets = Vec::new();
+        for target in primitive.morph_targets() {
+            let deltas = |accessor: Option<gltf::Accessor>| match accessor {
+                Some(accessor) => accessor::read_vec3(&source, &accessor, vertex_types)
+                    .map(|deltas| deltas.into_iter().map(Vector3::from).collect()),
+                None => Ok(vec![Vector3::new(0.0, 0.0, 0.0); vertex_count]),
+            };
+            let target = MorphTarget {
+                position_deltas: deltas(target.positions())?,
+                normal_deltas: deltas(target.normals())?,
+            };
+            if target.position_deltas.len() != vertex_count || target.normal_deltas.len() != vertex_count {
+                return Err(InvalidMorphTargets { mesh: mesh_name(&mesh_doc) });
+            }
+            morph_targets.push(target);
+        }
+
+        let default_weights = match mesh_doc.weights() {
+            Some(weights) => weights.to_vec(),
+            None => vec![0.0; morph_targets.len()],
+        };
+
+        let parents = scene::node_parents(document)?;
+        let transforms = scene::world_transforms(document, &parents);
+
+        let mesh_node = document.nodes().find(|node| node.mesh().map(|mesh| mesh.index()) == Some(mesh_doc.index()));
+        let skin = match mesh_node.as_ref().and_then(|node| node.skin()) {
+            Some(skin) => Some(load_skin(&source, document, &parents, &skin)?),
+            None => None,
+        };
+        if let Some(skin) = skin.as_ref() {
+            let joint_count = skin.joint_count();
+            let joint = vertices.iter()
+                .flat_map(|vertex| vertex.joints.iter())
+                .find(|&&joint| joint as usize >= joint_count);
+            if let Some(&joint) = joint {
+                return Err(JointOutOfRange { mesh: mesh_name(&mesh_doc), joint, joint_count });
+            }
+        }
+
+        let animations = document.animations()
+            .map(|animation| load_animation(&source, &animation))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if skin.is_some() {
+            attributes.push(Attribute::Joints);
+            attributes.push(Attribute::Weights);
+        }
+
+        // Quantized positions are dequantized by their node's transform. The renderer places models
+        // itself, so the transform is baked in unless a skin overrides it anyway.
+        if positions_doc.data_type() != gltf::accessor::DataType::F32 && skin.is_none() {
+            if let Some(node) = mesh_node.as_ref() {
+                bake_transform(&mut vertices, &mut morph_targets, transforms[node.index()]);
+            }
+        }
+
+        let cameras = scene::load_cameras(document, &transforms);
+        let lights = scene::load_lights(&source, &transforms)?;
+
+        let mut model_data = ModelData {
+            indices: indices.into_iter().map(|index| index as u16).collect(),
             vertices,
+            layout: VertexLayout::with_attributes(&attributes),
+            material,
             texture,
-        })
+            occlusion,
+            lightmap,
+            normal_map,
+            skin,
+            animations,
+            morph_targets,
+            default_weights,
+            mesh_node: mesh_node.map(|node| node.index()),
+            lods: Vec::new(),
+            bounds: Bounds::empty(),
+            cameras,
+            lights,
+        };
+        model_data.bounds = model_data.compute_bounds();
+        if model_data.normal_map.is_some() && tangents.is_none() {
+            model_data.generate_tangents()?;
+        }
+        Ok(model_data)
+    }
+}
+
+fn bake_transform(vertices: &mut [Vertex], morph_targets: &mut [MorphTarget], transform: Matrix4<f32>) {
+    let linear = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+    let normal_matrix = linear.invert().map(|inverse| inverse.transpose()).unwrap_or(linear);
+    let normal = |normal: Vector3<f32>| {
+        let normal = normal_matrix * normal;
+        if normal.magnitude2() > 0.0 { normal.normalize() } else { normal }
+    };
+    for vertex in vertices.iter_mut() {
+        vertex.position = transform.transform_point(Point3::from_vec(vertex.position)).to_vec();
+        vertex.normal = normal(vertex.normal);
+        let tangent = linear * Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+        if tangent.magnitude2() > 0.0 {
+            let tangent = tangent.normalize();
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, vertex.tangent[3]];
+        }
     }
+    for target in morph_targets.iter_mut() {
+        for delta in target.position_deltas.iter_mut() {
+            *delta = linear * *delta;
+        }
+        for delta in target.normal_deltas.iter_mut() {
+            *delta = normal_matrix * *delta;
+        }
+    }
+}
+
+fn load_animation(source: &GltfSource, animation: &gltf::Animation) -> Result<AnimationClip, ModelLoadError> {
+    use gltf::animation::Property;
+
+    let name = animation.name().unwrap_or("<unknown>").to_string();
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let sampler = channel.sampler();
+        let interpolation = match sampler.interpolation() {
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            gltf::animation::Interpolation::CatmullRomSpline => {
+                return Err(ModelLoadError::UnsupportedInterpolation { animation: name });
+            }
+        };
+
+        let times = accessor::read_scalars(source, &sampler.input(), accessor::FLOAT)?;
+        if times.iter().any(|time| !time.is_finite()) || times.windows(2).any(|pair| pair[1] < pair[0]) {
+            return Err(ModelLoadError::InvalidAnimationChannel { animation: name });
+        }
+        let output = sampler.output();
+        let (property, values): (ChannelProperty, Vec<f32>) = match channel.target().property() {
+            Property::Translation => {
+                let translations = accessor::read_vec3(source, &output, accessor::FLOAT)?;
+                (ChannelProperty::Translation, translations.iter().flat_map(|t| t.to_vec()).collect())
+            }
+            Property::Rotation => {
+                let rotations = accessor::read_vec4(source, &output, accessor::SIGNED)?;
+                (ChannelProperty::Rotation, rotations.iter().flat_map(|r| r.to_vec()).collect())
+            }
+            Property::Scale => {
+                let scales = accessor::read_vec3(source, &output, accessor::FLOAT)?;
+                (ChannelProperty::Scale, scales.iter().flat_map(|s| s.to_vec()).collect())
+            }
+            Property::MorphTargetWeights => {
+                (ChannelProperty::MorphWeights, accessor::read_scalars(source, &output, accessor::SIGNED)?)
+            }
+        };
+
+        let values_per_key = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        let components = match property {
+            ChannelProperty::Translation | ChannelProperty::Scale => 3,
+            ChannelProperty::Rotation => 4,
+            ChannelProperty::MorphWeights if !times.is_empty() => values.len() / (times.len() * values_per_key),
+            ChannelProperty::MorphWeights => 0,
+        };
+        if values.len() != times.len() * values_per_key * components {
+            return Err(ModelLoadError::InvalidAnimationChannel { animation: name });
+        }
+
+        channels.push(Channel {
+            node: channel.target().node().index(),
+            property,
+            interpolation,
+            times,
+            values,
+            components,
+        });
+    }
+
+    let duration = channels.iter()
+        .filter_map(|channel| channel.times.last().cloned())
+        .fold(0.0, f32::max);
+
+    Ok(AnimationClip {
+        name,
+        duration,
+        channels,
+    })
+}
+
+fn load_skin(
+    source: &GltfSource,
+    document: &gltf::Document,
+    node_parents: &[Option<usize>],
+    skin: &gltf::Skin,
+) -> Result<Skin, ModelLoadError> {
+    let joint_nodes: Vec<gltf::Node> = skin.joints().collect();
+
+    let inverse_bind_matrices: Vec<Matrix4<f32>> = match skin.inverse_bind_matrices() {
+        Some(matrices) => accessor::read_matrices(source, &matrices)?.into_iter().map(Matrix4::from).collect(),
+        None => vec![Matrix4::from_scale(1.0); joint_nodes.len()],
+    };
+    if inverse_bind_matrices.len() < joint_nodes.len() {
+        return Err(ModelLoadError::InvalidSkin {
+            skin: skin.name().unwrap_or("<unknown>").to_string(),
+        });
+    }
+
+    let joint_index = |node: usize| joint_nodes.iter().position(|joint| joint.index() == node);
+    let locals: Vec<Matrix4<f32>> = document.nodes().map(|node| Matrix4::from(node.transform().matrix())).collect();
+
+    let joints = joint_nodes.iter()
+        .zip(inverse_bind_matrices)
+        .map(|(node, inverse_bind_matrix)| {
+            let mut parent = node_parents[node.index()];
+            let mut parent_transform = Matrix4::identity();
+            while let Some(parent_node) = parent {
+                if joint_index(parent_node).is_some() {
+                    break;
+                }
+                parent_transform = locals[parent_node] * parent_transform;
+                parent = node_parents[parent_node];
+            }
+
+            let (translation, rotation, scale) = node.transform().decomposed();
+            Joint {
+                name: node.name().unwrap_or("<unknown>").to_string(),
+                node: node.index(),
+                parent: parent.and_then(joint_index),
+                parent_transform,
+                inverse_bind_matrix,
+                rest: JointTransform {
+                    translation: translation.into(),
+                    rotation: Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+                    scale: scale.into(),
+                },
+            }
+        })
+        .collect();
+
+    Ok(Skin { joints })
 }
 
 #[derive(Debug, Error)]
 pub enum ModelLoadError {
+    #[error(display = "model file {} has an unsupported format", path)]
+    UnsupportedModelFormat {
+        path: String,
+    },
+    #[error(display = "could not read model file")]
+    Io(#[error(cause)] std::io::Error),
+    #[error(display = "{}:{}: {}", path, line, message)]
+    ObjParse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    #[error(display = "mesh has {} vertices, more than 16-bit indices can address", count)]
+    TooManyVertices {
+        count: usize,
+    },
     #[error(display = "could not import glTf file")]
     InvalidImport(#[error(cause)] gltf::Error),
+    #[error(display = "GLB container is invalid: {}", reason)]
+    InvalidGlb {
+        reason: &'static str,
+    },
+    #[error(display = "{} is invalid: {}", path, reason)]
+    InvalidDocument {
+        path: String,
+        reason: &'static str,
+    },
+    #[error(display = "file requires the unsupported glTF extension {}", extension)]
+    UnsupportedExtension {
+        extension: String,
+    },
+    #[error(display = "compressed buffer view {} is invalid: {}", view, reason)]
+    InvalidCompressedView {
+        view: usize,
+        reason: &'static str,
+    },
+    #[error(display = "buffer {} is invalid: {}", buffer, reason)]
+    InvalidBuffer {
+        buffer: usize,
+        reason: &'static str,
+    },
+    #[error(display = "URI {} is invalid: {}", uri, reason)]
+    InvalidUri {
+        uri: String,
+        reason: &'static str,
+    },
+    #[error(display = "buffer view {} extends past the end of its buffer", view)]
+    BufferViewOutOfRange {
+        view: usize,
+    },
+    #[error(display = "accessor {} extends past the end of its buffer view", accessor)]
+    AccessorOutOfRange {
+        accessor: usize,
+    },
+    #[error(display = "accessor {} is not aligned to its component size", accessor)]
+    MisalignedAccessor {
+        accessor: usize,
+    },
+    #[error(display = "accessor {} has unexpected type {:?} of {:?}", accessor, dimensions, data_type)]
+    UnexpectedAccessorType {
+        accessor: usize,
+        dimensions: gltf::accessor::Dimensions,
+        data_type: gltf::accessor::DataType,
+    },
+    #[error(display = "accessor {} is invalid: {}", accessor, reason)]
+    InvalidAccessor {
+        accessor: usize,
+        reason: &'static str,
+    },
+    #[error(display = "node {} has more than one parent or is part of a cycle", node)]
+    InvalidNodeHierarchy {
+        node: usize,
+    },
     #[error(display = "file {} has no meshes", path)]
     NoMesh {
         path: String
@@ -144,16 +737,86 @@ pub enum ModelLoadError {
         mesh: String,
         semantic: gltf::mesh::Semantic,
     },
+    #[error(display = "mesh {} uses unsupported primitive mode {:?}", mesh, mode)]
+    UnsupportedPrimitiveMode {
+        mesh: String,
+        mode: gltf::mesh::Mode,
+    },
+    #[error(display = "mesh {} has {} values for {:?}, expected {}", mesh, actual, semantic, expected)]
+    MismatchedAttributeCount {
+        mesh: String,
+        semantic: gltf::mesh::Semantic,
+        expected: usize,
+        actual: usize,
+    },
+    #[error(display = "mesh {} has {} indices, not a whole number of triangles", mesh, count)]
+    InvalidIndexCount {
+        mesh: String,
+        count: usize,
+    },
+    #[error(display = "mesh {} has index {} but only {} vertices", mesh, index, vertex_count)]
+    IndexOutOfRange {
+        mesh: String,
+        index: u32,
+        vertex_count: usize,
+    },
+    #[error(display = "mesh {} refers to joint {} but its skin has {} joints", mesh, joint, joint_count)]
+    JointOutOfRange {
+        mesh: String,
+        joint: u16,
+        joint_count: usize,
+    },
     #[error(display = "mesh {} has no base color texture", mesh)]
     NoBaseColorTexture {
         mesh: String,
     },
+    #[error(display = "normal texture of mesh {} uses UV set {}, but only the base color's UV set is supported", mesh, tex_coord)]
+    UnsupportedNormalTexCoord {
+        mesh: String,
+        tex_coord: u32,
+    },
+    #[error(display = "skin {} has fewer inverse bind matrices than joints", skin)]
+    InvalidSkin {
+        skin: String,
+    },
+    #[error(display = "mesh {} has morph targets that don't match its vertex count", mesh)]
+    InvalidMorphTargets {
+        mesh: String,
+    },
+    #[error(display = "animation {} uses unsupported interpolation", animation)]
+    UnsupportedInterpolation {
+        animation: String,
+    },
+    #[error(display = "animation {} has a channel with invalid or mismatched keyframes", animation)]
+    InvalidAnimationChannel {
+        animation: String,
+    },
+    #[error(display = "light {} is invalid: {}", light, reason)]
+    InvalidLight {
+        light: usize,
+        reason: &'static str,
+    },
+    #[error(display = "texture container is invalid: {}", reason)]
+    InvalidTextureContainer {
+        reason: &'static str,
+    },
+    #[error(display = "texture format {} is not supported", format)]
+    UnsupportedTextureFormat {
+        format: String,
+    },
+    #[error(display = "image is {}x{}, larger than the supported texture size", width, height)]
+    ImageTooLarge {
+        width: u32,
+        height: u32,
+    },
     #[error(display = "unknown image format {}", mime_type)]
     UnsupportedImageFormat {
         mime_type: String,
     },
     #[error(display = "decoding image failed")]
     ImageDecodeFailed(#[error(cause)] png::DecodingError),
+    #[error(display = "vertex layout is invalid")]
+    InvalidVertexLayout(#[error(cause)] VertexLayoutError),
 }
 
 impl From<gltf::Error> for ModelLoadError {
@@ -162,74 +825,75 @@ impl From<gltf::Error> for ModelLoadError {
     }
 }
 
+impl From<std::io::Error> for ModelLoadError {
+    fn from(err: std::io::Error) -> Self {
+        ModelLoadError::Io(err)
+    }
+}
+
 impl From<png::DecodingError> for ModelLoadError {
     fn from(err: png::DecodingError) -> Self {
         ModelLoadError::ImageDecodeFailed(err)
     }
 }
 
-fn mesh_name(mesh: &gltf::mesh::Mesh) -> String {
-    mesh.name().unwrap_or("<unknown>").to_string()
-}
-
-fn attribute_bytes<'a>(
-    buffers: &'a [gltf::buffer::Data],
-    mesh: &gltf::mesh::Mesh,
-    primitive: &gltf::Primitive,
-    semantic: gltf::mesh::Semantic,
-) -> Result<&'a [u8], ModelLoadError> {
-    let doc = primitive.get(&semantic)
-        .ok_or_else(|| ModelLoadError::NoSemantic { mesh: mesh_name(mesh), semantic })?;
-    Ok(access_bytes(buffers, &doc.view()))
+impl From<VertexLayoutError> for ModelLoadError {
+    fn from(err: VertexLayoutError) -> Self {
+        ModelLoadError::InvalidVertexLayout(err)
+    }
 }
 
-fn access_bytes<'a>(buffers: &'a [gltf::buffer::Data], view: &gltf::buffer::View) -> &'a [u8] {
-    let buffer_i = view.buffer().index();
-    let buffer = &buffers[buffer_i];
-
-    let start_i = view.offset();
-    let end_i = view.offset() + view.length();
-
-    &buffer[start_i..end_i]
+fn load_texture(source: &GltfSource, texture: &gltf::Texture) -> Result<Texture, ModelLoadError> {
+    let (bytes, mime_type) = source.image(&texture.source())?;
+    match mime_type.as_ref().map(String::as_str) {
+        Some("image/png") => load_png(&bytes),
+        Some("image/ktx2") => Ok(CompressedTexture::from_ktx2(&bytes)?.decode()),
+        Some("image/vnd-ms.dds") => Ok(CompressedTexture::from_dds(&bytes)?.decode()),
+        mime_type => Err(ModelLoadError::UnsupportedImageFormat {
+            mime_type: mime_type.unwrap_or("<unknown>").to_string(),
+        }),
+    }
 }
 
-fn bytes_to_u16(bytes: &[u8]) -> &[u16] {
-    use ::std::mem;
-    unsafe {
-        ::std::slice::from_raw_parts(
-            bytes.as_ptr() as *const u16,
-            bytes.len() / mem::size_of::<u16>())
-    }
+fn model_extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
 }
 
-fn bytes_to_point2(bytes: &[u8]) -> &[Point2<f32>] {
-    use ::std::mem;
-    unsafe {
-        ::std::slice::from_raw_parts(
-            bytes.as_ptr() as *const Point2<f32>,
-            bytes.len() / mem::size_of::<Point2<f32>>())
-    }
+fn mesh_name(mesh: &gltf::mesh::Mesh) -> String {
+    mesh.name().unwrap_or("<unknown>").to_string()
 }
 
-fn bytes_to_vector3(bytes: &[u8]) -> &[Vector3<f32>] {
-    use ::std::mem;
-    unsafe {
-        ::std::slice::from_raw_parts(
-            bytes.as_ptr() as *const Vector3<f32>,
-            bytes.len() / mem::size_of::<Vector3<f32>>())
-    }
+fn attribute<'a>(
+    mesh: &gltf::mesh::Mesh,
+    primitive: &'a gltf::Primitive,
+    semantic: gltf::mesh::Semantic,
+) -> Result<gltf::Accessor<'a>, ModelLoadError> {
+    primitive.get(&semantic).ok_or_else(|| ModelLoadError::NoSemantic { mesh: mesh_name(mesh), semantic })
 }
 
-fn load_png(bytes: &[u8]) -> Result<Texture, png::DecodingError> {
+fn load_png(bytes: &[u8]) -> Result<Texture, ModelLoadError> {
     use png::Decoder;
 
     let decoder = Decoder::new(bytes);
 
     let (info, mut reader) = decoder.read_info()?;
+    if info.width > MAX_TEXTURE_SIZE || info.height > MAX_TEXTURE_SIZE {
+        return Err(ModelLoadError::ImageTooLarge { width: info.width, height: info.height });
+    }
 
     let mut pixels = vec![0; info.buffer_size()];
     reader.next_frame(&mut pixels)?;
 
+    let pixels = match info.color_type {
+        png::ColorType::RGBA => pixels,
+        png::ColorType::RGB => pixels.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale | png::ColorType::Indexed => pixels.iter().flat_map(|&p| vec![p, p, p, 255]).collect(),
+    };
+
     Ok(Texture {
         width: info.width,
         height: info.height,
@@ -0,0 +1,160 @@
+use gltf::accessor::{DataType, Dimensions};
+
+use super::source::GltfSource;
+use super::ModelLoadError;
+
+pub const FLOAT: &[DataType] = &[DataType::F32];
+pub const UNSIGNED: &[DataType] = &[DataType::F32, DataType::U8, DataType::U16];
+pub const SIGNED: &[DataType] = &[DataType::F32, DataType::I8, DataType::U8, DataType::I16, DataType::U16];
+const INDEX: &[DataType] = &[DataType::U8, DataType::U16, DataType::U32];
+const JOINT: &[DataType] = &[DataType::U8, DataType::U16];
+
+// A bounds-checked window into a buffer view that reads one accessor's elements.
+struct Elements<'a> {
+    bytes: &'a [u8],
+    stride: usize,
+    count: usize,
+    data_type: DataType,
+    components: usize,
+    normalized: bool,
+}
+
+impl<'a> Elements<'a> {
+    fn new(
+        source: &'a GltfSource,
+        accessor: &gltf::Accessor,
+        dimensions: &[Dimensions],
+        data_types: &[DataType],
+    ) -> Result<Elements<'a>, ModelLoadError> {
+        let index = accessor.index();
+        if accessor.sparse().is_some() {
+            return Err(ModelLoadError::InvalidAccessor { accessor: index, reason: "sparse accessors are not supported" });
+        }
+        let (dimension, data_type) = (accessor.dimensions(), accessor.data_type());
+        if !dimensions.contains(&dimension) || !data_types.contains(&data_type) {
+            return Err(ModelLoadError::UnexpectedAccessorType { accessor: index, dimensions: dimension, data_type });
+        }
+
+        let view = accessor.view();
+        let view_bytes = source.view(&view)?;
+        let components = dimension.multiplicity();
+        let element_size = components * data_type.size();
+        let stride = view.stride().unwrap_or(element_size);
+        if stride < element_size {
+            return Err(ModelLoadError::InvalidAccessor { accessor: index, reason: "byte stride is smaller than an element" });
+        }
+        if (view.offset() + accessor.offset()) % data_type.size() != 0 || stride % data_type.size() != 0 {
+            return Err(ModelLoadError::MisalignedAccessor { accessor: index });
+        }
+
+        let count = accessor.count();
+        let end = match count {
+            0 => Some(accessor.offset()),
+            _ => stride.checked_mul(count - 1)
+                .and_then(|size| size.checked_add(element_size))
+                .and_then(|size| size.checked_add(accessor.offset())),
+        };
+        match end {
+            Some(end) if end <= view_bytes.len() => Ok(Elements {
+                bytes: &view_bytes[accessor.offset()..end],
+                stride,
+                count,
+                data_type,
+                components,
+                normalized: accessor.normalized(),
+            }),
+            _ => Err(ModelLoadError::AccessorOutOfRange { accessor: index }),
+        }
+    }
+
+    fn component_bytes(&self, element: usize, component: usize) -> &[u8] {
+        let start = element * self.stride + component * self.data_type.size();
+        &self.bytes[start..start + self.data_type.size()]
+    }
+
+    fn integer(&self, element: usize, component: usize) -> u32 {
+        let bytes = self.component_bytes(element, component);
+        match self.data_type {
+            DataType::U8 | DataType::I8 => bytes[0] as u32,
+            DataType::U16 | DataType::I16 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            DataType::U32 | DataType::F32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    // Normalized integers map to [0, 1] or [-1, 1]; other integers keep their value.
+    fn float(&self, element: usize, component: usize) -> f32 {
+        let value = self.integer(element, component);
+        match (self.data_type, self.normalized) {
+            (DataType::F32, _) => f32::from_bits(value),
+            (DataType::I8, true) => (value as u8 as i8 as f32 / 127.0).max(-1.0),
+            (DataType::I8, false) => value as u8 as i8 as f32,
+            (DataType::I16, true) => (value as u16 as i16 as f32 / 32767.0).max(-1.0),
+            (DataType::I16, false) => value as u16 as i16 as f32,
+            (DataType::U8, true) => value as f32 / 255.0,
+            (DataType::U16, true) => value as f32 / 65535.0,
+            (DataType::U32, true) => value as f32 / 4_294_967_295.0,
+            (_, false) => value as f32,
+        }
+    }
+
+    fn map<T>(&self, read: impl Fn(&Elements, usize) -> T) -> Vec<T> {
+        (0..self.count).map(|element| read(self, element)).collect()
+    }
+}
+
+pub fn read_scalars(source: &GltfSource, accessor: &gltf::Accessor, data_types: &[DataType]) -> Result<Vec<f32>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Scalar], data_types)?;
+    Ok(elements.map(|elements, i| elements.float(i, 0)))
+}
+
+pub fn read_vec2(source: &GltfSource, accessor: &gltf::Accessor, data_types: &[DataType]) -> Result<Vec<[f32; 2]>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Vec2], data_types)?;
+    Ok(elements.map(|elements, i| [elements.float(i, 0), elements.float(i, 1)]))
+}
+
+pub fn read_vec3(source: &GltfSource, accessor: &gltf::Accessor, data_types: &[DataType]) -> Result<Vec<[f32; 3]>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Vec3], data_types)?;
+    Ok(elements.map(|elements, i| [elements.float(i, 0), elements.float(i, 1), elements.float(i, 2)]))
+}
+
+pub fn read_vec4(source: &GltfSource, accessor: &gltf::Accessor, data_types: &[DataType]) -> Result<Vec<[f32; 4]>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Vec4], data_types)?;
+    Ok(elements.map(|elements, i| {
+        [elements.float(i, 0), elements.float(i, 1), elements.float(i, 2), elements.float(i, 3)]
+    }))
+}
+
+// Colors may be RGB or RGBA; missing alpha is opaque.
+pub fn read_colors(source: &GltfSource, accessor: &gltf::Accessor) -> Result<Vec<[f32; 4]>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Vec3, Dimensions::Vec4], UNSIGNED)?;
+    Ok(elements.map(|elements, i| {
+        let alpha = if elements.components == 4 { elements.float(i, 3) } else { 1.0 };
+        [elements.float(i, 0), elements.float(i, 1), elements.float(i, 2), alpha]
+    }))
+}
+
+pub fn read_matrices(source: &GltfSource, accessor: &gltf::Accessor) -> Result<Vec<[[f32; 4]; 4]>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Mat4], FLOAT)?;
+    Ok(elements.map(|elements, i| {
+        let column = |c: usize| [
+            elements.float(i, c * 4),
+            elements.float(i, c * 4 + 1),
+            elements.float(i, c * 4 + 2),
+            elements.float(i, c * 4 + 3),
+        ];
+        [column(0), column(1), column(2), column(3)]
+    }))
+}
+
+pub fn read_indices(source: &GltfSource, accessor: &gltf::Accessor) -> Result<Vec<u32>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Scalar], INDEX)?;
+    Ok(elements.map(|elements, i| elements.integer(i, 0)))
+}
+
+pub fn read_joints(source: &GltfSource, accessor: &gltf::Accessor) -> Result<Vec<[u16; 4]>, ModelLoadError> {
+    let elements = Elements::new(source, accessor, &[Dimensions::Vec4], JOINT)?;
+    Ok(elements.map(|elements, i| {
+        let joint = |c: usize| elements.integer(i, c) as u16;
+        [joint(0), joint(1), joint(2), joint(3)]
+    }))
+}
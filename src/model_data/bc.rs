@@ -0,0 +1,953 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    Bc6h,
+    Bc7,
+}
+
+type Block = [[u8; 4]; 16];
+
+impl BlockFormat {
+    pub fn from_name(name: &str) -> Option<BlockFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "bc1" => Some(BlockFormat::Bc1),
+            "bc2" => Some(BlockFormat::Bc2),
+            "bc3" => Some(BlockFormat::Bc3),
+            "bc4" => Some(BlockFormat::Bc4),
+            "bc5" => Some(BlockFormat::Bc5),
+            "bc6h" => Some(BlockFormat::Bc6h),
+            "bc7" => Some(BlockFormat::Bc7),
+            _ => None,
+        }
+    }
+
+    pub fn block_size(self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    pub fn can_encode(self) -> bool {
+        match self {
+            BlockFormat::Bc2 | BlockFormat::Bc6h => false,
+            _ => true,
+        }
+    }
+
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        let blocks_wide = ((width + 3) / 4).max(1) as usize;
+        let blocks_high = ((height + 3) / 4).max(1) as usize;
+        blocks_wide * blocks_high * self.block_size()
+    }
+}
+
+pub fn decode_image(format: BlockFormat, signed: bool, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = (width + 3) / 4;
+    let mut pixels = vec![0; width * height * 4];
+    for (i, block) in data.chunks(format.block_size()).enumerate() {
+        let (block_x, block_y) = (i % blocks_wide * 4, i / blocks_wide * 4);
+        if block_y >= height {
+            break;
+        }
+        let decoded = decode_block(format, signed, block);
+        for (texel, color) in decoded.iter().enumerate() {
+            let (x, y) = (block_x + texel % 4, block_y + texel / 4);
+            if x < width && y < height {
+                pixels[(y * width + x) * 4..(y * width + x) * 4 + 4].copy_from_slice(color);
+            }
+        }
+    }
+    pixels
+}
+
+pub fn encode_image(format: BlockFormat, width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut data = Vec::with_capacity(format.level_size(width as u32, height as u32));
+    for block_y in (0..height.max(1)).step_by(4) {
+        for block_x in (0..width.max(1)).step_by(4) {
+            let mut block = [[0u8; 4]; 16];
+            for (texel, color) in block.iter_mut().enumerate() {
+                let x = (block_x + texel % 4).min(width - 1);
+                let y = (block_y + texel / 4).min(height - 1);
+                color.copy_from_slice(&pixels[(y * width + x) * 4..(y * width + x) * 4 + 4]);
+            }
+            data.extend_from_slice(&encode_block(format, &block));
+        }
+    }
+    data
+}
+
+pub fn decode_block(format: BlockFormat, signed: bool, block: &[u8]) -> Block {
+    let mut pixels = [[0, 0, 0, 255]; 16];
+    match format {
+        BlockFormat::Bc1 => decode_color(block, false, &mut pixels),
+        BlockFormat::Bc2 => {
+            decode_color(&block[8..16], true, &mut pixels);
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                pixel[3] = ((block[i / 2] >> (i % 2 * 4)) & 0x0F) * 17;
+            }
+        }
+        BlockFormat::Bc3 => {
+            decode_color(&block[8..16], true, &mut pixels);
+            decode_alpha(&block[0..8], false, &mut pixels, 3);
+        }
+        BlockFormat::Bc4 => {
+            decode_alpha(block, signed, &mut pixels, 0);
+            for pixel in pixels.iter_mut() {
+                pixel[1] = pixel[0];
+                pixel[2] = pixel[0];
+            }
+        }
+        BlockFormat::Bc5 => {
+            decode_alpha(&block[0..8], signed, &mut pixels, 0);
+            decode_alpha(&block[8..16], signed, &mut pixels, 1);
+        }
+        BlockFormat::Bc6h => decode_bc6h(block, signed, &mut pixels),
+        BlockFormat::Bc7 => decode_bc7(block, &mut pixels),
+    }
+    pixels
+}
+
+pub fn encode_block(format: BlockFormat, pixels: &Block) -> Vec<u8> {
+    match format {
+        BlockFormat::Bc1 => encode_color(pixels, true).to_vec(),
+        BlockFormat::Bc3 => {
+            let mut block = encode_alpha(pixels, 3).to_vec();
+            block.extend_from_slice(&encode_color(pixels, false));
+            block
+        }
+        BlockFormat::Bc4 => encode_alpha(pixels, 0).to_vec(),
+        BlockFormat::Bc5 => {
+            let mut block = encode_alpha(pixels, 0).to_vec();
+            block.extend_from_slice(&encode_alpha(pixels, 1));
+            block
+        }
+        BlockFormat::Bc7 => encode_bc7(pixels).to_vec(),
+        BlockFormat::Bc2 | BlockFormat::Bc6h => vec![0; format.block_size()],
+    }
+}
+
+fn expand_565(color: u16) -> [i32; 3] {
+    let (r, g, b) = ((color >> 11) & 0x1F, (color >> 5) & 0x3F, color & 0x1F);
+    [
+        ((r << 3) | (r >> 2)) as i32,
+        ((g << 2) | (g >> 4)) as i32,
+        ((b << 3) | (b >> 2)) as i32,
+    ]
+}
+
+fn color_palette(c0: u16, c1: u16, four_colors: bool) -> [[u8; 4]; 4] {
+    let (a, b) = (expand_565(c0), expand_565(c1));
+    let mix = |wa: i32, wb: i32, total: i32| -> [u8; 4] {
+        [
+            ((a[0] * wa + b[0] * wb + total / 2) / total) as u8,
+            ((a[1] * wa + b[1] * wb + total / 2) / total) as u8,
+            ((a[2] * wa + b[2] * wb + total / 2) / total) as u8,
+            255,
+        ]
+    };
+    if four_colors {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(1, 1, 2), [0, 0, 0, 0]]
+    }
+}
+
+fn decode_color(block: &[u8], always_four_colors: bool, pixels: &mut Block) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let palette = color_palette(c0, c1, always_four_colors || c0 > c1);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let color = palette[(indices >> (i * 2)) as usize & 3];
+        if always_four_colors {
+            pixel[..3].copy_from_slice(&color[..3]);
+        } else {
+            *pixel = color;
+        }
+    }
+}
+
+fn alpha_palette(a0: i32, a1: i32, signed: bool) -> [i32; 8] {
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 0];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * a0 + i as i32 * a1 + 3) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as i32) * a0 + i as i32 * a1 + 2) / 5;
+        }
+        palette[6] = if signed { -127 } else { 0 };
+        palette[7] = if signed { 127 } else { 255 };
+    }
+    palette
+}
+
+fn decode_alpha(block: &[u8], signed: bool, pixels: &mut Block, channel: usize) {
+    let (a0, a1) = if signed {
+        ((block[0] as i8).max(-127) as i32, (block[1] as i8).max(-127) as i32)
+    } else {
+        (block[0] as i32, block[1] as i32)
+    };
+    let palette = alpha_palette(a0, a1, signed);
+    let bits = block[2..8].iter().rev().fold(0u64, |bits, &byte| bits << 8 | byte as u64);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let value = palette[(bits >> (i * 3)) as usize & 7];
+        pixel[channel] = if signed { ((value + 127) * 255 / 254) as u8 } else { value as u8 };
+    }
+}
+
+fn to_565(color: [f32; 3]) -> u16 {
+    let quantize = |value: f32, max: f32| (value.max(0.0).min(255.0) / 255.0 * max).round() as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+// Endpoints along the principal axis of the colors, found with a few power iterations.
+fn principal_endpoints<'a>(colors: impl Iterator<Item = &'a [u8; 4]> + Clone, channels: usize) -> ([f32; 4], [f32; 4]) {
+    let count = colors.clone().count().max(1) as f32;
+    let mut mean = [0.0f32; 4];
+    for color in colors.clone() {
+        for c in 0..channels {
+            mean[c] += color[c] as f32 / count;
+        }
+    }
+
+    let mut covariance = [[0.0f32; 4]; 4];
+    for color in colors.clone() {
+        for i in 0..channels {
+            for j in 0..channels {
+                covariance[i][j] += (color[i] as f32 - mean[i]) * (color[j] as f32 - mean[j]);
+            }
+        }
+    }
+
+    let mut axis = [1.0f32; 4];
+    for _ in 0..8 {
+        let mut next = [0.0f32; 4];
+        for i in 0..channels {
+            for j in 0..channels {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = [next[0] / length, next[1] / length, next[2] / length, next[3] / length];
+    }
+
+    let project = |color: &[u8; 4]| (0..channels).map(|c| (color[c] as f32 - mean[c]) * axis[c]).sum::<f32>();
+    let min = colors.clone().map(project).fold(::std::f32::MAX, f32::min);
+    let max = colors.map(project).fold(::std::f32::MIN, f32::max);
+    let point = |t: f32| [mean[0] + axis[0] * t, mean[1] + axis[1] * t, mean[2] + axis[2] * t, mean[3] + axis[3] * t];
+    (point(min.min(max)), point(max.max(min)))
+}
+
+fn color_distance(a: &[u8; 4], b: &[u8; 4], channels: usize) -> i32 {
+    (0..channels).map(|c| (a[c] as i32 - b[c] as i32).pow(2)).sum()
+}
+
+fn nearest(palette: &[[u8; 4]], color: &[u8; 4], channels: usize) -> usize {
+    (0..palette.len()).min_by_key(|&i| color_distance(&palette[i], color, channels)).unwrap_or(0)
+}
+
+fn encode_color(pixels: &Block, allow_transparent: bool) -> [u8; 8] {
+    let transparent = allow_transparent && pixels.iter().any(|pixel| pixel[3] < 128);
+    let opaque = pixels.iter().filter(|pixel| !allow_transparent || pixel[3] >= 128);
+    let (low, high) = if opaque.clone().next().is_some() {
+        principal_endpoints(opaque, 3)
+    } else {
+        ([0.0; 4], [0.0; 4])
+    };
+
+    let (mut c0, mut c1) = (to_565([high[0], high[1], high[2]]), to_565([low[0], low[1], low[2]]));
+    if transparent == (c0 > c1) {
+        ::std::mem::swap(&mut c0, &mut c1);
+    }
+    let four_colors = c0 > c1;
+    let palette = color_palette(c0, c1, four_colors);
+    let usable = if four_colors { 4 } else { 3 };
+
+    let mut indices = 0u32;
+    for (i, pixel) in pixels.iter().enumerate() {
+        let index = if transparent && pixel[3] < 128 { 3 } else { nearest(&palette[..usable], pixel, 3) };
+        indices |= (index as u32) << (i * 2);
+    }
+
+    let mut block = [0u8; 8];
+    block[0..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..8].copy_from_slice(&indices.to_le_bytes());
+    block
+}
+
+fn encode_alpha(pixels: &Block, channel: usize) -> [u8; 8] {
+    let a0 = pixels.iter().map(|pixel| pixel[channel]).max().unwrap_or(0);
+    let a1 = pixels.iter().map(|pixel| pixel[channel]).min().unwrap_or(0);
+    let palette = alpha_palette(a0 as i32, a1 as i32, false);
+
+    let mut bits = 0u64;
+    for (i, pixel) in pixels.iter().enumerate() {
+        let value = pixel[channel] as i32;
+        let index = (0..8).min_by_key(|&j| (palette[j] - value).abs()).unwrap_or(0);
+        bits |= (index as u64) << (i * 3);
+    }
+
+    let mut block = [a0, a1, 0, 0, 0, 0, 0, 0];
+    for (i, byte) in block[2..8].iter_mut().enumerate() {
+        *byte = (bits >> (i * 8)) as u8;
+    }
+    block
+}
+
+const WEIGHTS_2: [i32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(bits: u32) -> &'static [i32] {
+    match bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+fn interpolate(a: i32, b: i32, weight: i32) -> i32 {
+    (a * (64 - weight) + b * weight + 32) >> 6
+}
+
+// Subset of each texel for the two-subset partitions, one bit per texel.
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+// Subset of each texel for the three-subset partitions, two bits per texel.
+const PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050,
+    0x5555A0A0, 0x5A5A5050, 0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090,
+    0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250, 0xA5945040, 0x0A425054,
+    0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414,
+    0x50A4A450, 0x6A5A0200, 0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424,
+    0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50, 0x500AA550, 0xAAAA4444,
+    0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580,
+    0xAA141414, 0x96960000, 0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000,
+    0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+const ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+    3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+    8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+    3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+];
+
+const ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+    15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+    15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+    15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+fn subset(subsets: usize, partition: usize, texel: usize) -> usize {
+    match subsets {
+        2 => (PARTITIONS_2[partition] >> texel) as usize & 1,
+        3 => (PARTITIONS_3[partition] >> (texel * 2)) as usize & 3,
+        _ => 0,
+    }
+}
+
+fn is_anchor(subsets: usize, partition: usize, texel: usize) -> bool {
+    texel == 0 || match subsets {
+        2 => ANCHORS_2[partition] as usize == texel,
+        3 => ANCHORS_3_SECOND[partition] as usize == texel || ANCHORS_3_THIRD[partition] as usize == texel,
+        _ => false,
+    }
+}
+
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> BitReader {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&block[..16]);
+        BitReader { bits: u128::from_le_bytes(bytes) }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits & ((1u128 << count) - 1)) as u32;
+        self.bits >>= count;
+        value
+    }
+}
+
+struct BitWriter {
+    bits: u128,
+    position: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= ((value as u128) & ((1u128 << count) - 1)) << self.position;
+        self.position += count;
+    }
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, secondary_index_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, secondary_index_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, secondary_index_bits: 0 },
+];
+
+fn unquantize_bc7(value: u32, bits: u32) -> i32 {
+    let value = value << (8 - bits);
+    (value | (value >> bits)) as i32
+}
+
+fn decode_bc7(block: &[u8], pixels: &mut Block) {
+    let mut reader = BitReader::new(block);
+    let mode_index = match (0..8).find(|_| reader.read(1) == 1) {
+        Some(mode_index) => mode_index,
+        None => {
+            *pixels = [[0; 4]; 16];
+            return;
+        }
+    };
+    let mode = &BC7_MODES[mode_index];
+
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = reader.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = reader.read(mode.alpha_bits);
+    }
+
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+    if mode.endpoint_pbits {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            let pbit = reader.read(1);
+            for channel in endpoint.iter_mut() {
+                *channel = *channel << 1 | pbit;
+            }
+        }
+    } else if mode.shared_pbits {
+        for subset in 0..mode.subsets {
+            let pbit = reader.read(1);
+            for endpoint in endpoints[subset * 2..subset * 2 + 2].iter_mut() {
+                for channel in endpoint.iter_mut() {
+                    *channel = *channel << 1 | pbit;
+                }
+            }
+        }
+    }
+
+    let pbit = if has_pbits { 1 } else { 0 };
+    let mut colors = [[0i32; 4]; 6];
+    for (color, endpoint) in colors.iter_mut().zip(endpoints.iter()).take(endpoint_count) {
+        for channel in 0..3 {
+            color[channel] = unquantize_bc7(endpoint[channel], mode.color_bits + pbit);
+        }
+        color[3] = if mode.alpha_bits == 0 { 255 } else { unquantize_bc7(endpoint[3], mode.alpha_bits + pbit) };
+    }
+
+    let mut primary = [0u32; 16];
+    for (texel, index) in primary.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, texel);
+        *index = reader.read(mode.index_bits - anchor as u32);
+    }
+    let mut secondary = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (texel, index) in secondary.iter_mut().enumerate() {
+            *index = reader.read(mode.secondary_index_bits - (texel == 0) as u32);
+        }
+    }
+
+    for (texel, pixel) in pixels.iter_mut().enumerate() {
+        let subset = subset(mode.subsets, partition, texel);
+        let (a, b) = (colors[subset * 2], colors[subset * 2 + 1]);
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = weights(mode.index_bits)[primary[texel] as usize];
+            (weight, weight)
+        } else if index_selection == 0 {
+            (weights(mode.index_bits)[primary[texel] as usize], weights(mode.secondary_index_bits)[secondary[texel] as usize])
+        } else {
+            (weights(mode.secondary_index_bits)[secondary[texel] as usize], weights(mode.index_bits)[primary[texel] as usize])
+        };
+
+        let mut color = [0u8; 4];
+        for channel in 0..3 {
+            color[channel] = interpolate(a[channel], b[channel], color_weight) as u8;
+        }
+        color[3] = interpolate(a[3], b[3], alpha_weight) as u8;
+        if rotation > 0 {
+            color.swap(3, rotation as usize - 1);
+        }
+        *pixel = color;
+    }
+}
+
+// Encodes every block with mode 6: one subset, 7-bit RGBA endpoints with a p-bit each and
+// 4-bit indices. It's fast and handles alpha, which is what the conversion command needs.
+fn encode_bc7(pixels: &Block) -> [u8; 16] {
+    let (low, high) = principal_endpoints(pixels.iter(), 4);
+
+    let quantize = |endpoint: [f32; 4]| -> ([u32; 4], u32) {
+        (0..2u32)
+            .map(|pbit| {
+                let mut quantized = [0u32; 4];
+                let mut error = 0.0;
+                for channel in 0..4 {
+                    let value = endpoint[channel].max(0.0).min(255.0);
+                    let q = ((value - pbit as f32) / 2.0).round().max(0.0).min(127.0) as u32;
+                    error += (unquantize_bc7(q << 1 | pbit, 8) as f32 - value).powi(2);
+                    quantized[channel] = q;
+                }
+                (quantized, pbit, error)
+            })
+            .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(::std::cmp::Ordering::Equal))
+            .map(|(quantized, pbit, _)| (quantized, pbit))
+            .unwrap()
+    };
+    let (mut e0, mut e1) = (quantize(low), quantize(high));
+
+    let palette = |e0: &([u32; 4], u32), e1: &([u32; 4], u32)| -> Vec<[u8; 4]> {
+        WEIGHTS_4.iter()
+            .map(|&weight| {
+                let mut color = [0u8; 4];
+                for channel in 0..4 {
+                    let a = unquantize_bc7(e0.0[channel] << 1 | e0.1, 8);
+                    let b = unquantize_bc7(e1.0[channel] << 1 | e1.1, 8);
+                    color[channel] = interpolate(a, b, weight) as u8;
+                }
+                color
+            })
+            .collect()
+    };
+
+    let mut colors = palette(&e0, &e1);
+    let mut indices: Vec<usize> = pixels.iter().map(|pixel| nearest(&colors, pixel, 4)).collect();
+    if indices[0] >= 8 {
+        ::std::mem::swap(&mut e0, &mut e1);
+        colors = palette(&e0, &e1);
+        indices = pixels.iter().map(|pixel| nearest(&colors, pixel, 4)).collect();
+    }
+
+    let mut writer = BitWriter { bits: 0, position: 0 };
+    writer.write(1 << 6, 7);
+    for channel in 0..4 {
+        writer.write(e0.0[channel], 7);
+        writer.write(e1.0[channel], 7);
+    }
+    writer.write(e0.1, 1);
+    writer.write(e1.1, 1);
+    for (texel, &index) in indices.iter().enumerate() {
+        writer.write(index as u32, if texel == 0 { 3 } else { 4 });
+    }
+    writer.bits.to_le_bytes()
+}
+
+// Bit layout of each BC6H mode as (field, first bit, bit count) runs in stream order. Fields are
+// endpoint * 3 + channel for the w, x, y and z endpoints, and 12 for the partition.
+const RW: u8 = 0;
+const GW: u8 = 1;
+const BW: u8 = 2;
+const RX: u8 = 3;
+const GX: u8 = 4;
+const BX: u8 = 5;
+const RY: u8 = 6;
+const GY: u8 = 7;
+const BY: u8 = 8;
+const RZ: u8 = 9;
+const GZ: u8 = 10;
+const BZ: u8 = 11;
+const D: u8 = 12;
+
+const BC6H_LAYOUTS: [&[(u8, u8, u8)]; 14] = [
+    &[(GY, 4, 1), (BY, 4, 1), (BZ, 4, 1), (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1), (D, 0, 5)],
+    &[(GY, 5, 1), (GZ, 4, 1), (GZ, 5, 1), (RW, 0, 7), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 7), (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 7), (BZ, 3, 1), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6), (D, 0, 5)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5), (RW, 10, 1), (GY, 0, 4), (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 4), (BW, 10, 1), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1), (D, 0, 5)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (GW, 10, 1), (GZ, 0, 4), (BX, 0, 4), (BW, 10, 1), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 4), (BZ, 0, 1), (BZ, 2, 1), (RZ, 0, 4), (GY, 4, 1), (BZ, 3, 1), (D, 0, 5)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (BY, 4, 1), (GY, 0, 4), (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BW, 10, 1), (BY, 0, 4), (RY, 0, 4), (BZ, 1, 1), (BZ, 2, 1), (RZ, 0, 4), (BZ, 4, 1), (BZ, 3, 1), (D, 0, 5)],
+    &[(RW, 0, 9), (BY, 4, 1), (GW, 0, 9), (GY, 4, 1), (BW, 0, 9), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1), (D, 0, 5)],
+    &[(RW, 0, 8), (GZ, 4, 1), (BY, 4, 1), (GW, 0, 8), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 8), (BZ, 3, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6), (D, 0, 5)],
+    &[(RW, 0, 8), (BZ, 0, 1), (BY, 4, 1), (GW, 0, 8), (GY, 5, 1), (GY, 4, 1), (BW, 0, 8), (GZ, 5, 1), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1), (D, 0, 5)],
+    &[(RW, 0, 8), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 8), (BY, 5, 1), (GY, 4, 1), (BW, 0, 8), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1), (D, 0, 5)],
+    &[(RW, 0, 6), (GZ, 4, 1), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 6), (GY, 5, 1), (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 6), (GZ, 5, 1), (BZ, 3, 1), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6), (D, 0, 5)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 10), (GX, 0, 10), (BX, 0, 10)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 9), (RW, 10, 1), (GX, 0, 9), (GW, 10, 1), (BX, 0, 9), (BW, 10, 1)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 8), (RW, 11, 1), (RW, 10, 1), (GX, 0, 8), (GW, 11, 1), (GW, 10, 1), (BX, 0, 8), (BW, 11, 1), (BW, 10, 1)],
+    &[(RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 15, 1), (RW, 14, 1), (RW, 13, 1), (RW, 12, 1), (RW, 11, 1), (RW, 10, 1), (GX, 0, 4), (GW, 15, 1), (GW, 14, 1), (GW, 13, 1), (GW, 12, 1), (GW, 11, 1), (GW, 10, 1), (BX, 0, 4), (BW, 15, 1), (BW, 14, 1), (BW, 13, 1), (BW, 12, 1), (BW, 11, 1), (BW, 10, 1)],
+];
+
+// Endpoint precision and delta precision per channel for each BC6H mode.
+const BC6H_ENDPOINT_BITS: [i32; 14] = [10, 7, 11, 11, 11, 9, 8, 8, 8, 6, 10, 11, 12, 16];
+const BC6H_DELTA_BITS: [[i32; 3]; 14] = [
+    [5, 5, 5], [6, 6, 6], [5, 4, 4], [4, 5, 4], [4, 4, 5], [5, 5, 5], [6, 5, 5],
+    [5, 6, 5], [5, 5, 6], [6, 6, 6], [10, 10, 10], [9, 9, 9], [8, 8, 8], [4, 4, 4],
+];
+
+fn sign_extend(value: i32, bits: i32) -> i32 {
+    (value << (32 - bits)) >> (32 - bits)
+}
+
+fn unquantize_bc6h(value: i32, bits: i32, signed: bool) -> i32 {
+    if !signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xFFFF
+        } else {
+            ((value << 16) + 0x8000) >> bits
+        }
+    } else {
+        if bits >= 16 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let unquantized = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 { -unquantized } else { unquantized }
+    }
+}
+
+fn finish_unquantize_bc6h(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else {
+        let scaled = if value < 0 { -((-value * 31) >> 5) } else { (value * 31) >> 5 };
+        if scaled < 0 { 0x8000 | (-scaled) as u16 } else { scaled as u16 }
+    }
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1F;
+    let mantissa = (half & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa / 1024.0 * 2.0f32.powi(-14),
+        0x1F => ::std::f32::INFINITY,
+        _ => (1.0 + mantissa / 1024.0) * 2.0f32.powi(exponent as i32 - 15),
+    }
+}
+
+// BC6H holds HDR data; the decoder clamps it to [0, 1] since textures are stored as RGBA8.
+fn decode_bc6h(block: &[u8], signed: bool, pixels: &mut Block) {
+    let mut reader = BitReader::new(block);
+    let mut mode_bits = reader.read(2);
+    if mode_bits > 1 {
+        mode_bits |= reader.read(3) << 2;
+    }
+    let mode = match mode_bits {
+        0b00 => 0,
+        0b01 => 1,
+        0b00010 => 2,
+        0b00110 => 3,
+        0b01010 => 4,
+        0b01110 => 5,
+        0b10010 => 6,
+        0b10110 => 7,
+        0b11010 => 8,
+        0b11110 => 9,
+        0b00011 => 10,
+        0b00111 => 11,
+        0b01011 => 12,
+        0b01111 => 13,
+        _ => {
+            *pixels = [[0, 0, 0, 255]; 16];
+            return;
+        }
+    };
+
+    let mut fields = [0i32; 13];
+    for &(field, shift, count) in BC6H_LAYOUTS[mode] {
+        fields[field as usize] |= (reader.read(count as u32) as i32) << shift;
+    }
+    let partition = fields[D as usize] as usize;
+    let subsets = if mode >= 10 { 1 } else { 2 };
+    let endpoint_bits = BC6H_ENDPOINT_BITS[mode];
+    let transformed = mode != 9 && mode != 10;
+
+    let mut endpoints = [[0i32; 3]; 4];
+    for (endpoint, values) in endpoints.iter_mut().enumerate().take(subsets * 2) {
+        for channel in 0..3 {
+            let mut value = fields[endpoint * 3 + channel];
+            if endpoint == 0 {
+                if signed {
+                    value = sign_extend(value, endpoint_bits);
+                }
+            } else {
+                if transformed || signed {
+                    value = sign_extend(value, BC6H_DELTA_BITS[mode][channel]);
+                }
+                if transformed {
+                    value = (value + fields[channel]) & ((1 << endpoint_bits) - 1);
+                    if signed {
+                        value = sign_extend(value, endpoint_bits);
+                    }
+                }
+            }
+            values[channel] = value;
+        }
+    }
+    for values in endpoints.iter_mut().take(subsets * 2) {
+        for value in values.iter_mut() {
+            *value = unquantize_bc6h(*value, endpoint_bits, signed);
+        }
+    }
+
+    let index_bits = if subsets == 1 { 4 } else { 3 };
+    for (texel, pixel) in pixels.iter_mut().enumerate() {
+        let subset = if subsets == 1 { 0 } else { subset(2, partition, texel) };
+        let anchor = is_anchor(subsets, partition, texel);
+        let index = reader.read(index_bits - anchor as u32) as usize;
+        let weight = weights(index_bits)[index];
+        for channel in 0..3 {
+            let value = interpolate(endpoints[subset * 2][channel], endpoints[subset * 2 + 1][channel], weight);
+            let half = finish_unquantize_bc6h(value, signed);
+            pixel[channel] = (half_to_f32(half).max(0.0).min(1.0) * 255.0).round() as u8;
+        }
+        pixel[3] = 255;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_block, decode_image, encode_block, encode_image, Block, BlockFormat};
+
+    const ENCODABLE: [BlockFormat; 5] = [BlockFormat::Bc1, BlockFormat::Bc3, BlockFormat::Bc4, BlockFormat::Bc5, BlockFormat::Bc7];
+
+    // The channels each format stores, BC4 and BC5 only keep red and green.
+    fn channels(format: BlockFormat) -> &'static [usize] {
+        match format {
+            BlockFormat::Bc1 => &[0, 1, 2],
+            BlockFormat::Bc4 => &[0],
+            BlockFormat::Bc5 => &[0, 1],
+            _ => &[0, 1, 2, 3],
+        }
+    }
+
+    // Largest per channel error for a short gradient, from how many palette entries each format
+    // interpolates between its endpoints.
+    fn tolerance(format: BlockFormat) -> i32 {
+        match format {
+            BlockFormat::Bc1 | BlockFormat::Bc3 => 12,
+            BlockFormat::Bc4 | BlockFormat::Bc5 => 6,
+            _ => 3,
+        }
+    }
+
+    fn max_error(format: BlockFormat, a: &[[u8; 4]], b: &[[u8; 4]]) -> i32 {
+        a.iter().zip(b.iter())
+            .flat_map(|(a, b)| channels(format).iter().map(move |&c| (a[c] as i32 - b[c] as i32).abs()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Colors along a line, which is what block compression is built for.
+    fn gradient() -> Block {
+        let mut block = [[0; 4]; 16];
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let t = 100 + i as u8 * 4;
+            *pixel = [t, 255 - t, t / 2 + 64, 255 - t / 3];
+        }
+        block
+    }
+
+    #[test]
+    fn decodes_bc1_blocks() {
+        // Red and blue endpoints, each row uses the four palette entries in order.
+        let four_colors = decode_block(BlockFormat::Bc1, false, &[0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4]);
+        assert_eq!(four_colors[..4], [[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]]);
+        assert_eq!(four_colors[12..], four_colors[..4]);
+
+        // With the endpoints the other way around the third color is the midpoint and the fourth
+        // is transparent black.
+        let three_colors = decode_block(BlockFormat::Bc1, false, &[0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4]);
+        assert_eq!(three_colors[..4], [[0, 0, 255, 255], [255, 0, 0, 255], [128, 0, 128, 255], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn decodes_bc2_and_bc3_alpha() {
+        // BC2 stores alpha as 4-bit values, low nibble first. Its color block is always opaque.
+        let mut bc2 = vec![0xF0, 0x5A, 0, 0, 0, 0, 0, 0];
+        bc2.extend_from_slice(&[0x1F, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let pixels = decode_block(BlockFormat::Bc2, false, &bc2);
+        assert_eq!(pixels[0], [170, 0, 85, 0]);
+        assert_eq!(pixels[1], [170, 0, 85, 255]);
+        assert_eq!(pixels[2][3], 0xA * 17);
+        assert_eq!(pixels[3][3], 0x5 * 17);
+
+        // Eight value alpha palette from 255 down to 0, with texels 0 to 7 using each entry.
+        let mut bc3 = vec![255, 0, 0x88, 0xC6, 0xFA, 0, 0, 0];
+        bc3.extend_from_slice(&[0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0]);
+        let pixels = decode_block(BlockFormat::Bc3, false, &bc3);
+        let alphas: Vec<u8> = pixels[..8].iter().map(|pixel| pixel[3]).collect();
+        assert_eq!(alphas, vec![255, 0, 219, 182, 146, 109, 73, 36]);
+        assert_eq!(pixels[0][..3], [255, 0, 0]);
+        assert_eq!(pixels[8][3], 255);
+    }
+
+    #[test]
+    fn decodes_bc4_and_bc5_blocks() {
+        // With a0 <= a1 the palette has four interpolated values plus 0 and 255.
+        let block = [0, 255, 0x88, 0xC6, 0xFA, 0, 0, 0];
+        let pixels = decode_block(BlockFormat::Bc4, false, &block);
+        let reds: Vec<u8> = pixels[..8].iter().map(|pixel| pixel[0]).collect();
+        assert_eq!(reds, vec![0, 255, 51, 102, 153, 204, 0, 255]);
+        assert_eq!(pixels[2], [51, 51, 51, 255]);
+
+        // Signed values from -127 to 127 are mapped to 0 to 255.
+        let signed = decode_block(BlockFormat::Bc4, true, &[0x7F, 0x81, 0x08, 0, 0, 0, 0, 0]);
+        assert_eq!(signed[0][0], 255);
+        assert_eq!(signed[1][0], 0);
+
+        let mut bc5 = block.to_vec();
+        bc5.extend_from_slice(&[200, 100, 0, 0, 0, 0, 0, 0]);
+        let pixels = decode_block(BlockFormat::Bc5, false, &bc5);
+        assert_eq!(pixels[2], [51, 200, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc7_blocks() {
+        // Mode 6: one subset with 7-bit RGBA endpoints, a p-bit each and 4-bit indices 0 to 15.
+        let mode_6 = [64, 192, 31, 8, 248, 3, 254, 255, 17, 50, 84, 118, 152, 186, 220, 254];
+        let pixels = decode_block(BlockFormat::Bc7, false, &mode_6);
+        assert_eq!(pixels[0], [1, 129, 255, 255]);
+        assert_eq!(pixels[8], [136, 61, 120, 255]);
+        assert_eq!(pixels[15], [255, 1, 1, 255]);
+
+        // Mode 1 with partition 13: a gray ramp in the top half and red to blue in the bottom half,
+        // whose anchor index is texel 15.
+        let mode_1 = [54, 192, 255, 3, 192, 15, 0, 192, 15, 252, 17, 141, 245, 17, 141, 245];
+        let pixels = decode_block(BlockFormat::Bc7, false, &mode_1);
+        assert_eq!(pixels[..8], [
+            [2, 2, 2, 255], [38, 38, 38, 255], [73, 73, 73, 255], [109, 109, 109, 255],
+            [148, 148, 148, 255], [184, 184, 184, 255], [219, 219, 219, 255], [255, 255, 255, 255],
+        ]);
+        assert_eq!(pixels[8..], [
+            [253, 0, 0, 255], [217, 0, 36, 255], [182, 0, 71, 255], [146, 0, 107, 255],
+            [107, 0, 146, 255], [71, 0, 182, 255], [36, 0, 217, 255], [146, 0, 107, 255],
+        ]);
+
+        // A block without a mode bit is reserved and decodes to transparent black.
+        assert_eq!(decode_block(BlockFormat::Bc7, false, &[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn decodes_bc6h_blocks() {
+        // Mode 10: one subset with untransformed 10-bit endpoints, black to (max, 462, 0), clamped
+        // to [0, 1] after converting from half floats.
+        let mode_10 = [3, 0, 0, 0, 248, 223, 57, 0, 16, 50, 84, 118, 152, 186, 220, 254];
+        let pixels = decode_block(BlockFormat::Bc6h, false, &mode_10);
+        assert_eq!(pixels[0], [0, 0, 0, 255]);
+        assert_eq!(pixels[6], [51, 0, 0, 255]);
+        assert_eq!(pixels[7], [195, 1, 0, 255]);
+        assert_eq!(pixels[15], [255, 128, 0, 255]);
+    }
+
+    #[test]
+    fn encoded_blocks_decode_close_to_the_source() {
+        // Flat blocks survive almost exactly, gradients within each format's precision.
+        let flat = [[40, 120, 200, 255]; 16];
+        for &format in &ENCODABLE {
+            let decoded = decode_block(format, false, &encode_block(format, &flat));
+            assert!(max_error(format, &flat, &decoded) <= 4, "{:?}: {:?}", format, decoded[0]);
+
+            let block = gradient();
+            let encoded = encode_block(format, &block);
+            assert_eq!(encoded.len(), format.block_size());
+            let error = max_error(format, &block, &decode_block(format, false, &encoded));
+            assert!(error <= tolerance(format), "{:?} is off by {}", format, error);
+        }
+    }
+
+    #[test]
+    fn bc1_keeps_transparent_texels() {
+        let mut block = gradient();
+        for pixel in block.iter_mut().step_by(3) {
+            pixel[3] = 0;
+        }
+        let decoded = decode_block(BlockFormat::Bc1, false, &encode_block(BlockFormat::Bc1, &block));
+        for (source, decoded) in block.iter().zip(decoded.iter()) {
+            assert_eq!(source[3] == 0, decoded[3] == 0);
+        }
+    }
+
+    #[test]
+    fn images_round_trip_with_partial_blocks() {
+        let (width, height) = (6, 5);
+        let pixels: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                let t = (i % width + i / width) as u8 * 8;
+                vec![t, 200 - t, 100 + t / 2, 255 - t]
+            })
+            .collect();
+        for &format in &ENCODABLE {
+            let encoded = encode_image(format, width, height, &pixels);
+            assert_eq!(encoded.len(), format.level_size(width, height));
+            let decoded = decode_image(format, false, width, height, &encoded);
+            assert_eq!(decoded.len(), pixels.len());
+
+            let as_texels = |bytes: &[u8]| -> Vec<[u8; 4]> {
+                bytes.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect()
+            };
+            let error = max_error(format, &as_texels(&pixels), &as_texels(&decoded));
+            assert!(error <= tolerance(format), "{:?} is off by {}", format, error);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Transform, Vector3, Zero};
+
+use super::ModelData;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Aabb> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Aabb { min: first, max: first }, |aabb, point| Aabb {
+            min: Point3::new(aabb.min.x.min(point.x), aabb.min.y.min(point.y), aabb.min.z.min(point.z)),
+            max: Point3::new(aabb.max.x.max(point.x), aabb.max.y.max(point.y), aabb.max.z.max(point.z)),
+        }))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x
+            && self.min.y <= other.max.y && self.max.y >= other.min.y
+            && self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        let center = matrix.transform_point(self.center());
+        let extents = self.extents();
+        let linear = Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate());
+        let world_extents = Vector3::new(
+            linear.x.x.abs() * extents.x + linear.y.x.abs() * extents.y + linear.z.x.abs() * extents.z,
+            linear.x.y.abs() * extents.x + linear.y.y.abs() * extents.y + linear.z.y.abs() * extents.z,
+            linear.x.z.abs() * extents.x + linear.y.z.abs() * extents.y + linear.z.z.abs() * extents.z,
+        );
+        Aabb {
+            min: center - world_extents,
+            max: center + world_extents,
+        }
+    }
+}
+
+impl BoundingSphere {
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        (point - self.center).magnitude2() <= self.radius * self.radius
+    }
+
+    pub fn intersects(&self, other: &BoundingSphere) -> bool {
+        let radius = self.radius + other.radius;
+        (other.center - self.center).magnitude2() <= radius * radius
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> BoundingSphere {
+        let scale = [matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate()]
+            .iter()
+            .map(|axis| axis.magnitude())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl Bounds {
+    pub fn empty() -> Bounds {
+        Bounds {
+            aabb: Aabb { min: Point3::origin(), max: Point3::origin() },
+            sphere: BoundingSphere { center: Point3::origin(), radius: 0.0 },
+        }
+    }
+
+    pub fn from_points(points: &[Point3<f32>]) -> Bounds {
+        let aabb = match Aabb::from_points(points.iter().cloned()) {
+            Some(aabb) => aabb,
+            None => return Bounds::empty(),
+        };
+        let center = aabb.center();
+        let radius = points.iter()
+            .map(|&point| (point - center).magnitude())
+            .fold(0.0, f32::max);
+        Bounds {
+            aabb,
+            sphere: BoundingSphere { center, radius },
+        }
+    }
+
+    // Both shapes are conservative, so bounds that fail either test can't overlap.
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.sphere.intersects(&other.sphere) && self.aabb.intersects(&other.aabb)
+    }
+
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transform(matrix),
+            sphere: self.sphere.transform(matrix),
+        }
+    }
+}
+
+impl ModelData {
+    pub fn compute_bounds(&self) -> Bounds {
+        let mut points: Vec<Point3<f32>> = self.vertices.iter()
+            .map(|vertex| Point3::from_vec(vertex.position))
+            .collect();
+        // Any mix of targets can be active at once. With weights between 0 and 1, a vertex stays
+        // in the box between the sums of its negative and positive deltas, so its corners are added.
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let (mut low, mut high) = (Vector3::zero(), Vector3::zero());
+            for delta in self.morph_targets.iter().filter_map(|target| target.position_deltas.get(i)) {
+                low += delta.map(|c| c.min(0.0));
+                high += delta.map(|c| c.max(0.0));
+            }
+            if low == Vector3::zero() && high == Vector3::zero() {
+                continue;
+            }
+            for corner in 0..8 {
+                let pick = |bit: usize, low: f32, high: f32| if corner & bit == 0 { low } else { high };
+                let offset = Vector3::new(pick(1, low.x, high.x), pick(2, low.y, high.y), pick(4, low.z, high.z));
+                points.push(Point3::from_vec(vertex.position + offset));
+            }
+        }
+        Bounds::from_points(&points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::{primitives, MorphTarget};
+
+    #[test]
+    fn bounds_cover_morph_targets_applied_together() {
+        let mut model = primitives::plane(2.0, 2.0, 1, 1).unwrap();
+        let target = |delta: Vector3<f32>| MorphTarget {
+            position_deltas: vec![delta; 4],
+            normal_deltas: vec![Vector3::zero(); 4],
+        };
+        model.morph_targets = vec![
+            target(Vector3::new(0.0, 0.0, 1.0)),
+            target(Vector3::new(0.0, 0.0, 2.0)),
+            target(Vector3::new(0.5, 0.0, -1.0)),
+        ];
+
+        let bounds = model.compute_bounds();
+        assert_eq!(bounds.aabb.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bounds.aabb.max, Point3::new(1.5, 1.0, 3.0));
+        // The first two targets at full weight, the third at none.
+        for vertex in &model.vertices {
+            let point = Point3::from_vec(vertex.position + Vector3::new(0.0, 0.0, 3.0));
+            assert!((point - bounds.sphere.center).magnitude() <= bounds.sphere.radius);
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use cgmath::{Matrix4, Point2, Rad, Vector3};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{LightKind, Material, ModelData, MorphTarget, Projection, SceneCamera, SceneLight, Texture, TextureTransform, Vertex, VertexLayout};
+use super::layout::{self, Attribute};
+
+const MAGIC: &[u8; 8] = b"VOIDMESH";
+const VERSION: u32 = 9;
+const HEADER_SIZE: usize = 8 + 4 + 8 + 4;
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error(display = "could not access mesh cache")]
+    Io(#[error(cause)] std::io::Error),
+    #[error(display = "mesh cache has an invalid header")]
+    InvalidHeader,
+    #[error(display = "mesh cache version {} is not supported", version)]
+    UnsupportedVersion {
+        version: u32,
+    },
+    #[error(display = "mesh cache checksum mismatch")]
+    ChecksumMismatch,
+    #[error(display = "mesh cache is truncated")]
+    Truncated,
+    #[error(display = "mesh cache contains invalid data")]
+    InvalidData,
+    #[error(display = "model has skins or animations, which the mesh cache doesn't store")]
+    Unsupported,
+}
+
+impl From<std::io::Error> for CacheError {
+    fn from(err: std::io::Error) -> CacheError {
+        CacheError::Io(err)
+    }
+}
+
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut file_name = source.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    file_name.push(".meshcache");
+    source.with_file_name(file_name)
+}
+
+pub fn is_fresh(source: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(source), modified(&cache_path(source))) {
+        (Ok(source), Ok(cache)) => cache >= source,
+        _ => false,
+    }
+}
+
+pub fn read(source: &Path) -> Result<ModelData, CacheError> {
+    decode(&fs::read(cache_path(source))?)
+}
+
+pub fn write(source: &Path, model: &ModelData) -> Result<(), CacheError> {
+    fs::write(cache_path(source), encode(model)?)?;
+    Ok(())
+}
+
+pub fn encode(model: &ModelData) -> Result<Vec<u8>, CacheError> {
+    if model.skin.is_some() || !model.animations.is_empty() {
+        return Err(CacheError::Unsupported);
+    }
+
+    let mut payload = Writer(Vec::new());
+    payload.string(&model.material.name);
+    payload.f32s(&model.material.base_color);
+    payload.f32s(&model.material.emissive);
+    payload.u32(model.material.unlit as u32);
+    payload.texture_transform(&model.material.base_color_transform);
+    payload.texture_transform(&model.material.occlusion_transform);
+    payload.texture_transform(&model.material.normal_transform);
+    payload.f32s(&[model.material.normal_scale]);
+
+    payload.u32(model.layout.attributes().len() as u32);
+    for attribute in model.layout.attributes() {
+        let index = Attribute::ALL.iter().position(|&existing| existing == attribute.attribute).unwrap();
+        payload.u32(index as u32);
+        payload.u32(attribute.format as u32);
+        payload.u32(attribute.location);
+    }
+    payload.u32(model.vertices.len() as u32);
+    for vertex in &model.vertices {
+        payload.vector3(vertex.position);
+        payload.vector3(vertex.normal);
+        payload.f32s(&[vertex.tex_coord.x, vertex.tex_coord.y]);
+        for &joint in &vertex.joints {
+            payload.u16(joint);
+        }
+        payload.f32s(&vertex.weights);
+        payload.f32s(&vertex.color);
+        payload.f32s(&[vertex.tex_coord1.x, vertex.tex_coord1.y]);
+        payload.f32s(&vertex.tangent);
+    }
+
+    payload.u32(model.indices.len() as u32);
+    for &index in &model.indices {
+        payload.u16(index);
+    }
+
+    payload.texture(&model.texture);
+    match &model.occlusion {
+        Some(occlusion) => {
+            payload.u32(1);
+            payload.texture(occlusion);
+        }
+        None => payload.u32(0),
+    }
+    match &model.normal_map {
+        Some(normal_map) => {
+            payload.u32(1);
+            payload.texture(normal_map);
+        }
+        None => payload.u32(0),
+    }
+    match &model.lightmap {
+        Some(lightmap) => {
+            payload.u32(1);
+            payload.texture(lightmap);
+        }
+        None => payload.u32(0),
+    }
+
+    payload.u32(model.morph_targets.len() as u32);
+    for target in &model.morph_targets {
+        for (&position, &normal) in target.position_deltas.iter().zip(target.normal_deltas.iter()) {
+            payload.vector3(position);
+            payload.vector3(normal);
+        }
+    }
+    payload.u32(model.default_weights.len() as u32);
+    payload.f32s(&model.default_weights);
+    match model.mesh_node {
+        Some(node) => {
+            payload.u32(1);
+            payload.u32(node as u32);
+        }
+        None => payload.u32(0),
+    }
+
+    payload.u32(model.cameras.len() as u32);
+    for camera in &model.cameras {
+        payload.string(&camera.name);
+        match camera.projection {
+            Projection::Perspective { yfov, aspect_ratio, znear, zfar } => {
+                payload.u32(0);
+                payload.f32s(&[yfov.0]);
+                payload.option(aspect_ratio);
+                payload.f32s(&[znear]);
+                payload.option(zfar);
+            }
+            Projection::Orthographic { xmag, ymag, znear, zfar } => {
+                payload.u32(1);
+                payload.f32s(&[xmag, ymag, znear, zfar]);
+            }
+        }
+        payload.matrix4(&camera.transform);
+    }
+
+    payload.u32(model.lights.len() as u32);
+    for light in &model.lights {
+        payload.string(&light.name);
+        match light.kind {
+            LightKind::Directional => payload.u32(0),
+            LightKind::Point => payload.u32(1),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                payload.u32(2);
+                payload.f32s(&[inner_cone_angle.0, outer_cone_angle.0]);
+            }
+        }
+        payload.f32s(&light.color);
+        payload.f32s(&[light.intensity]);
+        payload.option(light.range);
+        payload.matrix4(&light.transform);
+    }
+
+    let payload = payload.0;
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<ModelData, CacheError> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..8] != MAGIC {
+        return Err(CacheError::InvalidHeader);
+    }
+    let mut header = Reader(&bytes[8..HEADER_SIZE]);
+    let version = header.u32()?;
+    if version != VERSION {
+        return Err(CacheError::UnsupportedVersion { version });
+    }
+    let payload_len = header.u64()?;
+    let checksum = header.u32()?;
+
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() as u64 != payload_len {
+        return Err(CacheError::Truncated);
+    }
+    if crc32(payload) != checksum {
+        return Err(CacheError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader(payload);
+    let name = reader.string()?;
+    let mut material = Material::new(name, reader.f32_array()?);
+    material.emissive = [reader.f32()?, reader.f32()?, reader.f32()?];
+    material.unlit = match reader.u32()? {
+        0 => false,
+        1 => true,
+        _ => return Err(CacheError::InvalidData),
+    };
+    material.base_color_transform = reader.texture_transform()?;
+    material.occlusion_transform = reader.texture_transform()?;
+    material.normal_transform = reader.texture_transform()?;
+    material.normal_scale = reader.f32()?;
+
+    let attribute_count = reader.count(3 * 4)?;
+    let mut attributes = Vec::with_capacity(attribute_count);
+    for _ in 0..attribute_count {
+        let attribute = Attribute::ALL.get(reader.u32()? as usize).cloned();
+        let format = layout::format_from_id(reader.u32()?);
+        match (attribute, format) {
+            (Some(attribute), Some(format)) => attributes.push((attribute, format, reader.u32()?)),
+            _ => return Err(CacheError::InvalidData),
+        }
+    }
+    let layout = VertexLayout::new(&attributes).map_err(|_| CacheError::InvalidData)?;
+
+    let vertex_count = reader.count(4 * 22 + 2 * 4)?;
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for _ in 0..vertex_count {
+        let position = reader.vector3()?;
+        let normal = reader.vector3()?;
+        let tex_coord = Point2::new(reader.f32()?, reader.f32()?);
+        let joints = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+        let weights = reader.f32_array()?;
+        let color = reader.f32_array()?;
+        let tex_coord1 = Point2::new(reader.f32()?, reader.f32()?);
+        let tangent = reader.f32_array()?;
+        vertices.push(Vertex { position, normal, tex_coord, joints, weights, color, tex_coord1, tangent });
+    }
+
+    let index_count = reader.count(2)?;
+    let indices = (0..index_count).map(|_| reader.u16()).collect::<Result<Vec<_>, _>>()?;
+    if indices.len() % 3 != 0 || indices.iter().any(|&index| index as usize >= vertices.len()) {
+        return Err(CacheError::InvalidData);
+    }
+
+    let texture = reader.texture()?;
+    let occlusion = match reader.u32()? {
+        0 => None,
+        1 => Some(reader.texture()?),
+        _ => return Err(CacheError::InvalidData),
+    };
+    let normal_map = match reader.u32()? {
+        0 => None,
+        1 => Some(reader.texture()?),
+        _ => return Err(CacheError::InvalidData),
+    };
+    let lightmap = match reader.u32()? {
+        0 => None,
+        1 => Some(reader.texture()?),
+        _ => return Err(CacheError::InvalidData),
+    };
+
+    let target_count = reader.count(vertex_count * 4 * 6)?;
+    let mut morph_targets = Vec::with_capacity(target_count);
+    for _ in 0..target_count {
+        let mut target = MorphTarget {
+            position_deltas: Vec::with_capacity(vertex_count),
+            normal_deltas: Vec::with_capacity(vertex_count),
+        };
+        for _ in 0..vertex_count {
+            target.position_deltas.push(reader.vector3()?);
+            target.normal_deltas.push(reader.vector3()?);
+        }
+        morph_targets.push(target);
+    }
+    let weight_count = reader.count(4)?;
+    let default_weights = (0..weight_count).map(|_| reader.f32()).collect::<Result<Vec<_>, _>>()?;
+    let mesh_node = match reader.u32()? {
+        0 => None,
+        1 => Some(reader.u32()? as usize),
+        _ => return Err(CacheError::InvalidData),
+    };
+
+    let camera_count = reader.count(4 + 4 + 4 * 4 + 16 * 4)?;
+    let mut cameras = Vec::with_capacity(camera_count);
+    for _ in 0..camera_count {
+        let name = reader.string()?;
+        let projection = match reader.u32()? {
+            0 => Projection::Perspective {
+                yfov: Rad(reader.f32()?),
+                aspect_ratio: reader.option()?,
+                znear: reader.f32()?,
+                zfar: reader.option()?,
+            },
+            1 => Projection::Orthographic {
+                xmag: reader.f32()?,
+                ymag: reader.f32()?,
+                znear: reader.f32()?,
+                zfar: reader.f32()?,
+            },
+            _ => return Err(CacheError::InvalidData),
+        };
+        cameras.push(SceneCamera { name, projection, transform: reader.matrix4()? });
+    }
+
+    let light_count = reader.count(4 + 4 + 3 * 4 + 4 + 4 + 16 * 4)?;
+    let mut lights = Vec::with_capacity(light_count);
+    for _ in 0..light_count {
+        let name = reader.string()?;
+        let kind = match reader.u32()? {
+            0 => LightKind::Directional,
+            1 => LightKind::Point,
+            2 => LightKind::Spot {
+                inner_cone_angle: Rad(reader.f32()?),
+                outer_cone_angle: Rad(reader.f32()?),
+            },
+            _ => return Err(CacheError::InvalidData),
+        };
+        lights.push(SceneLight {
+            name,
+            kind,
+            color: [reader.f32()?, reader.f32()?, reader.f32()?],
+            intensity: reader.f32()?,
+            range: reader.option()?,
+            transform: reader.matrix4()?,
+        });
+    }
+
+    if !reader.0.is_empty() {
+        return Err(CacheError::InvalidData);
+    }
+
+    let mut model = ModelData::new(
+        vertices,
+        indices,
+        material,
+        texture,
+    );
+    model.layout = layout;
+    model.occlusion = occlusion;
+    model.normal_map = normal_map;
+    model.lightmap = lightmap;
+    model.morph_targets = morph_targets;
+    model.default_weights = default_weights;
+    model.mesh_node = mesh_node;
+    model.cameras = cameras;
+    model.lights = lights;
+    model.bounds = model.compute_bounds();
+    Ok(model)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.u32(value.to_bits());
+        }
+    }
+
+    fn vector3(&mut self, value: Vector3<f32>) {
+        self.f32s(&[value.x, value.y, value.z]);
+    }
+
+    fn option(&mut self, value: Option<f32>) {
+        match value {
+            Some(value) => {
+                self.u32(1);
+                self.f32s(&[value]);
+            }
+            None => self.u32(0),
+        }
+    }
+
+    fn matrix4(&mut self, matrix: &Matrix4<f32>) {
+        let columns: &[[f32; 4]; 4] = matrix.as_ref();
+        for column in columns {
+            self.f32s(column);
+        }
+    }
+
+    fn texture_transform(&mut self, transform: &TextureTransform) {
+        self.f32s(&transform.offset);
+        self.f32s(&[transform.rotation]);
+        self.f32s(&transform.scale);
+    }
+
+    fn texture(&mut self, texture: &Texture) {
+        self.u32(texture.width);
+        self.u32(texture.height);
+        self.bytes(&texture.pixels);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CacheError> {
+        if self.0.len() < len {
+            return Err(CacheError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, CacheError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheError> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    fn f32(&mut self) -> Result<f32, CacheError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f32_array(&mut self) -> Result<[f32; 4], CacheError> {
+        Ok([self.f32()?, self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    fn texture_transform(&mut self) -> Result<TextureTransform, CacheError> {
+        Ok(TextureTransform {
+            offset: [self.f32()?, self.f32()?],
+            rotation: self.f32()?,
+            scale: [self.f32()?, self.f32()?],
+        })
+    }
+
+    fn vector3(&mut self) -> Result<Vector3<f32>, CacheError> {
+        Ok(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn option(&mut self) -> Result<Option<f32>, CacheError> {
+        match self.u32()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.f32()?)),
+            _ => Err(CacheError::InvalidData),
+        }
+    }
+
+    fn matrix4(&mut self) -> Result<Matrix4<f32>, CacheError> {
+        Ok(Matrix4::from_cols(
+            self.f32_array()?.into(),
+            self.f32_array()?.into(),
+            self.f32_array()?.into(),
+            self.f32_array()?.into(),
+        ))
+    }
+
+    fn count(&mut self, element_size: usize) -> Result<usize, CacheError> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(element_size) > self.0.len() {
+            return Err(CacheError::Truncated);
+        }
+        Ok(count)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CacheError> {
+        let len = self.count(1)?;
+        self.take(len)
+    }
+
+    fn texture(&mut self) -> Result<Texture, CacheError> {
+        let width = self.u32()?;
+        let height = self.u32()?;
+        let pixels = self.bytes()?.to_vec();
+        if pixels.len() as u64 != width as u64 * height as u64 * 4 {
+            return Err(CacheError::InvalidData);
+        }
+        Ok(Texture { pixels, width, height })
+    }
+
+    fn string(&mut self) -> Result<String, CacheError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| CacheError::InvalidData)
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+
+    !bytes.iter().fold(!0u32, |crc, &byte| table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::primitives;
+
+    // A primitive with every optional part the cache stores.
+    fn detailed_model() -> ModelData {
+        let mut model = primitives::torus(0.5, 0.2, 12, 6).unwrap();
+        let count = model.vertices.len();
+        model.morph_targets.push(MorphTarget {
+            position_deltas: (0..count).map(|i| Vector3::new(0.0, 0.0, i as f32 * 0.01)).collect(),
+            normal_deltas: vec![Vector3::new(0.0, 0.0, 0.0); count],
+        });
+        model.default_weights = vec![0.25];
+        model.bounds = model.compute_bounds();
+        model.mesh_node = Some(2);
+        model.occlusion = Some(Texture::solid([0.5, 0.5, 0.5, 1.0]));
+        model.lightmap = Some(Texture::solid([0.1, 0.2, 0.3, 1.0]));
+        model.material.emissive = [1.0, 0.5, 0.0];
+        model.material.unlit = true;
+        model.cameras.push(SceneCamera {
+            name: "camera".to_string(),
+            projection: Projection::Perspective { yfov: Rad(0.8), aspect_ratio: None, znear: 0.1, zfar: Some(50.0) },
+            transform: Matrix4::from_translation(Vector3::new(0.0, -4.0, 1.0)),
+        });
+        model.lights.push(SceneLight {
+            name: "spot".to_string(),
+            kind: LightKind::Spot { inner_cone_angle: Rad(0.2), outer_cone_angle: Rad(0.4) },
+            color: [1.0, 0.9, 0.8],
+            intensity: 20.0,
+            range: Some(10.0),
+            transform: Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)),
+        });
+        model
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let normal_mapped = ModelData::load_uncached("assets/corpus/normal-map.gltf").unwrap();
+        for model in &[detailed_model(), normal_mapped] {
+            let decoded = decode(&encode(model).unwrap()).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", model));
+        }
+    }
+
+    #[test]
+    fn rejects_skinned_models() {
+        let skinned = ModelData::load_uncached("assets/corpus/skinned-animated.gltf").unwrap();
+        match encode(&skinned) {
+            Err(CacheError::Unsupported) => (),
+            other => panic!("expected Unsupported, got {:?}", other.map(|bytes| bytes.len())),
+        }
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut bytes = encode(&detailed_model()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        match decode(&bytes) {
+            Err(CacheError::ChecksumMismatch) => (),
+            other => panic!("expected ChecksumMismatch, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = encode(&detailed_model()).unwrap();
+        match decode(&bytes[..bytes.len() - 10]) {
+            Err(CacheError::Truncated) => (),
+            other => panic!("expected Truncated, got {:?}", other.map(|_| ())),
+        }
+        match decode(&bytes[..HEADER_SIZE - 1]) {
+            Err(CacheError::InvalidHeader) => (),
+            other => panic!("expected InvalidHeader, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = encode(&detailed_model()).unwrap();
+        bytes[8..12].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        match decode(&bytes) {
+            Err(CacheError::UnsupportedVersion { version }) => assert_eq!(version, VERSION - 1),
+            other => panic!("expected UnsupportedVersion, got {:?}", other.map(|_| ())),
+        }
+    }
+}
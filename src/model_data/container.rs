@@ -0,0 +1,406 @@
+use std::path::Path;
+
+use super::bc::{self, BlockFormat};
+use super::{load_png, ModelLoadError, Texture, MAX_TEXTURE_SIZE};
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DDS_HEADER_SIZE: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_INDEX_SIZE: usize = 24;
+
+// Block-compressed texture as stored in a DDS or KTX2 container, base level first. wgpu 0.2 has
+// no BC texture formats, so these are always expanded to RGBA8 with the CPU decoder before upload
+// and take as much video memory as the same texture loaded from a PNG. The containers only make
+// files smaller.
+#[derive(Debug, Clone)]
+pub struct CompressedTexture {
+    pub format: BlockFormat,
+    pub srgb: bool,
+    pub signed: bool,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedTexture {
+    pub fn load(path: &Path) -> Result<CompressedTexture, ModelLoadError> {
+        let bytes = ::std::fs::read(path)?;
+        match extension(path).as_ref().map(String::as_str) {
+            Some("dds") => CompressedTexture::from_dds(&bytes),
+            Some("ktx2") => CompressedTexture::from_ktx2(&bytes),
+            _ => Err(ModelLoadError::UnsupportedImageFormat { mime_type: path.display().to_string() }),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ModelLoadError> {
+        let bytes = match extension(path).as_ref().map(String::as_str) {
+            Some("dds") => self.to_dds(),
+            Some("ktx2") => self.to_ktx2(),
+            _ => return Err(ModelLoadError::UnsupportedImageFormat { mime_type: path.display().to_string() }),
+        };
+        ::std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    pub fn encode(texture: &Texture, format: BlockFormat, srgb: bool) -> Result<CompressedTexture, ModelLoadError> {
+        if !format.can_encode() {
+            return Err(ModelLoadError::UnsupportedTextureFormat { format: format!("{:?}", format) });
+        }
+        Ok(CompressedTexture {
+            format,
+            srgb,
+            signed: false,
+            width: texture.width,
+            height: texture.height,
+            levels: vec![bc::encode_image(format, texture.width, texture.height, &texture.pixels)],
+        })
+    }
+
+    // Only the base level is decoded, the renderer can't upload the others.
+    pub fn decode(&self) -> Texture {
+        match self.levels.first() {
+            Some(data) => Texture {
+                pixels: bc::decode_image(self.format, self.signed, self.width, self.height, data),
+                width: self.width,
+                height: self.height,
+            },
+            None => Texture::solid([0.0, 0.0, 0.0, 0.0]),
+        }
+    }
+
+    pub fn level_extent(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    fn level_size(&self, level: usize) -> usize {
+        let (width, height) = self.level_extent(level);
+        self.format.level_size(width, height)
+    }
+
+    fn validate(self) -> Result<CompressedTexture, ModelLoadError> {
+        if self.levels.is_empty() {
+            return Err(invalid("texture has no pixels"));
+        }
+        if self.levels.len() > 32 - self.width.max(self.height).leading_zeros() as usize {
+            return Err(invalid("texture has more mip levels than its size allows"));
+        }
+        Ok(self)
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<CompressedTexture, ModelLoadError> {
+        if bytes.len() < DDS_HEADER_SIZE || &bytes[0..4] != DDS_MAGIC || read_u32(bytes, 4) != 124 {
+            return Err(invalid("missing DDS header"));
+        }
+        let height = read_u32(bytes, 12);
+        let width = read_u32(bytes, 16);
+        check_extent(width, height)?;
+        if read_u32(bytes, 8) & 0x80_0000 != 0 && read_u32(bytes, 24) > 1 {
+            return Err(ModelLoadError::UnsupportedTextureFormat { format: "3D DDS texture".into() });
+        }
+        let level_count = read_u32(bytes, 28).max(1) as usize;
+        if read_u32(bytes, 80) & 0x4 == 0 {
+            return Err(ModelLoadError::UnsupportedTextureFormat { format: "uncompressed DDS".into() });
+        }
+
+        let four_cc = &bytes[84..88];
+        let (format, srgb, signed, mut offset) = match four_cc {
+            b"DXT1" => (BlockFormat::Bc1, false, false, DDS_HEADER_SIZE),
+            b"DXT2" | b"DXT3" => (BlockFormat::Bc2, false, false, DDS_HEADER_SIZE),
+            b"DXT4" | b"DXT5" => (BlockFormat::Bc3, false, false, DDS_HEADER_SIZE),
+            b"ATI1" | b"BC4U" => (BlockFormat::Bc4, false, false, DDS_HEADER_SIZE),
+            b"BC4S" => (BlockFormat::Bc4, false, true, DDS_HEADER_SIZE),
+            b"ATI2" | b"BC5U" => (BlockFormat::Bc5, false, false, DDS_HEADER_SIZE),
+            b"BC5S" => (BlockFormat::Bc5, false, true, DDS_HEADER_SIZE),
+            b"DX10" => {
+                if bytes.len() < DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE {
+                    return Err(invalid("truncated DDS DX10 header"));
+                }
+                let dxgi_format = read_u32(bytes, DDS_HEADER_SIZE);
+                let (format, srgb, signed) = from_dxgi(dxgi_format).ok_or_else(|| {
+                    ModelLoadError::UnsupportedTextureFormat { format: format!("DXGI format {}", dxgi_format) }
+                })?;
+                (format, srgb, signed, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)
+            }
+            _ => {
+                return Err(ModelLoadError::UnsupportedTextureFormat {
+                    format: format!("DDS four CC {}", String::from_utf8_lossy(four_cc)),
+                });
+            }
+        };
+
+        let mut texture = CompressedTexture { format, srgb, signed, width, height, levels: Vec::new() };
+        for level in 0..level_count.min(32) {
+            let size = texture.level_size(level);
+            let data = bytes.get(offset..offset + size).ok_or_else(|| invalid("truncated DDS mip level"))?;
+            texture.levels.push(data.to_vec());
+            offset += size;
+        }
+        texture.validate()
+    }
+
+    pub fn to_dds(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(DDS_MAGIC);
+        let mipmapped = self.levels.len() > 1;
+        let header = [
+            124,
+            0x1 | 0x2 | 0x4 | 0x1000 | 0x8_0000 | if mipmapped { 0x2_0000 } else { 0 },
+            self.height,
+            self.width,
+            self.level_size(0) as u32,
+            0,
+            self.levels.len() as u32,
+        ];
+        for &value in &header {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.resize(76, 0);
+        for &value in &[32, 0x4] {
+            bytes.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(b"DX10");
+        bytes.resize(108, 0);
+        let caps = 0x1000 | if mipmapped { 0x8 | 0x40_0000 } else { 0 };
+        bytes.extend_from_slice(&(caps as u32).to_le_bytes());
+        bytes.resize(DDS_HEADER_SIZE, 0);
+
+        for &value in &[to_dxgi(self.format, self.srgb, self.signed), 3, 0, 1, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for level in &self.levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<CompressedTexture, ModelLoadError> {
+        if bytes.len() < KTX2_HEADER_SIZE || bytes[0..12] != KTX2_IDENTIFIER {
+            return Err(invalid("missing KTX2 identifier"));
+        }
+        let vk_format = read_u32(bytes, 12);
+        let width = read_u32(bytes, 20);
+        let height = read_u32(bytes, 24);
+        check_extent(width, height)?;
+        let level_count = read_u32(bytes, 40).max(1) as usize;
+        let supercompression = read_u32(bytes, 44);
+        if supercompression != 0 {
+            return Err(ModelLoadError::UnsupportedTextureFormat {
+                format: format!("KTX2 supercompression scheme {}", supercompression),
+            });
+        }
+        if read_u32(bytes, 28) > 1 {
+            return Err(ModelLoadError::UnsupportedTextureFormat { format: "3D KTX2 texture".into() });
+        }
+        let (format, srgb, signed) = from_vk_format(vk_format).ok_or_else(|| {
+            ModelLoadError::UnsupportedTextureFormat { format: format!("Vulkan format {}", vk_format) }
+        })?;
+
+        let mut texture = CompressedTexture { format, srgb, signed, width, height, levels: Vec::new() };
+        for level in 0..level_count.min(32) {
+            let index = KTX2_HEADER_SIZE + level * KTX2_LEVEL_INDEX_SIZE;
+            if bytes.len() < index + KTX2_LEVEL_INDEX_SIZE {
+                return Err(invalid("truncated KTX2 level index"));
+            }
+            let offset = read_u64(bytes, index);
+            let length = read_u64(bytes, index + 8);
+            let size = texture.level_size(level) as u64;
+            if length < size {
+                return Err(invalid("KTX2 mip level is smaller than its extent"));
+            }
+            let data = offset.checked_add(size)
+                .and_then(|end| bytes.get(offset as usize..end as usize))
+                .ok_or_else(|| invalid("KTX2 mip level points outside the file"))?;
+            texture.levels.push(data.to_vec());
+        }
+        texture.validate()
+    }
+
+    pub fn to_ktx2(&self) -> Vec<u8> {
+        let dfd = self.data_format_descriptor();
+        let index_end = KTX2_HEADER_SIZE + self.levels.len() * KTX2_LEVEL_INDEX_SIZE;
+        let dfd_offset = index_end;
+
+        let alignment = self.format.block_size();
+        let mut level_offsets = vec![0; self.levels.len()];
+        let mut end = dfd_offset + dfd.len();
+        for (level, data) in self.levels.iter().enumerate().rev() {
+            end = (end + alignment - 1) / alignment * alignment;
+            level_offsets[level] = end;
+            end += data.len();
+        }
+
+        let mut bytes = Vec::with_capacity(end);
+        bytes.extend_from_slice(&KTX2_IDENTIFIER);
+        let header = [
+            to_vk_format(self.format, self.srgb, self.signed),
+            1,
+            self.width,
+            self.height,
+            0,
+            0,
+            1,
+            self.levels.len() as u32,
+            0,
+            dfd_offset as u32,
+            dfd.len() as u32,
+            0,
+            0,
+        ];
+        for &value in &header {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        for (offset, data) in level_offsets.iter().zip(self.levels.iter()) {
+            for &value in &[*offset as u64, data.len() as u64, data.len() as u64] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&dfd);
+        for (level, data) in self.levels.iter().enumerate().rev() {
+            bytes.resize(level_offsets[level], 0);
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn data_format_descriptor(&self) -> Vec<u8> {
+        // (channel id, bit offset, bit length) of each sample in a block.
+        let (color_model, samples): (u8, &[(u8, u16, u8)]) = match self.format {
+            BlockFormat::Bc1 => (128, &[(1, 0, 64)]),
+            BlockFormat::Bc2 => (129, &[(15, 0, 64), (0, 64, 64)]),
+            BlockFormat::Bc3 => (130, &[(15, 0, 64), (0, 64, 64)]),
+            BlockFormat::Bc4 => (131, &[(0, 0, 64)]),
+            BlockFormat::Bc5 => (132, &[(0, 0, 64), (1, 64, 64)]),
+            BlockFormat::Bc6h => (133, &[(0, 0, 128)]),
+            BlockFormat::Bc7 => (134, &[(0, 0, 128)]),
+        };
+        let block_size = 24 + 16 * samples.len();
+
+        let mut dfd = Vec::with_capacity(4 + block_size);
+        dfd.extend_from_slice(&((4 + block_size) as u32).to_le_bytes());
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&2u16.to_le_bytes());
+        dfd.extend_from_slice(&(block_size as u16).to_le_bytes());
+        dfd.extend_from_slice(&[color_model, 1, if self.srgb { 2 } else { 1 }, 0]);
+        dfd.extend_from_slice(&[3, 3, 0, 0]);
+        dfd.extend_from_slice(&[self.format.block_size() as u8, 0, 0, 0, 0, 0, 0, 0]);
+        for &(channel, bit_offset, bit_length) in samples {
+            let qualifiers = if self.signed { 0x40 } else { 0 };
+            let (lower, upper): (u32, u32) = if self.signed { (0x8000_0001, 0x7FFF_FFFF) } else { (0, 0xFFFF_FFFF) };
+            dfd.extend_from_slice(&bit_offset.to_le_bytes());
+            dfd.extend_from_slice(&[bit_length - 1, channel | qualifiers, 0, 0, 0, 0]);
+            dfd.extend_from_slice(&lower.to_le_bytes());
+            dfd.extend_from_slice(&upper.to_le_bytes());
+        }
+        dfd
+    }
+}
+
+// Only the base level is written. Mip chains wait on a wgpu version that can upload them.
+pub fn convert_png(input: &Path, output: &Path, format: BlockFormat, srgb: bool) -> Result<CompressedTexture, ModelLoadError> {
+    let texture = load_png(&::std::fs::read(input)?)?;
+    let compressed = CompressedTexture::encode(&texture, format, srgb)?;
+    compressed.save(output)?;
+    Ok(compressed)
+}
+
+// Checked before any level sizes are computed from the extent, which would overflow for sizes
+// read from a broken header.
+fn check_extent(width: u32, height: u32) -> Result<(), ModelLoadError> {
+    if width == 0 || height == 0 {
+        return Err(invalid("texture has no pixels"));
+    }
+    if width > MAX_TEXTURE_SIZE || height > MAX_TEXTURE_SIZE {
+        return Err(ModelLoadError::ImageTooLarge { width, height });
+    }
+    Ok(())
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+}
+
+fn invalid(reason: &'static str) -> ModelLoadError {
+    ModelLoadError::InvalidTextureContainer { reason }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+// (format, sRGB, signed) for each supported DXGI_FORMAT value.
+const DXGI_FORMATS: [(u32, BlockFormat, bool, bool); 14] = [
+    (71, BlockFormat::Bc1, false, false),
+    (72, BlockFormat::Bc1, true, false),
+    (74, BlockFormat::Bc2, false, false),
+    (75, BlockFormat::Bc2, true, false),
+    (77, BlockFormat::Bc3, false, false),
+    (78, BlockFormat::Bc3, true, false),
+    (80, BlockFormat::Bc4, false, false),
+    (81, BlockFormat::Bc4, false, true),
+    (83, BlockFormat::Bc5, false, false),
+    (84, BlockFormat::Bc5, false, true),
+    (95, BlockFormat::Bc6h, false, false),
+    (96, BlockFormat::Bc6h, false, true),
+    (98, BlockFormat::Bc7, false, false),
+    (99, BlockFormat::Bc7, true, false),
+];
+
+// The same for VkFormat values; BC1 appears twice since the RGB and RGBA variants decode alike.
+const VK_FORMATS: [(u32, BlockFormat, bool, bool); 16] = [
+    (133, BlockFormat::Bc1, false, false),
+    (134, BlockFormat::Bc1, true, false),
+    (131, BlockFormat::Bc1, false, false),
+    (132, BlockFormat::Bc1, true, false),
+    (135, BlockFormat::Bc2, false, false),
+    (136, BlockFormat::Bc2, true, false),
+    (137, BlockFormat::Bc3, false, false),
+    (138, BlockFormat::Bc3, true, false),
+    (139, BlockFormat::Bc4, false, false),
+    (140, BlockFormat::Bc4, false, true),
+    (141, BlockFormat::Bc5, false, false),
+    (142, BlockFormat::Bc5, false, true),
+    (143, BlockFormat::Bc6h, false, false),
+    (144, BlockFormat::Bc6h, false, true),
+    (145, BlockFormat::Bc7, false, false),
+    (146, BlockFormat::Bc7, true, false),
+];
+
+fn lookup(table: &[(u32, BlockFormat, bool, bool)], value: u32) -> Option<(BlockFormat, bool, bool)> {
+    table.iter()
+        .find(|entry| entry.0 == value)
+        .map(|&(_, format, srgb, signed)| (format, srgb, signed))
+}
+
+fn reverse_lookup(table: &[(u32, BlockFormat, bool, bool)], format: BlockFormat, srgb: bool, signed: bool) -> u32 {
+    table.iter()
+        .find(|entry| entry.1 == format && entry.2 == srgb && entry.3 == signed)
+        .or_else(|| table.iter().find(|entry| entry.1 == format))
+        .map(|entry| entry.0)
+        .unwrap_or(0)
+}
+
+fn from_dxgi(value: u32) -> Option<(BlockFormat, bool, bool)> {
+    lookup(&DXGI_FORMATS, value)
+}
+
+fn to_dxgi(format: BlockFormat, srgb: bool, signed: bool) -> u32 {
+    reverse_lookup(&DXGI_FORMATS, format, srgb, signed)
+}
+
+fn from_vk_format(value: u32) -> Option<(BlockFormat, bool, bool)> {
+    lookup(&VK_FORMATS, value)
+}
+
+fn to_vk_format(format: BlockFormat, srgb: bool, signed: bool) -> u32 {
+    reverse_lookup(&VK_FORMATS, format, srgb, signed)
+}
@@ -0,0 +1,595 @@
+use cgmath::{Matrix4, SquareMatrix};
+use gltf::json::Value;
+use std::fs;
+use std::path::Path;
+
+use super::extensions::{TextureTransform, EMISSIVE_STRENGTH, LIGHTS_PUNCTUAL, MATERIALS_UNLIT, TEXTURE_TRANSFORM};
+use super::{Attribute, LightKind, ModelData, Projection, Texture};
+use crate::animation::{ChannelProperty, Interpolation};
+
+const GLB_VERSION: u32 = 2;
+const JSON_CHUNK: u32 = 0x4E4F_534A;
+const BIN_CHUNK: u32 = 0x004E_4942;
+const UNSIGNED_SHORT: u32 = 5123;
+const FLOAT: u32 = 5126;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error(display = "could not write glTF file")]
+    Io(#[error(cause)] std::io::Error),
+    #[error(display = "encoding image failed")]
+    ImageEncodeFailed(#[error(cause)] png::EncodingError),
+    #[error(display = "no model data for model group {}", group)]
+    MissingModelData {
+        group: String,
+    },
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(err: std::io::Error) -> ExportError {
+        ExportError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for ExportError {
+    fn from(err: png::EncodingError) -> ExportError {
+        ExportError::ImageEncodeFailed(err)
+    }
+}
+
+// Builds a single-buffer .glb out of models, their placements and their scene cameras and lights.
+pub struct GltfExporter {
+    root: Value,
+    bin: Vec<u8>,
+    scene_nodes: Vec<usize>,
+}
+
+impl GltfExporter {
+    pub fn new() -> GltfExporter {
+        let mut root = Value::Null;
+        root["asset"]["version"] = "2.0".into();
+        root["asset"]["generator"] = "voids".into();
+        GltfExporter {
+            root,
+            bin: Vec::new(),
+            scene_nodes: Vec::new(),
+        }
+    }
+
+    // Adds the model once and places a node for each transform. Animations that target the model
+    // itself are attached to the first placement.
+    pub fn add_model(&mut self, name: &str, model: &ModelData, transforms: &[Matrix4<f32>]) -> Result<(), ExportError> {
+        let mesh = self.add_mesh(name, model)?;
+
+        let joint_nodes = match &model.skin {
+            Some(skin) => {
+                let nodes: Vec<usize> = skin.joints.iter()
+                    .map(|joint| {
+                        let rotation = joint.rest.rotation;
+                        let mut node = Value::Null;
+                        node["name"] = joint.name.as_str().into();
+                        node["translation"] = vector(AsRef::<[f32; 3]>::as_ref(&joint.rest.translation));
+                        node["rotation"] = vector(&[rotation.v.x, rotation.v.y, rotation.v.z, rotation.s]);
+                        node["scale"] = vector(AsRef::<[f32; 3]>::as_ref(&joint.rest.scale));
+                        self.push("nodes", node)
+                    })
+                    .collect();
+                for (joint, &node) in skin.joints.iter().zip(nodes.iter()) {
+                    // Non-joint ancestors are written back as a single node above the joint.
+                    let child = if joint.parent_transform == Matrix4::identity() {
+                        node
+                    } else {
+                        let mut wrapper = Value::Null;
+                        wrapper["name"] = format!("{}.parent", joint.name).into();
+                        set_matrix(&mut wrapper, &joint.parent_transform);
+                        push_array(&mut wrapper["children"], node.into());
+                        self.push("nodes", wrapper)
+                    };
+                    match joint.parent {
+                        Some(parent) => {
+                            push_array(&mut self.root["nodes"][nodes[parent]]["children"], child.into());
+                        }
+                        None => self.scene_nodes.push(child),
+                    }
+                }
+                nodes
+            }
+            None => Vec::new(),
+        };
+        let skin = match &model.skin {
+            Some(skin) => {
+                let matrices: Vec<f32> = skin.joints.iter()
+                    .flat_map(|joint| AsRef::<[f32; 16]>::as_ref(&joint.inverse_bind_matrix).to_vec())
+                    .collect();
+                let inverse_bind_matrices = self.accessor(&floats(&matrices), FLOAT, skin.joints.len(), "MAT4", None);
+                let mut json = Value::Null;
+                json["joints"] = joint_nodes.clone().into();
+                json["inverseBindMatrices"] = inverse_bind_matrices.into();
+                Some(self.push("skins", json))
+            }
+            None => None,
+        };
+
+        let mut placements = Vec::new();
+        for (i, transform) in transforms.iter().enumerate() {
+            let mut node = Value::Null;
+            node["name"] = format!("{}.{}", name, i).into();
+            node["mesh"] = mesh.into();
+            if let Some(skin) = skin {
+                node["skin"] = skin.into();
+            }
+            set_matrix(&mut node, transform);
+            let node = self.push("nodes", node);
+            self.scene_nodes.push(node);
+            placements.push(node);
+        }
+
+        // Channels can only target the model's own node or its joints.
+        let target_node = |node: usize| {
+            if model.mesh_node == Some(node) {
+                return placements.first().cloned();
+            }
+            let skin = model.skin.as_ref()?;
+            skin.joints.iter().position(|joint| joint.node == node).map(|joint| joint_nodes[joint])
+        };
+        for animation in &model.animations {
+            let mut samplers = Vec::new();
+            let mut channels = Vec::new();
+            for channel in animation.channels.iter().filter(|channel| !channel.times.is_empty()) {
+                let node = match target_node(channel.node) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                let (path, type_) = match channel.property {
+                    ChannelProperty::Translation => ("translation", "VEC3"),
+                    ChannelProperty::Rotation => ("rotation", "VEC4"),
+                    ChannelProperty::Scale => ("scale", "VEC3"),
+                    ChannelProperty::MorphWeights => ("weights", "SCALAR"),
+                };
+                let components = if channel.property == ChannelProperty::MorphWeights { 1 } else { channel.components };
+                let input = self.accessor_with_bounds(&channel.times, 1, None);
+                let output = self.accessor(&floats(&channel.values), FLOAT, channel.values.len() / components.max(1), type_, None);
+
+                let mut sampler = Value::Null;
+                sampler["input"] = input.into();
+                sampler["output"] = output.into();
+                sampler["interpolation"] = match channel.interpolation {
+                    Interpolation::Step => "STEP",
+                    Interpolation::Linear => "LINEAR",
+                    Interpolation::CubicSpline => "CUBICSPLINE",
+                }.into();
+                let mut json = Value::Null;
+                json["sampler"] = samplers.len().into();
+                json["target"]["node"] = node.into();
+                json["target"]["path"] = path.into();
+                samplers.push(sampler);
+                channels.push(json);
+            }
+            if channels.is_empty() {
+                continue;
+            }
+            let mut json = Value::Null;
+            json["name"] = animation.name.as_str().into();
+            json["samplers"] = samplers.into();
+            json["channels"] = channels.into();
+            self.push("animations", json);
+        }
+
+        self.add_cameras_and_lights(model);
+        Ok(())
+    }
+
+    fn add_mesh(&mut self, name: &str, model: &ModelData) -> Result<usize, ExportError> {
+        let vertices = &model.vertices;
+        let positions: Vec<f32> = vertices.iter().flat_map(|v| vec![v.position.x, v.position.y, v.position.z]).collect();
+        let normals: Vec<f32> = vertices.iter().flat_map(|v| vec![v.normal.x, v.normal.y, v.normal.z]).collect();
+        let tex_coords: Vec<f32> = vertices.iter().flat_map(|v| vec![v.tex_coord.x, v.tex_coord.y]).collect();
+
+        let mut attributes = Value::Null;
+        attributes["POSITION"] = self.accessor_with_bounds(&positions, 3, Some(ARRAY_BUFFER)).into();
+        attributes["NORMAL"] = self.accessor(&floats(&normals), FLOAT, vertices.len(), "VEC3", Some(ARRAY_BUFFER)).into();
+        attributes["TEXCOORD_0"] = self.accessor(&floats(&tex_coords), FLOAT, vertices.len(), "VEC2", Some(ARRAY_BUFFER)).into();
+        if model.layout.contains(Attribute::TexCoord1) {
+            let tex_coords: Vec<f32> = vertices.iter().flat_map(|v| vec![v.tex_coord1.x, v.tex_coord1.y]).collect();
+            attributes["TEXCOORD_1"] = self.accessor(&floats(&tex_coords), FLOAT, vertices.len(), "VEC2", Some(ARRAY_BUFFER)).into();
+        }
+        if model.layout.contains(Attribute::Color) {
+            let colors: Vec<f32> = vertices.iter().flat_map(|v| v.color.to_vec()).collect();
+            attributes["COLOR_0"] = self.accessor(&floats(&colors), FLOAT, vertices.len(), "VEC4", Some(ARRAY_BUFFER)).into();
+        }
+        if model.layout.contains(Attribute::Tangent) {
+            let tangents: Vec<f32> = vertices.iter().flat_map(|v| v.tangent.to_vec()).collect();
+            attributes["TANGENT"] = self.accessor(&floats(&tangents), FLOAT, vertices.len(), "VEC4", Some(ARRAY_BUFFER)).into();
+        }
+        if model.skin.is_some() {
+            let joints: Vec<u8> = vertices.iter().flat_map(|v| v.joints.iter()).flat_map(|joint| joint.to_le_bytes().to_vec()).collect();
+            let weights: Vec<f32> = vertices.iter().flat_map(|v| v.weights.to_vec()).collect();
+            attributes["JOINTS_0"] = self.accessor(&joints, UNSIGNED_SHORT, vertices.len(), "VEC4", Some(ARRAY_BUFFER)).into();
+            attributes["WEIGHTS_0"] = self.accessor(&floats(&weights), FLOAT, vertices.len(), "VEC4", Some(ARRAY_BUFFER)).into();
+        }
+
+        let indices: Vec<u8> = model.indices.iter().flat_map(|index| index.to_le_bytes().to_vec()).collect();
+        let mut primitive = Value::Null;
+        primitive["attributes"] = attributes;
+        primitive["indices"] = self.accessor(&indices, UNSIGNED_SHORT, model.indices.len(), "SCALAR", Some(ELEMENT_ARRAY_BUFFER)).into();
+        primitive["material"] = self.add_material(model)?.into();
+
+        let mut targets = Vec::new();
+        for target in &model.morph_targets {
+            let positions: Vec<f32> = target.position_deltas.iter().flat_map(|d| vec![d.x, d.y, d.z]).collect();
+            let normals: Vec<f32> = target.normal_deltas.iter().flat_map(|d| vec![d.x, d.y, d.z]).collect();
+            let mut json = Value::Null;
+            json["POSITION"] = self.accessor_with_bounds(&positions, 3, Some(ARRAY_BUFFER)).into();
+            json["NORMAL"] = self.accessor(&floats(&normals), FLOAT, target.normal_deltas.len(), "VEC3", Some(ARRAY_BUFFER)).into();
+            targets.push(json);
+        }
+
+        let mut mesh = Value::Null;
+        mesh["name"] = name.into();
+        if !targets.is_empty() {
+            primitive["targets"] = targets.into();
+            mesh["weights"] = model.default_weights.clone().into();
+        }
+        mesh["primitives"] = vec![primitive].into();
+        Ok(self.push("meshes", mesh))
+    }
+
+    fn add_material(&mut self, model: &ModelData) -> Result<usize, ExportError> {
+        let mut material = Value::Null;
+        material["name"] = model.material.name.as_str().into();
+        material["pbrMetallicRoughness"]["baseColorFactor"] = vector(&model.material.base_color);
+        material["pbrMetallicRoughness"]["baseColorTexture"]["index"] = self.add_texture(&model.texture)?.into();
+        self.add_texture_transform(&mut material["pbrMetallicRoughness"]["baseColorTexture"], &model.material.base_color_transform);
+        // The renderer has no metallic workflow, so materials are exported as dielectrics.
+        material["pbrMetallicRoughness"]["metallicFactor"] = 0.0.into();
+        if let Some(occlusion) = &model.occlusion {
+            material["occlusionTexture"]["index"] = self.add_texture(occlusion)?.into();
+            material["occlusionTexture"]["texCoord"] = 1.into();
+            self.add_texture_transform(&mut material["occlusionTexture"], &model.material.occlusion_transform);
+        }
+        if let Some(lightmap) = &model.lightmap {
+            material["extras"]["lightmapTexture"]["index"] = self.add_texture(lightmap)?.into();
+            material["extras"]["lightmapTexture"]["texCoord"] = 1.into();
+        }
+        if let Some(normal_map) = &model.normal_map {
+            material["normalTexture"]["index"] = self.add_texture(normal_map)?.into();
+            if model.material.normal_scale != 1.0 {
+                material["normalTexture"]["scale"] = model.material.normal_scale.into();
+            }
+            self.add_texture_transform(&mut material["normalTexture"], &model.material.normal_transform);
+        }
+
+        // emissiveFactor is limited to [0, 1], anything brighter goes into the strength.
+        let emissive = model.material.emissive;
+        let strength = emissive.iter().cloned().fold(1.0, f32::max);
+        if emissive.iter().any(|&channel| channel != 0.0) {
+            material["emissiveFactor"] = vector(&[emissive[0] / strength, emissive[1] / strength, emissive[2] / strength]);
+        }
+        if strength > 1.0 {
+            material["extensions"][EMISSIVE_STRENGTH]["emissiveStrength"] = strength.into();
+            self.use_extension(EMISSIVE_STRENGTH);
+        }
+        if model.material.unlit {
+            material["extensions"][MATERIALS_UNLIT] = Value::Object(Default::default());
+            self.use_extension(MATERIALS_UNLIT);
+        }
+        Ok(self.push("materials", material))
+    }
+
+    fn add_texture_transform(&mut self, texture_info: &mut Value, transform: &TextureTransform) {
+        if transform.is_identity() {
+            return;
+        }
+        let json = &mut texture_info["extensions"][TEXTURE_TRANSFORM];
+        json["offset"] = vector(&transform.offset);
+        json["rotation"] = transform.rotation.into();
+        json["scale"] = vector(&transform.scale);
+        self.use_extension(TEXTURE_TRANSFORM);
+    }
+
+    fn add_texture(&mut self, texture: &Texture) -> Result<usize, ExportError> {
+        let mut png = Vec::new();
+        {
+            use png::HasParameters;
+            let mut encoder = png::Encoder::new(&mut png, texture.width, texture.height);
+            encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(&texture.pixels)?;
+        }
+
+        let mut image = Value::Null;
+        image["bufferView"] = self.view(&png, None).into();
+        image["mimeType"] = "image/png".into();
+        let image = self.push("images", image);
+        let mut json = Value::Null;
+        json["source"] = image.into();
+        Ok(self.push("textures", json))
+    }
+
+    fn add_cameras_and_lights(&mut self, model: &ModelData) {
+        for camera in &model.cameras {
+            let mut json = Value::Null;
+            json["name"] = camera.name.as_str().into();
+            match camera.projection {
+                Projection::Perspective { yfov, aspect_ratio, znear, zfar } => {
+                    json["type"] = "perspective".into();
+                    json["perspective"]["yfov"] = yfov.0.into();
+                    json["perspective"]["znear"] = znear.into();
+                    if let Some(aspect_ratio) = aspect_ratio {
+                        json["perspective"]["aspectRatio"] = aspect_ratio.into();
+                    }
+                    if let Some(zfar) = zfar {
+                        json["perspective"]["zfar"] = zfar.into();
+                    }
+                }
+                Projection::Orthographic { xmag, ymag, znear, zfar } => {
+                    json["type"] = "orthographic".into();
+                    json["orthographic"]["xmag"] = xmag.into();
+                    json["orthographic"]["ymag"] = ymag.into();
+                    json["orthographic"]["znear"] = znear.into();
+                    json["orthographic"]["zfar"] = zfar.into();
+                }
+            }
+            let mut node = Value::Null;
+            node["name"] = camera.name.as_str().into();
+            node["camera"] = self.push("cameras", json).into();
+            set_matrix(&mut node, &camera.transform);
+            let node = self.push("nodes", node);
+            self.scene_nodes.push(node);
+        }
+
+        for light in &model.lights {
+            let mut json = Value::Null;
+            json["name"] = light.name.as_str().into();
+            json["color"] = vector(&light.color);
+            json["intensity"] = light.intensity.into();
+            if let Some(range) = light.range {
+                json["range"] = range.into();
+            }
+            json["type"] = match light.kind {
+                LightKind::Directional => "directional",
+                LightKind::Point => "point",
+                LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                    json["spot"]["innerConeAngle"] = inner_cone_angle.0.into();
+                    json["spot"]["outerConeAngle"] = outer_cone_angle.0.into();
+                    "spot"
+                }
+            }.into();
+            let lights = &mut self.root["extensions"][LIGHTS_PUNCTUAL]["lights"];
+            let index = push_array(lights, json);
+
+            let mut node = Value::Null;
+            node["name"] = light.name.as_str().into();
+            node["extensions"][LIGHTS_PUNCTUAL]["light"] = index.into();
+            set_matrix(&mut node, &light.transform);
+            let node = self.push("nodes", node);
+            self.scene_nodes.push(node);
+        }
+        if !model.lights.is_empty() {
+            self.use_extension(LIGHTS_PUNCTUAL);
+        }
+    }
+
+    fn use_extension(&mut self, extension: &str) {
+        let used = &mut self.root["extensionsUsed"];
+        if !used.as_array().map_or(false, |used| used.iter().any(|used| used == extension)) {
+            push_array(used, extension.into());
+        }
+    }
+
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut root = self.root.clone();
+        root["scene"] = 0.into();
+        root["scenes"] = vec![Value::Null].into();
+        root["scenes"][0]["nodes"] = self.scene_nodes.clone().into();
+        if !self.bin.is_empty() {
+            root["buffers"] = vec![Value::Null].into();
+            root["buffers"][0]["byteLength"] = self.bin.len().into();
+        }
+
+        let mut json = gltf::json::serialize::to_vec(&root).expect("glTF JSON is always serializable");
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut bin = self.bin.clone();
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+
+        let chunks_size = 8 + json.len() + if bin.is_empty() { 0 } else { 8 + bin.len() };
+        let mut glb = Vec::with_capacity(12 + chunks_size);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+        glb.extend_from_slice(&(12 + chunks_size as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&JSON_CHUNK.to_le_bytes());
+        glb.extend_from_slice(&json);
+        if !bin.is_empty() {
+            glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            glb.extend_from_slice(&BIN_CHUNK.to_le_bytes());
+            glb.extend_from_slice(&bin);
+        }
+        glb
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ExportError> {
+        fs::write(path, self.to_glb())?;
+        Ok(())
+    }
+
+    fn push(&mut self, key: &str, value: Value) -> usize {
+        push_array(&mut self.root[key], value)
+    }
+
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        while self.bin.len() % 4 != 0 {
+            self.bin.push(0);
+        }
+        let mut view = Value::Null;
+        view["buffer"] = 0.into();
+        view["byteOffset"] = self.bin.len().into();
+        view["byteLength"] = bytes.len().into();
+        if let Some(target) = target {
+            view["target"] = target.into();
+        }
+        self.bin.extend_from_slice(bytes);
+        self.push("bufferViews", view)
+    }
+
+    fn accessor(&mut self, bytes: &[u8], component_type: u32, count: usize, type_: &str, target: Option<u32>) -> usize {
+        let mut accessor = Value::Null;
+        accessor["bufferView"] = self.view(bytes, target).into();
+        accessor["componentType"] = component_type.into();
+        accessor["count"] = count.into();
+        accessor["type"] = type_.into();
+        self.push("accessors", accessor)
+    }
+
+    // POSITION and animation input accessors must declare their bounds.
+    fn accessor_with_bounds(&mut self, values: &[f32], components: usize, target: Option<u32>) -> usize {
+        let type_ = if components == 1 { "SCALAR" } else { "VEC3" };
+        let count = values.len() / components;
+        let accessor = self.accessor(&floats(values), FLOAT, count, type_, target);
+        let bound = |fold: fn(f32, f32) -> f32| -> Vec<f32> {
+            (0..components)
+                .map(|c| values.iter().skip(c).step_by(components).cloned().fold(f32::NAN, fold))
+                .map(|value| if value.is_nan() { 0.0 } else { value })
+                .collect()
+        };
+        self.root["accessors"][accessor]["min"] = bound(f32::min).into();
+        self.root["accessors"][accessor]["max"] = bound(f32::max).into();
+        accessor
+    }
+}
+
+fn push_array(array: &mut Value, value: Value) -> usize {
+    if !array.is_array() {
+        *array = Value::Array(Vec::new());
+    }
+    let array = array.as_array_mut().unwrap();
+    array.push(value);
+    array.len() - 1
+}
+
+fn vector(values: &[f32]) -> Value {
+    values.to_vec().into()
+}
+
+fn set_matrix(node: &mut Value, transform: &Matrix4<f32>) {
+    if *transform != Matrix4::identity() {
+        node["matrix"] = vector(AsRef::<[f32; 16]>::as_ref(transform));
+    }
+}
+
+fn floats(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+}
+
+pub fn export_model(model: &ModelData, name: &str, path: &Path) -> Result<(), ExportError> {
+    let mut exporter = GltfExporter::new();
+    exporter.add_model(name, model, &[Matrix4::identity()])?;
+    exporter.save(path)
+}
+
+// Lists what was lost between a model and its exported and reimported copy. Node indices are
+// renumbered on export, so skins and animations are compared by structure.
+pub fn differences(expected: &ModelData, actual: &ModelData) -> Vec<String> {
+    let mut differences = Vec::new();
+    let mut check = |equal: bool, what: &str| {
+        if !equal {
+            differences.push(what.to_string());
+        }
+    };
+
+    let attributes = |model: &ModelData| -> Vec<Attribute> {
+        model.layout.attributes().iter().map(|attribute| attribute.attribute).collect()
+    };
+    check(attributes(expected) == attributes(actual), "vertex attributes");
+    check(expected.vertices.len() == actual.vertices.len(), "vertex count");
+    check(expected.vertices.iter().zip(actual.vertices.iter()).all(|(a, b)| {
+        a.position == b.position && a.normal == b.normal && a.tex_coord == b.tex_coord
+            && a.joints == b.joints && a.weights == b.weights && a.color == b.color && a.tex_coord1 == b.tex_coord1
+            && a.tangent == b.tangent
+    }), "vertices");
+    check(expected.indices == actual.indices, "indices");
+    check(expected.material.name == actual.material.name, "material name");
+    check(expected.material.base_color == actual.material.base_color, "base color");
+    // Emissive colors brighter than 1 are split into a factor and a strength, which may round.
+    check(expected.material.emissive.iter().zip(actual.material.emissive.iter())
+        .all(|(a, b)| (a - b).abs() <= 1e-6 * a.abs().max(1.0)), "emissive color");
+    check(expected.material.unlit == actual.material.unlit, "unlit shading");
+    check(expected.material.base_color_transform == actual.material.base_color_transform, "base color texture transform");
+    check(expected.material.occlusion_transform == actual.material.occlusion_transform, "occlusion texture transform");
+    check(expected.material.normal_transform == actual.material.normal_transform, "normal texture transform");
+    check(expected.material.normal_scale == actual.material.normal_scale, "normal scale");
+
+    let same_texture = |a: &Texture, b: &Texture| a.width == b.width && a.height == b.height && a.pixels == b.pixels;
+    check(same_texture(&expected.texture, &actual.texture), "base color texture");
+    check(match (&expected.occlusion, &actual.occlusion) {
+        (Some(a), Some(b)) => same_texture(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    }, "occlusion texture");
+    check(match (&expected.lightmap, &actual.lightmap) {
+        (Some(a), Some(b)) => same_texture(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    }, "lightmap texture");
+    check(match (&expected.normal_map, &actual.normal_map) {
+        (Some(a), Some(b)) => same_texture(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    }, "normal texture");
+
+    check(match (&expected.skin, &actual.skin) {
+        (Some(a), Some(b)) => a.joints.len() == b.joints.len() && a.joints.iter().zip(b.joints.iter()).all(|(a, b)| {
+            a.name == b.name && a.parent == b.parent && a.parent_transform == b.parent_transform
+                && a.inverse_bind_matrix == b.inverse_bind_matrix
+                && a.rest.translation == b.rest.translation && a.rest.rotation == b.rest.rotation && a.rest.scale == b.rest.scale
+        }),
+        (a, b) => a.is_none() && b.is_none(),
+    }, "skin");
+    check(expected.animations.len() == actual.animations.len(), "animation count");
+    check(expected.animations.iter().zip(actual.animations.iter()).all(|(a, b)| {
+        a.name == b.name && a.channels.len() == b.channels.len() && a.channels.iter().zip(b.channels.iter()).all(|(a, b)| {
+            a.property == b.property && a.interpolation == b.interpolation && a.times == b.times && a.values == b.values
+        })
+    }), "animations");
+
+    check(expected.morph_targets.len() == actual.morph_targets.len(), "morph target count");
+    check(expected.morph_targets.iter().zip(actual.morph_targets.iter()).all(|(a, b)| {
+        a.position_deltas == b.position_deltas && a.normal_deltas == b.normal_deltas
+    }), "morph targets");
+    check(expected.default_weights == actual.default_weights, "morph target weights");
+
+    check(expected.cameras.len() == actual.cameras.len(), "camera count");
+    check(expected.cameras.iter().zip(actual.cameras.iter()).all(|(a, b)| {
+        a.name == b.name && a.projection == b.projection && a.transform == b.transform
+    }), "cameras");
+    check(expected.lights.len() == actual.lights.len(), "light count");
+    check(expected.lights.iter().zip(actual.lights.iter()).all(|(a, b)| {
+        a.name == b.name && a.kind == b.kind && a.color == b.color && a.intensity == b.intensity
+            && a.range == b.range && a.transform == b.transform
+    }), "lights");
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::primitives;
+    use cgmath::Vector3;
+
+    #[test]
+    fn exported_primitives_load_back_identically() {
+        let shapes = vec![
+            ("cuboid", primitives::cuboid(Vector3::new(1.0, 2.0, 3.0)).unwrap()),
+            ("torus", primitives::torus(0.4, 0.1, 12, 6).unwrap()),
+        ];
+        for (name, model) in &shapes {
+            let path = std::env::temp_dir().join(format!("voids-export-{}.glb", name));
+            export_model(model, name, &path).unwrap();
+            let reloaded = ModelData::load_uncached(&path.to_string_lossy()).unwrap();
+            let _ = fs::remove_file(&path);
+            assert_eq!(differences(model, &reloaded), Vec::<String>::new(), "{} changed on the way", name);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use gltf::json::{Root, Value};
+
+use super::ModelLoadError;
+
+pub const LIGHTS_PUNCTUAL: &str = "KHR_lights_punctual";
+pub const TEXTURE_TRANSFORM: &str = "KHR_texture_transform";
+pub const MATERIALS_UNLIT: &str = "KHR_materials_unlit";
+pub const EMISSIVE_STRENGTH: &str = "KHR_materials_emissive_strength";
+pub const MESH_QUANTIZATION: &str = "KHR_mesh_quantization";
+pub const MESHOPT_COMPRESSION: &str = "EXT_meshopt_compression";
+
+// Files that require anything else are rejected up front instead of rendering incorrectly.
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    LIGHTS_PUNCTUAL,
+    TEXTURE_TRANSFORM,
+    MATERIALS_UNLIT,
+    EMISSIVE_STRENGTH,
+    MESH_QUANTIZATION,
+    MESHOPT_COMPRESSION,
+];
+
+pub fn check_required(root: &Root) -> Result<(), ModelLoadError> {
+    match root.extensions_required.iter().find(|extension| !SUPPORTED_EXTENSIONS.contains(&extension.as_str())) {
+        Some(extension) => Err(ModelLoadError::UnsupportedExtension { extension: extension.clone() }),
+        None => Ok(()),
+    }
+}
+
+// KHR_texture_transform: UVs are scaled, then rotated, then offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTransform {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl TextureTransform {
+    pub const IDENTITY: TextureTransform = TextureTransform {
+        offset: [0.0, 0.0],
+        rotation: 0.0,
+        scale: [1.0, 1.0],
+    };
+
+    pub fn is_identity(&self) -> bool {
+        *self == TextureTransform::IDENTITY
+    }
+
+    // The first two rows of the 3x3 matrix applied to (u, v, 1).
+    pub fn rows(&self) -> [[f32; 3]; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            [cos * self.scale[0], sin * self.scale[1], self.offset[0]],
+            [-sin * self.scale[0], cos * self.scale[1], self.offset[1]],
+        ]
+    }
+}
+
+pub struct MaterialExtensions {
+    pub unlit: bool,
+    pub emissive_strength: f32,
+    pub base_color_transform: TextureTransform,
+    pub base_color_tex_coord: Option<u32>,
+    pub occlusion_transform: TextureTransform,
+    pub normal_transform: TextureTransform,
+    pub normal_tex_coord: Option<u32>,
+    pub lightmap_texture: Option<usize>,
+}
+
+// gltf 0.11 drops extensions it doesn't know, so they're read from the raw JSON.
+pub fn material_extensions(json: &Value, material: Option<usize>) -> Result<MaterialExtensions, ModelLoadError> {
+    let mut extensions = MaterialExtensions {
+        unlit: false,
+        emissive_strength: 1.0,
+        base_color_transform: TextureTransform::IDENTITY,
+        base_color_tex_coord: None,
+        occlusion_transform: TextureTransform::IDENTITY,
+        normal_transform: TextureTransform::IDENTITY,
+        normal_tex_coord: None,
+        lightmap_texture: None,
+    };
+    let index = match material {
+        Some(index) => index,
+        None => return Ok(extensions),
+    };
+    let material = &json["materials"][index];
+    let path = |field: &str| format!("materials[{}].{}", index, field);
+
+    extensions.unlit = !material["extensions"][MATERIALS_UNLIT].is_null();
+    let strength = &material["extensions"][EMISSIVE_STRENGTH]["emissiveStrength"];
+    extensions.emissive_strength = number(strength, 1.0)
+        .filter(|&strength| strength >= 0.0)
+        .ok_or_else(|| invalid(path(EMISSIVE_STRENGTH), "emissive strength must be a non-negative number"))?;
+
+    let base_color = &material["pbrMetallicRoughness"]["baseColorTexture"]["extensions"][TEXTURE_TRANSFORM];
+    let (transform, tex_coord) = texture_transform(base_color, || path("pbrMetallicRoughness.baseColorTexture"))?;
+    extensions.base_color_transform = transform;
+    extensions.base_color_tex_coord = tex_coord;
+    let occlusion = &material["occlusionTexture"]["extensions"][TEXTURE_TRANSFORM];
+    extensions.occlusion_transform = texture_transform(occlusion, || path("occlusionTexture"))?.0;
+    let normal = &material["normalTexture"]["extensions"][TEXTURE_TRANSFORM];
+    let (transform, tex_coord) = texture_transform(normal, || path("normalTexture"))?;
+    extensions.normal_transform = transform;
+    extensions.normal_tex_coord = tex_coord;
+
+    // Lightmaps have no glTF extension. They're a texture info in the material's extras, sampled
+    // with the second UV set like occlusion.
+    let lightmap = &material["extras"]["lightmapTexture"];
+    if !lightmap.is_null() {
+        let texture_count = json["textures"].as_array().map_or(0, Vec::len);
+        let texture = lightmap["index"].as_u64()
+            .filter(|&texture| texture < texture_count as u64)
+            .ok_or_else(|| invalid(path("extras.lightmapTexture"), "index must refer to a texture"))?;
+        if !lightmap["texCoord"].is_null() && lightmap["texCoord"].as_u64() != Some(1) {
+            return Err(invalid(path("extras.lightmapTexture"), "lightmaps must use the second UV set"));
+        }
+        extensions.lightmap_texture = Some(texture as usize);
+    }
+    Ok(extensions)
+}
+
+fn texture_transform(
+    json: &Value,
+    path: impl Fn() -> String,
+) -> Result<(TextureTransform, Option<u32>), ModelLoadError> {
+    if json.is_null() {
+        return Ok((TextureTransform::IDENTITY, None));
+    }
+    let invalid = |reason| invalid(format!("{}.extensions.{}", path(), TEXTURE_TRANSFORM), reason);
+    let pair = |value: &Value, default: [f32; 2]| match value {
+        Value::Null => Some(default),
+        Value::Array(values) if values.len() == 2 => Some([number(&values[0], 0.0)?, number(&values[1], 0.0)?]),
+        _ => None,
+    };
+
+    let transform = TextureTransform {
+        offset: pair(&json["offset"], [0.0, 0.0]).ok_or_else(|| invalid("offset must be two numbers"))?,
+        rotation: number(&json["rotation"], 0.0).ok_or_else(|| invalid("rotation must be a number"))?,
+        scale: pair(&json["scale"], [1.0, 1.0]).ok_or_else(|| invalid("scale must be two numbers"))?,
+    };
+    let tex_coord = match &json["texCoord"] {
+        Value::Null => None,
+        tex_coord => Some(tex_coord.as_u64().filter(|&tex_coord| tex_coord <= u32::max_value() as u64)
+            .ok_or_else(|| invalid("texCoord must be a UV set index"))? as u32),
+    };
+    Ok((transform, tex_coord))
+}
+
+fn number(value: &Value, default: f32) -> Option<f32> {
+    match value {
+        Value::Null => Some(default),
+        value => value.as_f64().map(|value| value as f32).filter(|value| value.is_finite()),
+    }
+}
+
+fn invalid(path: String, reason: &'static str) -> ModelLoadError {
+    ModelLoadError::InvalidDocument { path, reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(json: &str) -> Root {
+        gltf::json::deserialize::from_str(json).unwrap()
+    }
+
+    fn material(json: &str) -> Result<MaterialExtensions, ModelLoadError> {
+        let json: Value = gltf::json::deserialize::from_str(&format!(r#"{{"materials": [{}]}}"#, json)).unwrap();
+        material_extensions(&json, Some(0))
+    }
+
+    fn apply(transform: &TextureTransform, uv: [f32; 2]) -> [f32; 2] {
+        let rows = transform.rows();
+        let row = |row: [f32; 3]| row[0] * uv[0] + row[1] * uv[1] + row[2];
+        [row(rows[0]), row(rows[1])]
+    }
+
+    fn assert_close(actual: [f32; 2], expected: [f32; 2]) {
+        assert!((actual[0] - expected[0]).abs() < 1e-6 && (actual[1] - expected[1]).abs() < 1e-6,
+            "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn accepts_supported_required_extensions() {
+        let root = root(r#"{
+            "asset": {"version": "2.0"},
+            "extensionsUsed": ["KHR_texture_transform", "KHR_lights_punctual"],
+            "extensionsRequired": ["KHR_texture_transform"]
+        }"#);
+        assert!(check_required(&root).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_required_extensions() {
+        let root = root(r#"{
+            "asset": {"version": "2.0"},
+            "extensionsUsed": ["KHR_texture_transform", "KHR_draco_mesh_compression"],
+            "extensionsRequired": ["KHR_texture_transform", "KHR_draco_mesh_compression"]
+        }"#);
+        match check_required(&root) {
+            Err(ModelLoadError::UnsupportedExtension { extension }) => {
+                assert_eq!(extension, "KHR_draco_mesh_compression")
+            }
+            result => panic!("expected UnsupportedExtension, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn texture_transforms_scale_then_rotate_then_offset() {
+        let rotation = 0.5f32;
+        let transform = TextureTransform { offset: [0.25, -0.5], rotation, scale: [2.0, 3.0] };
+        let (sin, cos) = rotation.sin_cos();
+        // The rotation matrix is the one given in the KHR_texture_transform spec.
+        assert_close(apply(&transform, [1.0, 0.0]), [2.0 * cos + 0.25, -2.0 * sin - 0.5]);
+        assert_close(apply(&transform, [0.0, 1.0]), [3.0 * sin + 0.25, 3.0 * cos - 0.5]);
+        assert_close(apply(&transform, [0.0, 0.0]), [0.25, -0.5]);
+        assert_close(apply(&TextureTransform::IDENTITY, [0.3, 0.7]), [0.3, 0.7]);
+    }
+
+    #[test]
+    fn reads_each_texture_transform_with_its_tex_coord_override() {
+        let extensions = material(r#"{
+            "pbrMetallicRoughness": {"baseColorTexture": {"index": 0, "extensions": {
+                "KHR_texture_transform": {"offset": [0.5, 0.0], "texCoord": 1}
+            }}},
+            "normalTexture": {"index": 1, "extensions": {
+                "KHR_texture_transform": {"rotation": 1.5, "scale": [2.0, 2.0]}
+            }}
+        }"#).unwrap();
+        let offset = TextureTransform { offset: [0.5, 0.0], ..TextureTransform::IDENTITY };
+        assert_eq!(extensions.base_color_transform, offset);
+        assert_eq!(extensions.base_color_tex_coord, Some(1));
+        let rotated = TextureTransform { rotation: 1.5, scale: [2.0, 2.0], ..TextureTransform::IDENTITY };
+        assert_eq!(extensions.normal_transform, rotated);
+        assert_eq!(extensions.normal_tex_coord, None);
+        assert!(extensions.occlusion_transform.is_identity());
+    }
+
+    #[test]
+    fn rejects_malformed_texture_transforms() {
+        let reason = |transform: &str| {
+            let extensions = format!(r#"{{"KHR_texture_transform": {}}}"#, transform);
+            let json = format!(r#"{{"normalTexture": {{"index": 0, "extensions": {}}}}}"#, extensions);
+            match material(&json) {
+                Err(ModelLoadError::InvalidDocument { path, reason }) => {
+                    assert_eq!(path, "materials[0].normalTexture.extensions.KHR_texture_transform");
+                    reason
+                }
+                Err(err) => panic!("unexpected error {:?}", err),
+                Ok(_) => panic!("the transform was accepted"),
+            }
+        };
+        assert_eq!(reason(r#"{"texCoord": -1}"#), "texCoord must be a UV set index");
+        assert_eq!(reason(r#"{"offset": [1.0]}"#), "offset must be two numbers");
+        assert_eq!(reason(r#"{"rotation": "quarter"}"#), "rotation must be a number");
+        assert_eq!(reason(r#"{"scale": [1.0, null, 2.0]}"#), "scale must be two numbers");
+    }
+}
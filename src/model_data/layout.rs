@@ -0,0 +1,425 @@
+use wgpu::VertexFormat;
+
+use super::Vertex;
+
+// The instance model matrix occupies four consecutive locations starting here, and the normal
+// matrix three more after the last default attribute location.
+pub const INSTANCE_MATRIX_LOCATION: u32 = 3;
+const INSTANCE_MATRIX_COLUMNS: u32 = 4;
+pub const INSTANCE_NORMAL_MATRIX_LOCATION: u32 = 12;
+const INSTANCE_NORMAL_MATRIX_COLUMNS: u32 = 3;
+
+fn is_instance_location(location: u32) -> bool {
+    (location >= INSTANCE_MATRIX_LOCATION && location < INSTANCE_MATRIX_LOCATION + INSTANCE_MATRIX_COLUMNS)
+        || (location >= INSTANCE_NORMAL_MATRIX_LOCATION
+            && location < INSTANCE_NORMAL_MATRIX_LOCATION + INSTANCE_NORMAL_MATRIX_COLUMNS)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Attribute {
+    Position,
+    Normal,
+    TexCoord0,
+    TexCoord1,
+    Color,
+    Joints,
+    Weights,
+    Tangent,
+}
+
+impl Attribute {
+    pub const ALL: [Attribute; 8] = [
+        Attribute::Position,
+        Attribute::Normal,
+        Attribute::TexCoord0,
+        Attribute::TexCoord1,
+        Attribute::Color,
+        Attribute::Joints,
+        Attribute::Weights,
+        Attribute::Tangent,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Attribute::Position => "position",
+            Attribute::Normal => "normal",
+            Attribute::TexCoord0 => "tex_coord0",
+            Attribute::TexCoord1 => "tex_coord1",
+            Attribute::Color => "color",
+            Attribute::Joints => "joints",
+            Attribute::Weights => "weights",
+            Attribute::Tangent => "tangent",
+        }
+    }
+
+    pub fn default_format(self) -> VertexFormat {
+        match self {
+            Attribute::Position | Attribute::Normal => VertexFormat::Float3,
+            Attribute::TexCoord0 | Attribute::TexCoord1 => VertexFormat::Float2,
+            Attribute::Color | Attribute::Weights | Attribute::Tangent => VertexFormat::Float4,
+            Attribute::Joints => VertexFormat::Ushort4,
+        }
+    }
+
+    pub fn default_location(self) -> u32 {
+        match self {
+            Attribute::Position => 0,
+            Attribute::Normal => 1,
+            Attribute::TexCoord0 => 2,
+            Attribute::Joints => 7,
+            Attribute::Weights => 8,
+            Attribute::Color => 9,
+            Attribute::TexCoord1 => 10,
+            Attribute::Tangent => 11,
+        }
+    }
+
+    fn accepts(self, format: VertexFormat) -> bool {
+        match (self, component_type(format)) {
+            (_, None) => false,
+            (Attribute::Joints, Some(ComponentType::Integer(_))) => true,
+            (Attribute::Joints, _) | (_, Some(ComponentType::Integer(_))) => false,
+            _ => true,
+        }
+    }
+
+    fn values(self, vertex: &Vertex) -> [f32; 4] {
+        match self {
+            Attribute::Position => vertex.position.extend(0.0).into(),
+            Attribute::Normal => vertex.normal.extend(0.0).into(),
+            Attribute::TexCoord0 => [vertex.tex_coord.x, vertex.tex_coord.y, 0.0, 0.0],
+            Attribute::TexCoord1 => [vertex.tex_coord1.x, vertex.tex_coord1.y, 0.0, 0.0],
+            Attribute::Color => vertex.color,
+            Attribute::Joints => {
+                let joints = vertex.joints;
+                [joints[0] as f32, joints[1] as f32, joints[2] as f32, joints[3] as f32]
+            }
+            Attribute::Weights => vertex.weights,
+            Attribute::Tangent => vertex.tangent,
+        }
+    }
+
+    fn shader_name(self) -> String {
+        self.name().to_ascii_uppercase()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+    pub attribute: Attribute,
+    pub format: VertexFormat,
+    pub location: u32,
+    pub offset: u32,
+}
+
+#[derive(Debug, Error)]
+pub enum VertexLayoutError {
+    #[error(display = "vertex layout has no position attribute")]
+    MissingPosition,
+    #[error(display = "vertex attribute {} appears more than once", attribute)]
+    DuplicateAttribute {
+        attribute: &'static str,
+    },
+    #[error(display = "vertex location {} is used by more than one attribute", location)]
+    DuplicateLocation {
+        location: u32,
+    },
+    #[error(display = "vertex location {} is reserved for the instance matrices", location)]
+    ReservedLocation {
+        location: u32,
+    },
+    #[error(display = "vertex attribute {} can't be stored as {:?}", attribute, format)]
+    UnsupportedFormat {
+        attribute: &'static str,
+        format: VertexFormat,
+    },
+}
+
+// Interleaved vertex buffer layout assembled at runtime from named attributes. Vertex data is
+// packed into it on load, and the renderer compiles a vertex shader and pipeline per layout.
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    attributes: Vec<VertexAttribute>,
+    descriptors: Vec<wgpu::VertexAttributeDescriptor>,
+    stride: u32,
+}
+
+impl PartialEq for VertexLayout {
+    fn eq(&self, other: &VertexLayout) -> bool {
+        self.attributes == other.attributes
+    }
+}
+
+impl Eq for VertexLayout {}
+
+impl Default for VertexLayout {
+    fn default() -> VertexLayout {
+        VertexLayout::with_attributes(&[Attribute::Position, Attribute::Normal, Attribute::TexCoord0])
+    }
+}
+
+impl VertexLayout {
+    pub fn new(attributes: &[(Attribute, VertexFormat, u32)]) -> Result<VertexLayout, VertexLayoutError> {
+        if !attributes.iter().any(|&(attribute, _, _)| attribute == Attribute::Position) {
+            return Err(VertexLayoutError::MissingPosition);
+        }
+
+        let mut layout = VertexLayout { attributes: Vec::new(), descriptors: Vec::new(), stride: 0 };
+        for &(attribute, format, location) in attributes {
+            if layout.contains(attribute) {
+                return Err(VertexLayoutError::DuplicateAttribute { attribute: attribute.name() });
+            }
+            if layout.attributes.iter().any(|existing| existing.location == location) {
+                return Err(VertexLayoutError::DuplicateLocation { location });
+            }
+            if is_instance_location(location) {
+                return Err(VertexLayoutError::ReservedLocation { location });
+            }
+            if !attribute.accepts(format) {
+                return Err(VertexLayoutError::UnsupportedFormat { attribute: attribute.name(), format });
+            }
+
+            layout.attributes.push(VertexAttribute { attribute, format, location, offset: layout.stride });
+            layout.descriptors.push(wgpu::VertexAttributeDescriptor {
+                attribute_index: location,
+                format,
+                offset: layout.stride,
+            });
+            // Keep offsets and the stride 4-byte aligned.
+            layout.stride += (format_size(format) + 3) & !3;
+        }
+        Ok(layout)
+    }
+
+    // Layout with the default format and location of each attribute. Duplicates are ignored.
+    pub fn with_attributes(attributes: &[Attribute]) -> VertexLayout {
+        let mut unique: Vec<(Attribute, VertexFormat, u32)> = Vec::new();
+        for &attribute in [Attribute::Position].iter().chain(attributes) {
+            if !unique.iter().any(|&(existing, _, _)| existing == attribute) {
+                unique.push((attribute, attribute.default_format(), attribute.default_location()));
+            }
+        }
+        VertexLayout::new(&unique).expect("default vertex attributes form a valid layout")
+    }
+
+    pub fn attributes(&self) -> &[VertexAttribute] {
+        &self.attributes
+    }
+
+    pub fn contains(&self, attribute: Attribute) -> bool {
+        self.attributes.iter().any(|existing| existing.attribute == attribute)
+    }
+
+    pub fn buffer_descriptor(&self) -> wgpu::VertexBufferDescriptor {
+        wgpu::VertexBufferDescriptor {
+            stride: self.stride,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &self.descriptors,
+        }
+    }
+
+    pub fn shader_defines(&self) -> Vec<(String, String)> {
+        let mut defines = Vec::new();
+        for attribute in &self.attributes {
+            let name = attribute.attribute.shader_name();
+            defines.push((format!("HAS_{}", name), "1".to_string()));
+            defines.push((format!("{}_LOCATION", name), attribute.location.to_string()));
+        }
+        defines
+    }
+
+    pub fn pack(&self, vertices: &[Vertex]) -> Vec<u8> {
+        let mut bytes = vec![0; vertices.len() * self.stride as usize];
+        for (vertex, out) in vertices.iter().zip(bytes.chunks_mut(self.stride as usize)) {
+            for attribute in &self.attributes {
+                let values = attribute.attribute.values(vertex);
+                write_values(attribute.format, &values, &mut out[attribute.offset as usize..]);
+            }
+        }
+        bytes
+    }
+}
+
+const SUPPORTED_FORMATS: [VertexFormat; 16] = [
+    VertexFormat::Float,
+    VertexFormat::Float2,
+    VertexFormat::Float3,
+    VertexFormat::Float4,
+    VertexFormat::Half2,
+    VertexFormat::Half4,
+    VertexFormat::Uchar2Norm,
+    VertexFormat::Uchar4Norm,
+    VertexFormat::Ushort2Norm,
+    VertexFormat::Ushort4Norm,
+    VertexFormat::Char2Norm,
+    VertexFormat::Char4Norm,
+    VertexFormat::Short2Norm,
+    VertexFormat::Short4Norm,
+    VertexFormat::Uchar4,
+    VertexFormat::Ushort4,
+];
+
+pub(super) fn format_from_id(id: u32) -> Option<VertexFormat> {
+    SUPPORTED_FORMATS.iter().cloned().find(|&format| format as u32 == id)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ComponentType {
+    Float,
+    Half,
+    Unorm(u32),
+    Snorm(u32),
+    Integer(u32),
+}
+
+fn component_type(format: VertexFormat) -> Option<ComponentType> {
+    use wgpu::VertexFormat::*;
+    match format {
+        Float | Float2 | Float3 | Float4 => Some(ComponentType::Float),
+        Half2 | Half4 => Some(ComponentType::Half),
+        Uchar2Norm | Uchar4Norm => Some(ComponentType::Unorm(1)),
+        Ushort2Norm | Ushort4Norm => Some(ComponentType::Unorm(2)),
+        Char2Norm | Char4Norm => Some(ComponentType::Snorm(1)),
+        Short2Norm | Short4Norm => Some(ComponentType::Snorm(2)),
+        Uchar4 => Some(ComponentType::Integer(1)),
+        Ushort4 => Some(ComponentType::Integer(2)),
+        _ => None,
+    }
+}
+
+fn component_count(format: VertexFormat) -> usize {
+    use wgpu::VertexFormat::*;
+    match format {
+        Float => 1,
+        Float2 | Half2 | Uchar2Norm | Ushort2Norm | Char2Norm | Short2Norm => 2,
+        Float3 => 3,
+        _ => 4,
+    }
+}
+
+fn format_size(format: VertexFormat) -> u32 {
+    let component_size = match component_type(format) {
+        Some(ComponentType::Float) => 4,
+        Some(ComponentType::Half) => 2,
+        Some(ComponentType::Unorm(size)) | Some(ComponentType::Snorm(size)) | Some(ComponentType::Integer(size)) => size,
+        None => 0,
+    };
+    component_size * component_count(format) as u32
+}
+
+fn write_values(format: VertexFormat, values: &[f32; 4], out: &mut [u8]) {
+    let mut offset = 0;
+    let mut write = |bytes: &[u8]| {
+        out[offset..offset + bytes.len()].copy_from_slice(bytes);
+        offset += bytes.len();
+    };
+    for &value in &values[..component_count(format)] {
+        match component_type(format) {
+            Some(ComponentType::Float) => write(&value.to_bits().to_le_bytes()),
+            Some(ComponentType::Half) => write(&f32_to_f16(value).to_le_bytes()),
+            Some(ComponentType::Unorm(1)) => write(&[(value.max(0.0).min(1.0) * 255.0).round() as u8]),
+            Some(ComponentType::Unorm(_)) => write(&((value.max(0.0).min(1.0) * 65535.0).round() as u16).to_le_bytes()),
+            Some(ComponentType::Snorm(1)) => write(&[(value.max(-1.0).min(1.0) * 127.0).round() as i8 as u8]),
+            Some(ComponentType::Snorm(_)) => write(&((value.max(-1.0).min(1.0) * 32767.0).round() as i16).to_le_bytes()),
+            Some(ComponentType::Integer(1)) => write(&[value as u8]),
+            Some(ComponentType::Integer(_)) => write(&(value as u16).to_le_bytes()),
+            None => (),
+        }
+    }
+}
+
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+    if value.is_nan() {
+        sign | 0x7E00
+    } else if exponent >= 0x1F {
+        sign | 0x7C00
+    } else if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = (mantissa | 0x80_0000) >> (1 - exponent) as u32;
+        sign | ((mantissa + 0x1000) >> 13) as u16
+    } else {
+        let rounded = (((exponent as u32) << 10) | (mantissa >> 13)) + ((mantissa >> 12) & 1);
+        sign | rounded as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Point2, Vector3};
+
+    #[test]
+    fn rejects_duplicate_locations() {
+        let result = VertexLayout::new(&[
+            (Attribute::Position, VertexFormat::Float3, 0),
+            (Attribute::Normal, VertexFormat::Float3, 0),
+        ]);
+        match result {
+            Err(VertexLayoutError::DuplicateLocation { location: 0 }) => (),
+            other => panic!("expected a duplicate location, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_instance_matrix_locations() {
+        let model_locations = INSTANCE_MATRIX_LOCATION..INSTANCE_MATRIX_LOCATION + INSTANCE_MATRIX_COLUMNS;
+        let normal_locations =
+            INSTANCE_NORMAL_MATRIX_LOCATION..INSTANCE_NORMAL_MATRIX_LOCATION + INSTANCE_NORMAL_MATRIX_COLUMNS;
+        for location in model_locations.chain(normal_locations) {
+            let result = VertexLayout::new(&[
+                (Attribute::Position, VertexFormat::Float3, 0),
+                (Attribute::Normal, VertexFormat::Float3, location),
+            ]);
+            match result {
+                Err(VertexLayoutError::ReservedLocation { location: reserved }) => assert_eq!(reserved, location),
+                other => panic!("expected location {} to be reserved, got {:?}", location, other),
+            }
+        }
+        assert!(VertexLayout::new(&[
+            (Attribute::Position, VertexFormat::Float3, 0),
+            (Attribute::Normal, VertexFormat::Float3, INSTANCE_MATRIX_LOCATION + INSTANCE_MATRIX_COLUMNS),
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn accepts_only_matching_formats() {
+        assert!(Attribute::Joints.accepts(VertexFormat::Uchar4));
+        assert!(Attribute::Joints.accepts(VertexFormat::Ushort4));
+        assert!(!Attribute::Joints.accepts(VertexFormat::Float4));
+        assert!(!Attribute::Normal.accepts(VertexFormat::Ushort4));
+        assert!(Attribute::Normal.accepts(VertexFormat::Char4Norm));
+        assert!(Attribute::Color.accepts(VertexFormat::Uchar4Norm));
+        assert!(Attribute::TexCoord0.accepts(VertexFormat::Half2));
+        assert!(!Attribute::Position.accepts(VertexFormat::Int4));
+
+        let result = VertexLayout::new(&[(Attribute::Position, VertexFormat::Uint3, 0)]);
+        match result {
+            Err(VertexLayoutError::UnsupportedFormat { attribute: "position", .. }) => (),
+            other => panic!("expected an unsupported format, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn packs_half_and_snorm_components() {
+        let layout = VertexLayout::new(&[
+            (Attribute::Position, VertexFormat::Half4, 0),
+            (Attribute::Normal, VertexFormat::Char4Norm, 1),
+            (Attribute::TexCoord0, VertexFormat::Short2Norm, 2),
+        ])
+        .unwrap();
+        let vertex = Vertex::new(Vector3::new(1.0, -2.0, 0.5), Vector3::new(1.0, -1.0, 0.0), Point2::new(-0.5, 2.0));
+
+        let bytes = layout.pack(&[vertex]);
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(&bytes[0..8], &[0x00, 0x3C, 0x00, 0xC0, 0x00, 0x38, 0x00, 0x00]);
+        assert_eq!(&bytes[8..12], &[127, (-127i8) as u8, 0, 0]);
+        assert_eq!(&bytes[12..16], &[0x00, 0xC0, 0xFF, 0x7F]);
+    }
+}
@@ -0,0 +1,599 @@
+// Decoders for EXT_meshopt_compression buffer views, following the meshoptimizer bitstream
+// formats: vertex codec version 0, index codec versions 0 and 1, and the index sequence codec.
+
+const VERTEX_HEADER: u8 = 0xa0;
+const INDEX_HEADER: u8 = 0xe0;
+const SEQUENCE_HEADER: u8 = 0xd0;
+
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const BYTE_GROUP_SIZE: usize = 16;
+// A byte group reads at most 24 bytes, so the decoder only needs to check bounds once per group.
+const BYTE_GROUP_DECODE_LIMIT: usize = 24;
+const TAIL_MAX_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Attributes,
+    Triangles,
+    Indices,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    None,
+    Octahedral,
+    Quaternion,
+    Exponential,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "ATTRIBUTES" => Some(Mode::Attributes),
+            "TRIANGLES" => Some(Mode::Triangles),
+            "INDICES" => Some(Mode::Indices),
+            _ => None,
+        }
+    }
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "NONE" => Some(Filter::None),
+            "OCTAHEDRAL" => Some(Filter::Octahedral),
+            "QUATERNION" => Some(Filter::Quaternion),
+            "EXPONENTIAL" => Some(Filter::Exponential),
+            _ => None,
+        }
+    }
+}
+
+// Decodes count elements of stride bytes each and applies the filter to the result.
+pub fn decode(data: &[u8], count: usize, stride: usize, mode: Mode, filter: Filter) -> Result<Vec<u8>, &'static str> {
+    let valid_stride = match mode {
+        Mode::Attributes => stride > 0 && stride <= 256 && stride % 4 == 0,
+        Mode::Triangles | Mode::Indices => stride == 2 || stride == 4,
+    };
+    if !valid_stride {
+        return Err("byte stride isn't valid for the compression mode");
+    }
+    let valid_filter = match filter {
+        Filter::None => true,
+        Filter::Octahedral => mode == Mode::Attributes && (stride == 4 || stride == 8),
+        Filter::Quaternion => mode == Mode::Attributes && stride == 8,
+        Filter::Exponential => mode == Mode::Attributes,
+    };
+    if !valid_filter {
+        return Err("filter isn't valid for the compression mode and byte stride");
+    }
+
+    let mut output = match mode {
+        Mode::Attributes => decode_vertex_buffer(data, count, stride)?,
+        Mode::Triangles => decode_index_buffer(data, count, stride)?,
+        Mode::Indices => decode_index_sequence(data, count, stride)?,
+    };
+    match filter {
+        Filter::None => (),
+        Filter::Octahedral if stride == 4 => filter_octahedral_8(&mut output),
+        Filter::Octahedral => filter_octahedral_16(&mut output),
+        Filter::Quaternion => filter_quaternion(&mut output),
+        Filter::Exponential => filter_exponential(&mut output),
+    }
+    Ok(output)
+}
+
+fn decode_vertex_buffer(data: &[u8], count: usize, stride: usize) -> Result<Vec<u8>, &'static str> {
+    if data.len() < 1 + stride {
+        return Err("vertex data is truncated");
+    }
+    if data[0] != VERTEX_HEADER {
+        return Err("vertex data has an unsupported header");
+    }
+
+    let mut last_vertex = data[data.len() - stride..].to_vec();
+    let block_size = (VERTEX_BLOCK_SIZE_BYTES / stride & !(BYTE_GROUP_SIZE - 1)).min(VERTEX_BLOCK_MAX_SIZE);
+    // The output grows a block at a time so a bogus count runs out of input before allocating much.
+    let mut output = Vec::new();
+    let mut input = Input { data, position: 1 };
+    let mut offset = 0;
+    while offset < count {
+        let block = block_size.min(count - offset);
+        let start = output.len();
+        output.resize(start + block * stride, 0);
+        decode_vertex_block(&mut input, &mut output[start..], block, stride, &mut last_vertex)?;
+        offset += block;
+    }
+
+    if input.remaining() != stride.max(TAIL_MAX_SIZE) {
+        return Err("vertex data has trailing bytes");
+    }
+    Ok(output)
+}
+
+// Each byte of the vertex is stored as a separate stream of zigzag deltas from the previous vertex.
+fn decode_vertex_block(
+    input: &mut Input,
+    output: &mut [u8],
+    count: usize,
+    stride: usize,
+    last_vertex: &mut [u8],
+) -> Result<(), &'static str> {
+    let aligned = (count + BYTE_GROUP_SIZE - 1) & !(BYTE_GROUP_SIZE - 1);
+    let mut deltas = [0u8; VERTEX_BLOCK_MAX_SIZE];
+    for k in 0..stride {
+        decode_bytes(input, &mut deltas[..aligned])?;
+        let mut previous = last_vertex[k];
+        for i in 0..count {
+            let delta = deltas[i];
+            let value = ((delta >> 1) ^ (delta & 1).wrapping_neg()).wrapping_add(previous);
+            output[i * stride + k] = value;
+            previous = value;
+        }
+    }
+    last_vertex.copy_from_slice(&output[(count - 1) * stride..count * stride]);
+    Ok(())
+}
+
+// Groups of 16 bytes are stored with 0, 2, 4 or 8 bits per byte. Values that don't fit in 2 or 4
+// bits are marked with all ones and follow the group as whole bytes.
+fn decode_bytes(input: &mut Input, output: &mut [u8]) -> Result<(), &'static str> {
+    let groups = output.len() / BYTE_GROUP_SIZE;
+    let header = input.take((groups + 3) / 4)?;
+    for group in 0..groups {
+        if input.remaining() < BYTE_GROUP_DECODE_LIMIT {
+            return Err("vertex data is truncated");
+        }
+        let bits_log2 = (header[group / 4] >> ((group % 4) * 2)) & 3;
+        let output = &mut output[group * BYTE_GROUP_SIZE..(group + 1) * BYTE_GROUP_SIZE];
+        match bits_log2 {
+            0 => output.iter_mut().for_each(|byte| *byte = 0),
+            3 => output.copy_from_slice(input.take(BYTE_GROUP_SIZE)?),
+            _ => {
+                let bits = 1 << bits_log2;
+                let packed = input.take(BYTE_GROUP_SIZE * bits / 8)?;
+                let escape = (1u8 << bits) - 1;
+                for (i, byte) in output.iter_mut().enumerate() {
+                    let shift = 8 - bits - (i * bits) % 8;
+                    let value = (packed[i * bits / 8] >> shift) & escape;
+                    *byte = if value == escape { input.byte()? } else { value };
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_index_buffer(data: &[u8], count: usize, stride: usize) -> Result<Vec<u8>, &'static str> {
+    if count % 3 != 0 {
+        return Err("triangle index count isn't a multiple of 3");
+    }
+    // A header, one code per triangle and a 16-byte auxiliary code table.
+    if data.len() < 1 + count / 3 + 16 {
+        return Err("index data is truncated");
+    }
+    let version = match data[0] {
+        header if header & 0xf0 == INDEX_HEADER && header & 0x0f <= 1 => header & 0x0f,
+        _ => return Err("index data has an unsupported header"),
+    };
+
+    let mut edges = [[u32::max_value(); 2]; 16];
+    let mut vertices = [u32::max_value(); 16];
+    let mut edge_offset = 0usize;
+    let mut vertex_offset = 0usize;
+
+    let mut next = 0u32;
+    let mut last = 0u32;
+    let max_fifo_code = if version >= 1 { 13 } else { 15 };
+    let codes = &data[1..1 + count / 3];
+    let safe_end = data.len() - 16;
+    let code_aux_table = &data[safe_end..];
+    let mut input = Input { data: &data[..safe_end], position: 1 + count / 3 };
+    let mut indices = Vec::with_capacity(count);
+
+    for &code in codes {
+        let (a, b, c);
+        if code < 0xf0 {
+            // The triangle shares a recent edge, and the third vertex is new, recent or free.
+            let edge = edges[(edge_offset.wrapping_sub(1 + (code >> 4) as usize)) & 15];
+            a = edge[0];
+            b = edge[1];
+            let fec = code & 15;
+            if fec < max_fifo_code {
+                c = if fec == 0 { next } else { vertices[(vertex_offset.wrapping_sub(1 + fec as usize)) & 15] };
+                next += (fec == 0) as u32;
+                push_vertex(&mut vertices, &mut vertex_offset, c, fec == 0);
+            } else {
+                // Version 1 encodes the index right before or after the last free index in the code.
+                c = match fec {
+                    13 => last.wrapping_sub(1),
+                    14 => last.wrapping_add(1),
+                    _ => decode_index(&mut input, last)?,
+                };
+                last = c;
+                push_vertex(&mut vertices, &mut vertex_offset, c, true);
+            }
+            push_edge(&mut edges, &mut edge_offset, c, b);
+            push_edge(&mut edges, &mut edge_offset, a, c);
+        } else {
+            let (fea, code_aux) = match code {
+                0xfe => (0, input.byte()?),
+                0xff => (15, input.byte()?),
+                _ => (0, code_aux_table[(code & 15) as usize]),
+            };
+            let feb = code_aux >> 4;
+            let fec = code_aux & 15;
+            // A zero auxiliary code outside the table resets the running vertex counter.
+            if code >= 0xfe && code_aux == 0 {
+                next = 0;
+            }
+
+            let mut fifo_or_next = |fe: u8| {
+                if fe == 0 {
+                    next += 1;
+                    next - 1
+                } else {
+                    vertices[(vertex_offset.wrapping_sub(fe as usize)) & 15]
+                }
+            };
+            let mut va = if fea == 0 { fifo_or_next(0) } else { 0 };
+            let mut vb = fifo_or_next(feb);
+            let mut vc = fifo_or_next(fec);
+            if fea == 15 {
+                va = decode_index(&mut input, last)?;
+                last = va;
+            }
+            if feb == 15 {
+                vb = decode_index(&mut input, last)?;
+                last = vb;
+            }
+            if fec == 15 {
+                vc = decode_index(&mut input, last)?;
+                last = vc;
+            }
+            a = va;
+            b = vb;
+            c = vc;
+            push_vertex(&mut vertices, &mut vertex_offset, a, true);
+            push_vertex(&mut vertices, &mut vertex_offset, b, feb == 0 || feb == 15);
+            push_vertex(&mut vertices, &mut vertex_offset, c, fec == 0 || fec == 15);
+            push_edge(&mut edges, &mut edge_offset, b, a);
+            push_edge(&mut edges, &mut edge_offset, c, b);
+            push_edge(&mut edges, &mut edge_offset, a, c);
+        }
+        indices.extend_from_slice(&[a, b, c]);
+    }
+
+    if input.remaining() != 0 {
+        return Err("index data has trailing bytes");
+    }
+    Ok(write_indices(&indices, stride))
+}
+
+// Both ring buffers hold 16 entries and are indexed backwards from the most recent one.
+fn push_edge(edges: &mut [[u32; 2]; 16], offset: &mut usize, a: u32, b: u32) {
+    edges[*offset] = [a, b];
+    *offset = (*offset + 1) & 15;
+}
+
+fn push_vertex(vertices: &mut [u32; 16], offset: &mut usize, v: u32, advance: bool) {
+    vertices[*offset] = v;
+    *offset = (*offset + advance as usize) & 15;
+}
+
+fn decode_index_sequence(data: &[u8], count: usize, stride: usize) -> Result<Vec<u8>, &'static str> {
+    // A header, at least one byte per index and a 4-byte tail.
+    if data.len() < 1 + count + 4 {
+        return Err("index data is truncated");
+    }
+    match data[0] {
+        header if header & 0xf0 == SEQUENCE_HEADER && header & 0x0f <= 1 => (),
+        _ => return Err("index data has an unsupported header"),
+    }
+
+    // Each index is a delta from one of two baselines, selected by the lowest bit.
+    let mut last = [0u32; 2];
+    let mut input = Input { data: &data[..data.len() - 4], position: 1 };
+    let mut indices = Vec::with_capacity(count);
+    for _ in 0..count {
+        let v = input.vbyte()?;
+        let baseline = (v & 1) as usize;
+        let v = v >> 1;
+        let delta = (v >> 1) ^ (v & 1).wrapping_neg();
+        last[baseline] = last[baseline].wrapping_add(delta);
+        indices.push(last[baseline]);
+    }
+    if input.remaining() != 0 {
+        return Err("index data has trailing bytes");
+    }
+    Ok(write_indices(&indices, stride))
+}
+
+fn decode_index(input: &mut Input, last: u32) -> Result<u32, &'static str> {
+    let v = input.vbyte()?;
+    Ok(last.wrapping_add((v >> 1) ^ (v & 1).wrapping_neg()))
+}
+
+fn write_indices(indices: &[u32], stride: usize) -> Vec<u8> {
+    match stride {
+        2 => indices.iter().flat_map(|&index| (index as u16).to_le_bytes().to_vec()).collect(),
+        _ => indices.iter().flat_map(|&index| index.to_le_bytes().to_vec()).collect(),
+    }
+}
+
+// Octahedral normals: x and y are the encoded direction, z holds the value that means 1.0.
+fn filter_octahedral_8(data: &mut [u8]) {
+    for element in data.chunks_mut(4) {
+        let [x, y, z] = octahedral(element[0] as i8 as f32, element[1] as i8 as f32, element[2] as i8 as f32, 127.0);
+        element[0] = x as i8 as u8;
+        element[1] = y as i8 as u8;
+        element[2] = z as i8 as u8;
+    }
+}
+
+fn filter_octahedral_16(data: &mut [u8]) {
+    for element in data.chunks_mut(8) {
+        let component = |i: usize| i16::from_le_bytes([element[i * 2], element[i * 2 + 1]]) as f32;
+        let values = octahedral(component(0), component(1), component(2), 32767.0);
+        for (i, &value) in values.iter().enumerate() {
+            element[i * 2..i * 2 + 2].copy_from_slice(&(value as i16).to_le_bytes());
+        }
+    }
+}
+
+fn octahedral(x: f32, y: f32, one: f32, max: f32) -> [i32; 3] {
+    let z = one - x.abs() - y.abs();
+    let t = z.min(0.0);
+    let x = x + if x >= 0.0 { t } else { -t };
+    let y = y + if y >= 0.0 { t } else { -t };
+    let scale = max / (x * x + y * y + z * z).sqrt();
+    [round(x * scale), round(y * scale), round(z * scale)]
+}
+
+// Quaternions store three components and the index of the largest one, which is reconstructed.
+fn filter_quaternion(data: &mut [u8]) {
+    for element in data.chunks_mut(8) {
+        let component = |i: usize| i16::from_le_bytes([element[i * 2], element[i * 2 + 1]]);
+        let packed = component(3);
+        let scale = ::std::f32::consts::FRAC_1_SQRT_2 / (packed | 3) as f32;
+        let x = component(0) as f32 * scale;
+        let y = component(1) as f32 * scale;
+        let z = component(2) as f32 * scale;
+        let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+
+        let max_component = (packed & 3) as usize;
+        let values = [(1, x), (2, y), (3, z), (0, w)];
+        for &(order, value) in values.iter() {
+            let i = (max_component + order) & 3;
+            element[i * 2..i * 2 + 2].copy_from_slice(&(round(value * 32767.0) as i16).to_le_bytes());
+        }
+    }
+}
+
+// Each 32-bit value is a 24-bit signed mantissa and an 8-bit signed exponent.
+fn filter_exponential(data: &mut [u8]) {
+    for value in data.chunks_mut(4) {
+        let bits = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+        let mantissa = ((bits << 8) as i32) >> 8;
+        let exponent = (bits as i32) >> 24;
+        let scale = f32::from_bits(((exponent + 127) as u32) << 23);
+        value.copy_from_slice(&(scale * mantissa as f32).to_bits().to_le_bytes());
+    }
+}
+
+fn round(value: f32) -> i32 {
+    (value + if value >= 0.0 { 0.5 } else { -0.5 }) as i32
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if self.remaining() < len {
+            return Err("compressed data is truncated");
+        }
+        self.position += len;
+        Ok(&self.data[self.position - len..self.position])
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    // Little endian base-128 with at most five bytes.
+    fn vbyte(&mut self) -> Result<u32, &'static str> {
+        let lead = self.byte()?;
+        if lead < 128 {
+            return Ok(lead as u32);
+        }
+        let mut result = (lead & 127) as u32;
+        let mut shift = 7;
+        for _ in 0..4 {
+            let group = self.byte()?;
+            result |= ((group & 127) as u32) << shift;
+            shift += 7;
+            if group < 128 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::ModelData;
+    use cgmath::{InnerSpace, Vector3};
+    use std::f32::consts::PI;
+
+    fn indices(data: &[u8]) -> Vec<u32> {
+        data.chunks(4).map(|index| u32::from_le_bytes([index[0], index[1], index[2], index[3]])).collect()
+    }
+
+    fn components(data: &[u8]) -> Vec<i16> {
+        data.chunks(2).map(|value| i16::from_le_bytes([value[0], value[1]])).collect()
+    }
+
+    fn packed(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn decodes_vertex_streams() {
+        // 16 vertices whose bytes are stored as 0, 2 (with one escaped delta), 4 and 8 bit groups.
+        let data = [
+            0xa0, 0x00, 0x01, 0x2a, 0x38, 0xa9, 0x55, 0x22, 0x02, 0x0a, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+            0x03, 0x00, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5, 0xe5,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x97,
+        ];
+        let second = [0, 1, 2, 3, 3, 20, 21, 21, 22, 23, 24, 23, 22, 21, 20, 19];
+        let expected: Vec<u8> = (0..16u8)
+            .flat_map(|i| vec![5, second[i as usize], i * 5, i.wrapping_mul(73).wrapping_add(11).wrapping_mul(37)])
+            .collect();
+        assert_eq!(decode(&data, 16, 4, Mode::Attributes, Filter::None), Ok(expected));
+
+        assert!(decode(&data[..data.len() - 1], 16, 4, Mode::Attributes, Filter::None).is_err());
+        let mut newer = data;
+        newer[0] = 0xa1;
+        assert!(decode(&newer, 16, 4, Mode::Attributes, Filter::None).is_err());
+    }
+
+    #[test]
+    fn decodes_triangle_streams() {
+        // The version 0 stream from meshoptimizer's own tests.
+        let data = [
+            0xe0, 0xf0, 0x10, 0xfe, 0xff, 0xf0, 0x0c, 0xff, 0x02, 0x02, 0x02, 0x00, 0x76, 0x87, 0x56, 0x67, 0x78, 0xa9,
+            0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+        ];
+        let expected = vec![0, 1, 2, 2, 1, 3, 4, 6, 5, 7, 8, 9];
+        let decoded = decode(&data, 12, 4, Mode::Triangles, Filter::None).unwrap();
+        assert_eq!(indices(&decoded), expected);
+        let decoded = decode(&data, 12, 2, Mode::Triangles, Filter::None).unwrap();
+        assert_eq!(components(&decoded), expected.iter().map(|&i| i as i16).collect::<Vec<_>>());
+
+        // Version 1 adds codes for the index after (14) and before (13) the last free index.
+        let mut data = vec![0xe1, 0xf0, 0x10, 0xff, 0x0e, 0x0d, 0xff, 0x0e, 0x02, 0x02];
+        data.extend_from_slice(&[0; 16]);
+        let decoded = decode(&data, 15, 4, Mode::Triangles, Filter::None).unwrap();
+        assert_eq!(indices(&decoded), vec![0, 1, 2, 2, 1, 3, 7, 8, 9, 7, 9, 10, 7, 10, 9]);
+
+        assert!(decode(&data[..data.len() - 1], 15, 4, Mode::Triangles, Filter::None).is_err());
+        assert!(decode(&data, 14, 4, Mode::Triangles, Filter::None).is_err());
+    }
+
+    #[test]
+    fn decodes_index_sequences() {
+        // Also from meshoptimizer's tests, with indices from both baselines.
+        let data = [0xd1, 0x00, 0x04, 0xcd, 0x01, 0x04, 0x07, 0x98, 0x1f, 0x00, 0x00, 0x00, 0x00];
+        let decoded = decode(&data, 6, 4, Mode::Indices, Filter::None).unwrap();
+        assert_eq!(indices(&decoded), vec![0, 1, 51, 2, 49, 1000]);
+
+        assert!(decode(&data, 5, 4, Mode::Indices, Filter::None).is_err());
+        assert!(decode(&data, 7, 4, Mode::Indices, Filter::None).is_err());
+    }
+
+    #[test]
+    fn octahedral_filter_unpacks_normals() {
+        let mut data: Vec<u8> = [[0, 0, 127], [127, 0, 127], [0, -127, 127], [64, 64, 127], [-40, 30, 127]]
+            .iter()
+            .flat_map(|n: &[i8; 3]| vec![n[0] as u8, n[1] as u8, n[2] as u8, 0])
+            .collect();
+        filter_octahedral_8(&mut data);
+        let normals: Vec<_> = data.chunks(4).map(|n| [n[0] as i8, n[1] as i8, n[2] as i8, n[3] as i8]).collect();
+        // The fourth normal is past the diagonal of the octahedron, so it folds into the lower half.
+        assert_eq!(normals, vec![[0, 0, 127, 0], [127, 0, 0, 0], [0, -127, 0, 0], [90, 90, -1, 0], [-67, 50, 95, 0]]);
+
+        let mut data = packed(&[0, 0, 32767, 0, -16384, 16383, 32767, 0, 20000, 20000, 32767, 0]);
+        filter_octahedral_16(&mut data);
+        assert_eq!(
+            components(&data),
+            vec![0, 0, 32767, 0, -23170, 23169, 0, 0, 21508, 21508, -12185, 0]
+        );
+    }
+
+    #[test]
+    fn quaternion_filter_restores_the_largest_component() {
+        // The identity, a quarter turn around z and (0.8, 0.6, 0, 0) with x as the largest component.
+        let mut data = packed(&[0, 0, 0, 32767, 0, 0, 32767, 32767, 27804, 0, 0, 32764]);
+        filter_quaternion(&mut data);
+        assert_eq!(components(&data), vec![0, 0, 0, 32767, 0, 0, 23170, 23170, 26213, 19660, 0, 0]);
+    }
+
+    #[test]
+    fn exponential_filter_scales_the_mantissa() {
+        let encode = |mantissa: i32, exponent: i8| {
+            (((exponent as u8 as u32) << 24) | (mantissa as u32 & 0xff_ffff)).to_le_bytes()
+        };
+        let mut data: Vec<u8> = [(3, -1), (-5, 2), (0, 10), (0x7f_ffff, -23)]
+            .iter()
+            .flat_map(|&(mantissa, exponent)| encode(mantissa, exponent).to_vec())
+            .collect();
+        filter_exponential(&mut data);
+        let values: Vec<_> = data
+            .chunks(4)
+            .map(|value| f32::from_bits(u32::from_le_bytes([value[0], value[1], value[2], value[3]])))
+            .collect();
+        assert_eq!(values, vec![1.5, -20.0, 0.0, 0x7f_ffff as f32 / 8_388_608.0]);
+    }
+
+    #[test]
+    fn compressed_sphere_matches_its_parameterization() {
+        let model = ModelData::load_uncached("assets/corpus/meshopt-sphere.gltf").unwrap();
+        let (segments, rings) = (40, 30);
+        assert_eq!(model.vertices.len(), (segments + 1) * (rings + 1));
+        assert_eq!(model.indices.len(), segments * rings * 6);
+
+        // A radius 2 sphere around z, with u going around and v from the top pole to the bottom.
+        for vertex in &model.vertices {
+            let (u, v) = (vertex.tex_coord.x, vertex.tex_coord.y);
+            let (theta, phi) = (v * PI, u * 2.0 * PI);
+            let normal = Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+            assert!((vertex.position - normal * 2.0).magnitude() < 1e-4, "{:?}", vertex);
+            assert!((vertex.normal - normal).magnitude() < 1e-4, "{:?}", vertex);
+            let steps = |t: f32, n: usize| (t * n as f32 - (t * n as f32).round()).abs() < 1e-3;
+            assert!(steps(u, segments) && steps(v, rings), "{:?}", vertex);
+        }
+
+        // Every quad of the grid is split in two counterclockwise triangles that face outwards.
+        let cell = 1.0 / (segments * rings) as f32;
+        let mut area = 0.0;
+        for triangle in model.indices.chunks(3) {
+            let vertex = |i: usize| &model.vertices[triangle[i] as usize];
+            let (a, b, c) = (vertex(0), vertex(1), vertex(2));
+            let (ab, ac) = (b.tex_coord - a.tex_coord, c.tex_coord - a.tex_coord);
+            let uv_area = (ab.x * ac.y - ab.y * ac.x) / 2.0;
+            assert!((uv_area.abs() - cell / 2.0).abs() < 1e-6, "{:?}", triangle);
+            area += uv_area;
+            let face = (b.position - a.position).cross(c.position - a.position);
+            if face.magnitude() > 1e-6 {
+                assert!(face.dot(a.position) > 0.0, "{:?}", triangle);
+            }
+        }
+        assert!((area.abs() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn filtered_attributes_decode_to_the_scene_values() {
+        let model = ModelData::load_uncached("assets/corpus/meshopt-filters.gltf").unwrap();
+        assert_eq!(model.indices, vec![0, 1, 2]);
+        let positions: Vec<_> = model.vertices.iter().map(|vertex| vertex.position).collect();
+        assert_eq!(positions, vec![Vector3::new(0.0, 0.0, 0.0), Vector3::unit_x(), Vector3::unit_y()]);
+        assert!(model.vertices.iter().all(|vertex| vertex.normal == Vector3::unit_z()));
+
+        // Key times use the exponential filter and rotations the quaternion filter.
+        let channel = &model.animations[0].channels[0];
+        assert_eq!(channel.times, vec![0.0, 1.25]);
+        let quarter_turn = 23170.0 / 32767.0;
+        assert_eq!(channel.values, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, quarter_turn, quarter_turn]);
+    }
+}
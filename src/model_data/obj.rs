@@ -0,0 +1,376 @@
+use cgmath::{InnerSpace, Point2, Vector3, Zero};
+use hashbrown::hash_map::HashMap;
+use std::path::Path;
+
+use super::{Material, ModelData, ModelLoadError, Texture, Vertex};
+
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct ObjMaterial {
+    name: String,
+    diffuse: [f32; 3],
+    alpha: f32,
+    diffuse_map: Option<String>,
+}
+
+pub fn load(path: &str) -> Result<ModelData, ModelLoadError> {
+    let source = ::std::fs::read_to_string(path)?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Vector3<f32>> = Vec::new();
+    let mut normals: Vec<Vector3<f32>> = Vec::new();
+    let mut tex_coords: Vec<Point2<f32>> = Vec::new();
+    let mut triangles: Vec<[Corner; 3]> = Vec::new();
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    // A ModelData holds one material, so every face has to use the same one.
+    let mut current_material: Option<String> = None;
+    let mut used_material: Option<Option<String>> = None;
+
+    for (line_i, line) in source.lines().enumerate() {
+        let error = |message: &str| ModelLoadError::ObjParse {
+            path: path.into(),
+            line: line_i + 1,
+            message: message.into(),
+        };
+
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args, 3).ok_or_else(|| error("invalid vertex position"))?;
+                positions.push(Vector3::new(v[0], v[1], v[2]));
+            }
+            "vn" => {
+                let n = parse_floats(&args, 3).ok_or_else(|| error("invalid vertex normal"))?;
+                normals.push(Vector3::new(n[0], n[1], n[2]));
+            }
+            "vt" => {
+                let t = parse_floats(&args, 1).ok_or_else(|| error("invalid texture coordinate"))?;
+                let v = t.get(1).cloned().unwrap_or(0.0);
+                tex_coords.push(Point2::new(t[0], 1.0 - v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error("face has fewer than three vertices"));
+                }
+                let corners = args.iter()
+                    .map(|arg| parse_corner(arg, positions.len(), tex_coords.len(), normals.len()))
+                    .collect::<Option<Vec<Corner>>>()
+                    .ok_or_else(|| error("invalid face vertex reference"))?;
+                match used_material {
+                    None => used_material = Some(current_material.clone()),
+                    Some(ref used) if *used != current_material => {
+                        return Err(error("faces use more than one material, split the file into one model per material"));
+                    }
+                    Some(_) => (),
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "mtllib" => {
+                for library in &args {
+                    materials.extend(load_mtl(path, &base_dir.join(library))?);
+                }
+            }
+            "usemtl" => current_material = Some(args.join(" ")),
+            _ => (),
+        }
+    }
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u16> = Vec::with_capacity(triangles.len() * 3);
+    let mut unique: HashMap<Corner, u16> = HashMap::new();
+    let mut needs_normal: Vec<bool> = Vec::new();
+
+    for triangle in &triangles {
+        for corner in triangle.iter() {
+            if let Some(&index) = unique.get(corner) {
+                indices.push(index);
+                continue;
+            }
+
+            if vertices.len() > u16::max_value() as usize {
+                return Err(ModelLoadError::TooManyVertices { count: vertices.len() + 1 });
+            }
+            let (position, tex_coord, normal) = *corner;
+            let index = vertices.len() as u16;
+            vertices.push(Vertex {
+                position: positions[position],
+                normal: normal.map(|n| normals[n]).unwrap_or_else(Vector3::zero),
+                tex_coord: tex_coord.map(|t| tex_coords[t]).unwrap_or_else(|| Point2::new(0.0, 0.0)),
+                joints: [0; 4],
+                weights: [1.0, 0.0, 0.0, 0.0],
+                color: [1.0; 4],
+                tex_coord1: tex_coord.map(|t| tex_coords[t]).unwrap_or_else(|| Point2::new(0.0, 0.0)),
+                tangent: [1.0, 0.0, 0.0, 1.0],
+            });
+            needs_normal.push(normal.is_none());
+            unique.insert(*corner, index);
+            indices.push(index);
+        }
+    }
+
+    if needs_normal.iter().any(|&needs| needs) {
+        for triangle in indices.chunks(3) {
+            let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+            let face_normal = (vertices[b].position - vertices[a].position)
+                .cross(vertices[c].position - vertices[a].position);
+            for &i in &[a, b, c] {
+                if needs_normal[i] {
+                    vertices[i].normal += face_normal;
+                }
+            }
+        }
+        for (vertex, &needs) in vertices.iter_mut().zip(needs_normal.iter()) {
+            if needs && !vertex.normal.is_zero() {
+                vertex.normal = vertex.normal.normalize();
+            }
+        }
+    }
+
+    let obj_material = match used_material.unwrap_or(current_material) {
+        Some(name) => materials.into_iter().find(|material| material.name == name),
+        None => materials.into_iter().next(),
+    };
+
+    // Kd and d multiply the diffuse map like a glTF base color factor, so without a map they
+    // color a white texture.
+    let (material, texture) = match obj_material {
+        Some(obj_material) => {
+            let base_color = [obj_material.diffuse[0], obj_material.diffuse[1], obj_material.diffuse[2], obj_material.alpha];
+            let texture = match obj_material.diffuse_map {
+                Some(map) => Texture::load_file(&base_dir.join(map))?,
+                None => Texture::solid([1.0; 4]),
+            };
+            (Material::new(obj_material.name, base_color), texture)
+        }
+        None => (Material::new("<default>".into(), [1.0; 4]), Texture::solid([1.0; 4])),
+    };
+
+    Ok(ModelData::new(vertices, indices, material, texture))
+}
+
+fn parse_floats(args: &[&str], min_count: usize) -> Option<Vec<f32>> {
+    if args.len() < min_count {
+        return None;
+    }
+    args.iter().map(|arg| arg.parse::<f32>().ok()).collect()
+}
+
+fn parse_corner(arg: &str, position_count: usize, tex_coord_count: usize, normal_count: usize) -> Option<Corner> {
+    let mut parts = arg.split('/');
+    let position = resolve_index(parts.next()?, position_count)?;
+    let tex_coord = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, tex_coord_count)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, normal_count)?),
+    };
+    Some((position, tex_coord, normal))
+}
+
+fn resolve_index(index: &str, count: usize) -> Option<usize> {
+    let index: i64 = index.parse().ok()?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if resolved >= 0 && (resolved as usize) < count {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+fn load_mtl(obj_path: &str, path: &Path) -> Result<Vec<ObjMaterial>, ModelLoadError> {
+    let source = ::std::fs::read_to_string(path)?;
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+
+    for (line_i, line) in source.lines().enumerate() {
+        let error = |message: &str| ModelLoadError::ObjParse {
+            path: format!("{} ({})", path.display(), obj_path),
+            line: line_i + 1,
+            message: message.into(),
+        };
+
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial {
+                name: args.join(" "),
+                diffuse: [1.0, 1.0, 1.0],
+                alpha: 1.0,
+                diffuse_map: None,
+            });
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+        match keyword {
+            "Kd" => {
+                let kd = parse_floats(&args, 3).ok_or_else(|| error("invalid diffuse color"))?;
+                material.diffuse = [kd[0], kd[1], kd[2]];
+            }
+            "d" => {
+                let d = parse_floats(&args, 1).ok_or_else(|| error("invalid dissolve"))?;
+                material.alpha = d[0];
+            }
+            "Tr" => {
+                let tr = parse_floats(&args, 1).ok_or_else(|| error("invalid transparency"))?;
+                material.alpha = 1.0 - tr[0];
+            }
+            "map_Kd" => {
+                let file = args.last().ok_or_else(|| error("missing diffuse map file"))?;
+                material.diffuse_map = Some(file.to_string());
+            }
+            _ => (),
+        }
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point2, Vector3};
+    use std::path::PathBuf;
+    use crate::headless::Image;
+    use crate::model_data::{ModelData, ModelLoadError};
+    use super::{load, resolve_index};
+
+    // Every test writes its files into a directory of its own.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("voids-obj-{}", name));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    // Loads the first file.
+    fn load_files(name: &str, files: &[(&str, &str)]) -> Result<ModelData, ModelLoadError> {
+        let dir = write_files(name, files);
+        load(dir.join(files[0].0).to_str().unwrap())
+    }
+
+    fn error_line(result: Result<ModelData, ModelLoadError>) -> (usize, String) {
+        match result {
+            Err(ModelLoadError::ObjParse { line, message, .. }) => (line, message),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("the file loaded"),
+        }
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn triangulates_polygons_as_fans() {
+        let source = "v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n";
+        let model = load_files("fan", &[("fan.obj", source)]).unwrap();
+        assert_eq!(model.vertices.len(), 5);
+        assert_eq!(model.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn merges_repeated_index_combinations() {
+        let faces = "f 1/1/1 2/2/1 3/3/1\nf 1/1/1 3/3/1 4/4/1\nf 1/2/1 3/3/1 4/4/1\n";
+        let source = format!("{}vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n{}", SQUARE, faces);
+        let model = load_files("merge", &[("merge.obj", &source)]).unwrap();
+        // The last face's first corner has a different UV, so it gets a vertex of its own.
+        assert_eq!(model.vertices.len(), 5);
+        assert_eq!(model.indices, vec![0, 1, 2, 0, 2, 3, 4, 2, 3]);
+        assert_eq!(model.vertices[4].position, model.vertices[0].position);
+    }
+
+    #[test]
+    fn resolves_relative_indices() {
+        assert_eq!(resolve_index("1", 3), Some(0));
+        assert_eq!(resolve_index("3", 3), Some(2));
+        assert_eq!(resolve_index("-1", 3), Some(2));
+        assert_eq!(resolve_index("-3", 3), Some(0));
+        assert_eq!(resolve_index("0", 3), None);
+        assert_eq!(resolve_index("4", 3), None);
+        assert_eq!(resolve_index("-4", 3), None);
+        assert_eq!(resolve_index("x", 3), None);
+
+        // Relative indices count back from the elements defined so far, not from the end of the file.
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\n";
+        let model = load_files("relative", &[("relative.obj", source)]).unwrap();
+        assert_eq!(model.vertices[2].position, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn rejects_out_of_range_references() {
+        let triangle = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        let source = format!("{}f 1 2 4\n", triangle);
+        let (line, message) = error_line(load_files("range-v", &[("range.obj", &source)]));
+        assert_eq!((line, message.as_str()), (4, "invalid face vertex reference"));
+        let source = format!("{}vt 0 0\nf 1/1 2/2 3/1\n", triangle);
+        assert_eq!(error_line(load_files("range-vt", &[("range.obj", &source)])).0, 5);
+        let source = format!("{}f 1//1 2//1 3//1\n", triangle);
+        assert_eq!(error_line(load_files("range-vn", &[("range.obj", &source)])).0, 4);
+    }
+
+    #[test]
+    fn generates_normals_only_for_corners_without_one() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 1 0 0\nf 1//1 2 3\n";
+        let model = load_files("normals", &[("normals.obj", source)]).unwrap();
+        assert_eq!(model.vertices[0].normal, Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(model.vertices[1].normal, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(model.vertices[2].normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn flips_texture_coordinates_vertically() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25 0.75\nvt 0.5\nf 1/1 2/2 3\n";
+        let model = load_files("uv", &[("uv.obj", source)]).unwrap();
+        assert_eq!(model.vertices[0].tex_coord, Point2::new(0.25, 0.25));
+        assert_eq!(model.vertices[0].tex_coord1, Point2::new(0.25, 0.25));
+        assert_eq!(model.vertices[1].tex_coord, Point2::new(0.5, 1.0));
+        assert_eq!(model.vertices[2].tex_coord, Point2::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn reads_material_colors_and_diffuse_maps() {
+        let mtl = "newmtl plain\nKd 0.5 0.25 1\nd 0.5\n\nnewmtl tinted\nKd 1 0.5 0.5\nTr 0.25\nmap_Kd -bm 1 tint.png\n";
+        let dir = write_files("mtl", &[("scene.mtl", mtl)]);
+        let pixels = vec![255, 0, 0, 255, 0, 0, 255, 255];
+        Image { width: 2, height: 1, pixels: pixels.clone() }.save_png(&dir.join("tint.png")).unwrap();
+        let source = |material| format!("mtllib scene.mtl\nusemtl {}\n{}f 1 2 3\n", material, SQUARE);
+
+        let plain = load_files("mtl", &[("plain.obj", &source("plain"))]).unwrap();
+        assert_eq!(plain.material.name, "plain");
+        assert_eq!(plain.material.base_color, [0.5, 0.25, 1.0, 0.5]);
+        assert_eq!((plain.texture.width, plain.texture.pixels.as_slice()), (1, &[255u8; 4][..]));
+
+        let tinted = load_files("mtl", &[("tinted.obj", &source("tinted"))]).unwrap();
+        assert_eq!(tinted.material.base_color, [1.0, 0.5, 0.5, 0.75]);
+        assert_eq!((tinted.texture.width, tinted.texture.height), (2, 1));
+        assert_eq!(tinted.texture.pixels, pixels);
+    }
+
+    #[test]
+    fn rejects_more_than_one_material() {
+        let mtl = "newmtl a\nKd 1 0 0\nnewmtl b\nKd 0 1 0\n";
+        let source = format!("mtllib two.mtl\n{}usemtl a\nf 1 2 3\nusemtl b\nf 1 3 4\n", SQUARE);
+        let (line, message) = error_line(load_files("materials", &[("two.obj", &source), ("two.mtl", mtl)]));
+        assert_eq!(line, 9);
+        assert!(message.contains("more than one material"), "{}", message);
+    }
+}
@@ -0,0 +1,435 @@
+use cgmath::{InnerSpace, Vector3, Zero};
+use hashbrown::hash_map::HashMap;
+use std::fmt;
+
+use super::{ModelData, Vertex};
+
+const CACHE_SIZE: usize = 32;
+const STATS_CACHE_SIZE: usize = 16;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshStats {
+    pub vertices: usize,
+    pub triangles: usize,
+    pub acmr: f32,
+    pub atvr: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptimizationStats {
+    pub before: MeshStats,
+    pub after: MeshStats,
+}
+
+impl MeshStats {
+    pub fn measure(vertex_count: usize, indices: &[u16]) -> MeshStats {
+        let triangles = indices.len() / 3;
+        let mut cache: Vec<u16> = Vec::with_capacity(STATS_CACHE_SIZE);
+        let mut misses = 0;
+        for &index in indices {
+            if !cache.contains(&index) {
+                misses += 1;
+                if cache.len() == STATS_CACHE_SIZE {
+                    cache.remove(0);
+                }
+                cache.push(index);
+            }
+        }
+
+        MeshStats {
+            vertices: vertex_count,
+            triangles,
+            acmr: if triangles > 0 { misses as f32 / triangles as f32 } else { 0.0 },
+            atvr: if vertex_count > 0 { misses as f32 / vertex_count as f32 } else { 0.0 },
+        }
+    }
+}
+
+impl fmt::Display for MeshStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} vertices, {} triangles, ACMR {:.3}, ATVR {:.3}",
+            self.vertices, self.triangles, self.acmr, self.atvr
+        )
+    }
+}
+
+impl fmt::Display for OptimizationStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "before: {}; after: {}", self.before, self.after)
+    }
+}
+
+impl ModelData {
+    pub fn optimize(&mut self) -> OptimizationStats {
+        let before = MeshStats::measure(self.vertices.len(), &self.indices);
+
+        let remap = deduplicate_vertices(self);
+        self.apply_remap(&remap);
+
+        self.indices = optimize_vertex_cache(&self.indices, self.vertices.len());
+        self.indices = optimize_overdraw(&self.indices, &self.vertices);
+
+        // LODs can use vertices the full mesh doesn't, so they're numbered after it.
+        let mut index_buffers = vec![self.indices.as_slice()];
+        index_buffers.extend(self.lods.iter().map(|lod| lod.indices.as_slice()));
+        let remap = optimize_vertex_fetch(&index_buffers, self.vertices.len());
+        self.apply_remap(&remap);
+
+        OptimizationStats {
+            before,
+            after: MeshStats::measure(self.vertices.len(), &self.indices),
+        }
+    }
+
+    fn apply_remap(&mut self, remap: &[Option<u16>]) {
+        let vertex_count = remap.iter().filter_map(|&new| new).map(|new| new as usize + 1).max().unwrap_or(0);
+
+        self.vertices = remap_vertex_data(&self.vertices, remap, vertex_count);
+        for target in self.morph_targets.iter_mut() {
+            target.position_deltas = remap_vertex_data(&target.position_deltas, remap, vertex_count);
+            target.normal_deltas = remap_vertex_data(&target.normal_deltas, remap, vertex_count);
+        }
+        for index in self.indices.iter_mut() {
+            *index = remap[*index as usize].unwrap();
+        }
+        for lod in self.lods.iter_mut() {
+            for index in lod.indices.iter_mut() {
+                *index = remap[*index as usize].unwrap();
+            }
+        }
+    }
+}
+
+fn remap_vertex_data<T: Copy>(data: &[T], remap: &[Option<u16>], vertex_count: usize) -> Vec<T> {
+    let mut remapped: Vec<Option<T>> = vec![None; vertex_count];
+    for (old, &new) in remap.iter().enumerate() {
+        if let Some(new) = new {
+            remapped[new as usize] = Some(data[old]);
+        }
+    }
+    remapped.into_iter().map(Option::unwrap).collect()
+}
+
+fn vertex_key(model: &ModelData, i: usize) -> Vec<u32> {
+    let Vertex { position, normal, tex_coord, joints, weights, color, tex_coord1, tangent } = model.vertices[i];
+    let mut key = vec![
+        position.x.to_bits(), position.y.to_bits(), position.z.to_bits(),
+        normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits(),
+        tex_coord.x.to_bits(), tex_coord.y.to_bits(),
+    ];
+    key.extend(joints.iter().map(|&joint| joint as u32));
+    key.extend(weights.iter().map(|weight| weight.to_bits()));
+    key.extend(color.iter().map(|channel| channel.to_bits()));
+    key.extend_from_slice(&[tex_coord1.x.to_bits(), tex_coord1.y.to_bits()]);
+    key.extend(tangent.iter().map(|component| component.to_bits()));
+    for target in &model.morph_targets {
+        let (p, n) = (target.position_deltas[i], target.normal_deltas[i]);
+        key.extend_from_slice(&[p.x.to_bits(), p.y.to_bits(), p.z.to_bits(), n.x.to_bits(), n.y.to_bits(), n.z.to_bits()]);
+    }
+    key
+}
+
+fn deduplicate_vertices(model: &ModelData) -> Vec<Option<u16>> {
+    let mut unique: HashMap<Vec<u32>, u16> = HashMap::new();
+    let mut remap = Vec::with_capacity(model.vertices.len());
+    for i in 0..model.vertices.len() {
+        let next = unique.len() as u16;
+        let index = *unique.entry(vertex_key(model, i)).or_insert(next);
+        remap.push(Some(index));
+    }
+    remap
+}
+
+// Numbers the vertices in the order the index buffers first use them. Vertices none of them use
+// are dropped.
+fn optimize_vertex_fetch(index_buffers: &[&[u16]], vertex_count: usize) -> Vec<Option<u16>> {
+    let mut remap = vec![None; vertex_count];
+    let mut next = 0usize;
+    for &index in index_buffers.iter().flat_map(|indices| indices.iter()) {
+        if remap[index as usize].is_none() {
+            remap[index as usize] = Some(next as u16);
+            next += 1;
+        }
+    }
+    remap
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_valence: usize) -> f32 {
+    if remaining_valence == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scaler = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scaler).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    cache_score + VALENCE_BOOST_SCALE * (remaining_valence as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// Splits the cache ordered triangles into clusters wherever all three corners miss the cache and
+// draws the clusters that face away from the middle of the mesh first, so they tend to hide the
+// ones behind them from any direction. Reordering whole clusters keeps most of the cache locality.
+fn optimize_overdraw(indices: &[u16], vertices: &[Vertex]) -> Vec<u16> {
+    let triangle_count = indices.len() / 3;
+    let mut cache: Vec<u16> = Vec::with_capacity(STATS_CACHE_SIZE);
+    let mut clusters: Vec<usize> = Vec::new();
+    for triangle in 0..triangle_count {
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        if corners.iter().all(|index| !cache.contains(index)) {
+            clusters.push(triangle);
+        }
+        for &index in corners {
+            if !cache.contains(&index) {
+                if cache.len() == STATS_CACHE_SIZE {
+                    cache.remove(0);
+                }
+                cache.push(index);
+            }
+        }
+    }
+
+    // Triangle centroids and normals, weighted by area.
+    let triangle_shape = |triangle: usize| {
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        let (a, b, c) = (vertices[corners[0] as usize], vertices[corners[1] as usize], vertices[corners[2] as usize]);
+        let area = (b.position - a.position).cross(c.position - a.position).magnitude() * 0.5;
+        let centroid = (a.position + b.position + c.position) / 3.0;
+        (area, centroid, (a.normal + b.normal + c.normal) * area)
+    };
+    let (mut mesh_area, mut mesh_centroid) = (0.0, Vector3::zero());
+    for triangle in 0..triangle_count {
+        let (area, centroid, _) = triangle_shape(triangle);
+        mesh_area += area;
+        mesh_centroid += centroid * area;
+    }
+    if mesh_area > 0.0 {
+        mesh_centroid /= mesh_area;
+    }
+
+    let mut ranges: Vec<(f32, usize, usize)> = clusters
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = clusters.get(i + 1).cloned().unwrap_or(triangle_count);
+            let (mut area, mut centroid, mut normal) = (0.0, Vector3::zero(), Vector3::zero());
+            for triangle in start..end {
+                let (triangle_area, triangle_centroid, triangle_normal) = triangle_shape(triangle);
+                area += triangle_area;
+                centroid += triangle_centroid * triangle_area;
+                normal += triangle_normal;
+            }
+            let key = if area > 0.0 && normal.magnitude2() > 0.0 {
+                (centroid / area - mesh_centroid).dot(normal.normalize())
+            } else {
+                0.0
+            };
+            (key, start, end)
+        })
+        .collect();
+    // The sort is stable, so clusters with equal keys keep the cache order.
+    ranges.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    ranges.iter().flat_map(|&(_, start, end)| indices[start * 3..end * 3].iter().cloned()).collect()
+}
+
+pub(super) fn optimize_vertex_cache(indices: &[u16], vertex_count: usize) -> Vec<u16> {
+    let triangle_count = indices.len() / 3;
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for triangle in 0..triangle_count {
+        for &index in &indices[triangle * 3..triangle * 3 + 3] {
+            vertex_triangles[index as usize].push(triangle);
+        }
+    }
+
+    let mut remaining_valence: Vec<usize> = vertex_triangles.iter().map(Vec::len).collect();
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = (0..vertex_count)
+        .map(|v| vertex_score(None, remaining_valence[v]))
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let triangle_score = |triangle: usize, scores: &[f32]| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3].iter().map(|&i| scores[i as usize]).sum()
+    };
+
+    let mut cache: Vec<u16> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut result = Vec::with_capacity(indices.len());
+    let mut next_unemitted = 0;
+
+    for _ in 0..triangle_count {
+        let mut best: Option<(usize, f32)> = None;
+        for &vertex in &cache {
+            for &triangle in &vertex_triangles[vertex as usize] {
+                if emitted[triangle] {
+                    continue;
+                }
+                let score = triangle_score(triangle, &vertex_scores);
+                match best {
+                    Some((best_triangle, best_score))
+                        if best_score > score || (best_score == score && best_triangle < triangle) => (),
+                    _ => best = Some((triangle, score)),
+                }
+            }
+        }
+
+        let triangle = match best {
+            Some((triangle, _)) => triangle,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+        emitted[triangle] = true;
+
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        result.extend_from_slice(corners);
+        for &vertex in corners {
+            remaining_valence[vertex as usize] -= 1;
+            if let Some(position) = cache.iter().position(|&cached| cached == vertex) {
+                cache.remove(position);
+            }
+        }
+        for &vertex in corners.iter().rev() {
+            cache.insert(0, vertex);
+        }
+
+        for evicted in cache.drain(CACHE_SIZE.min(cache.len())..) {
+            cache_position[evicted as usize] = None;
+            vertex_scores[evicted as usize] = vertex_score(None, remaining_valence[evicted as usize]);
+        }
+        for (position, &vertex) in cache.iter().enumerate() {
+            cache_position[vertex as usize] = Some(position);
+            vertex_scores[vertex as usize] = vertex_score(Some(position), remaining_valence[vertex as usize]);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point2, Vector3};
+
+    use super::super::{primitives, Lod, Material, ModelData, Texture, Vertex};
+    use super::{optimize_overdraw, optimize_vertex_fetch, MeshStats};
+
+    fn vertex(x: f32, y: f32, z: f32, normal: Vector3<f32>) -> Vertex {
+        Vertex::new(Vector3::new(x, y, z), normal, Point2::new(x, y))
+    }
+
+    fn model(vertices: Vec<Vertex>, indices: Vec<u16>) -> ModelData {
+        let color = [1.0, 1.0, 1.0, 1.0];
+        ModelData::new(vertices, indices, Material::new("test".into(), color), Texture::solid(color))
+    }
+
+    fn triangles(model: &ModelData) -> Vec<[Vector3<f32>; 3]> {
+        let mut triangles: Vec<_> = model
+            .indices
+            .chunks(3)
+            .map(|t| {
+                let corner = |i: usize| model.vertices[t[i] as usize].position;
+                [corner(0), corner(1), corner(2)]
+            })
+            .collect();
+        triangles.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+        triangles
+    }
+
+    #[test]
+    fn merges_identical_vertices() {
+        let up = Vector3::unit_z();
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let vertices = corners.iter().map(|&(x, y)| vertex(x, y, 0.0, up)).collect();
+        let mut quad = model(vertices, (0..6).collect());
+        let expected = triangles(&quad);
+
+        let stats = quad.optimize();
+        assert_eq!(stats.before.vertices, 6);
+        assert_eq!(stats.after.vertices, 4);
+        assert_eq!(quad.vertices.len(), 4);
+        assert_eq!(triangles(&quad), expected);
+    }
+
+    #[test]
+    fn reorders_for_the_vertex_cache_and_fetch_order() {
+        // A grid whose triangles are spread out so that consecutive ones share no vertices.
+        let grid = primitives::plane(1.0, 1.0, 24, 24).unwrap();
+        let count = grid.indices.len() / 3;
+        let mut scattered = Vec::with_capacity(grid.indices.len());
+        for i in 0..count {
+            let triangle = i * 97 % count;
+            scattered.extend_from_slice(&grid.indices[triangle * 3..triangle * 3 + 3]);
+        }
+        let mut grid = model(grid.vertices, scattered);
+        let expected = triangles(&grid);
+
+        let stats = grid.optimize();
+        assert!(stats.after.acmr < stats.before.acmr * 0.5, "{}", stats);
+        assert_eq!(stats.after, MeshStats::measure(grid.vertices.len(), &grid.indices));
+        assert_eq!(triangles(&grid), expected);
+
+        // Vertices are stored in the order the indices first use them.
+        let mut next = 0;
+        for &index in &grid.indices {
+            assert!(index <= next);
+            if index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, grid.vertices.len());
+    }
+
+    #[test]
+    fn draws_outward_facing_clusters_first() {
+        // Three separate quads: one facing in towards the middle and two facing out.
+        let quad = |z: f32, normal: Vector3<f32>| vec![
+            vertex(0.0, 0.0, z, normal),
+            vertex(1.0, 0.0, z, normal),
+            vertex(1.0, 1.0, z, normal),
+            vertex(0.0, 1.0, z, normal),
+        ];
+        let mut vertices = quad(0.5, -Vector3::unit_z());
+        vertices.extend(quad(1.0, Vector3::unit_z()));
+        vertices.extend(quad(-1.0, -Vector3::unit_z()));
+        let indices: Vec<u16> = (0..3).flat_map(|q| vec![0, 1, 2, 0, 2, 3].into_iter().map(move |i| q * 4 + i)).collect();
+
+        let ordered = optimize_overdraw(&indices, &vertices);
+        assert_eq!(ordered[..6], indices[12..]);
+        assert_eq!(ordered[6..12], indices[6..12]);
+        assert_eq!(ordered[12..], indices[..6]);
+    }
+
+    #[test]
+    fn keeps_vertices_only_lods_use() {
+        let up = Vector3::unit_z();
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let mut quad = model(corners.iter().map(|&(x, y)| vertex(x, y, 0.0, up)).collect(), vec![0, 1, 2]);
+        quad.lods = vec![Lod { indices: vec![0, 2, 3], screen_size: 0.5 }];
+
+        quad.optimize();
+        assert_eq!(quad.vertices.len(), 4);
+        let lod: Vec<_> = quad.lods[0].indices.iter().map(|&i| quad.vertices[i as usize].position).collect();
+        let expected = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.0), Vector3::new(0.0, 1.0, 0.0)];
+        assert_eq!(lod, expected);
+    }
+
+    #[test]
+    fn numbers_every_vertex_a_16_bit_index_can_address() {
+        let indices: Vec<u16> = (0..=u16::max_value()).rev().collect();
+        let remap = optimize_vertex_fetch(&[&indices], indices.len());
+        assert_eq!(remap[0], Some(u16::max_value()));
+        assert_eq!(remap[usize::from(u16::max_value())], Some(0));
+    }
+}
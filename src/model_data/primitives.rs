@@ -0,0 +1,379 @@
+use cgmath::{InnerSpace, Point2, Vector3};
+use hashbrown::hash_map::HashMap;
+use std::f32::consts::PI;
+
+use super::{Material, ModelData, ModelLoadError, Texture, Vertex};
+
+// Generated meshes use 16-bit indices like loaded ones, so every shape is limited to 65536
+// vertices. Segment counts that would need more return TooManyVertices before anything is built.
+fn check_vertex_count(count: u64) -> Result<(), ModelLoadError> {
+    if count > u16::max_value() as u64 + 1 {
+        return Err(ModelLoadError::TooManyVertices { count: count.min(usize::max_value() as u64) as usize });
+    }
+    Ok(())
+}
+
+struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+}
+
+impl MeshBuilder {
+    fn new() -> MeshBuilder {
+        MeshBuilder {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tex_coord: Point2<f32>) -> u16 {
+        self.vertices.push(Vertex::new(position, normal, tex_coord));
+        (self.vertices.len() - 1) as u16
+    }
+
+    fn triangle(&mut self, a: u16, b: u16, c: u16) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    fn quad(&mut self, a: u16, b: u16, c: u16, d: u16) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    // Revolves a profile around the Z axis. Profile points go from top to bottom as
+    // (radius, z, radial normal, z normal, v).
+    fn lathe(&mut self, profile: &[(f32, f32, f32, f32, f32)], segments: u32) {
+        let first = self.vertices.len() as u16;
+        let columns = segments as u16 + 1;
+        for &(radius, z, normal_radial, normal_z, v) in profile {
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                // The last column repeats the first one's angle so the seam's positions match exactly.
+                let (sin, cos) = ((segment % segments) as f32 / segments as f32 * 2.0 * PI).sin_cos();
+                self.vertex(
+                    Vector3::new(radius * cos, radius * sin, z),
+                    Vector3::new(normal_radial * cos, normal_radial * sin, normal_z).normalize(),
+                    Point2::new(u, v),
+                );
+            }
+        }
+
+        for ring in 0..profile.len() as u16 - 1 {
+            for segment in 0..segments as u16 {
+                let a = first + ring * columns + segment;
+                let b = a + columns;
+                let (c, d) = (b + 1, a + 1);
+                if profile[ring as usize + 1].0 != 0.0 {
+                    self.triangle(a, b, c);
+                }
+                if profile[ring as usize].0 != 0.0 {
+                    self.triangle(a, c, d);
+                }
+            }
+        }
+    }
+
+    fn disc(&mut self, radius: f32, z: f32, facing_up: bool, segments: u32) {
+        let normal = Vector3::new(0.0, 0.0, if facing_up { 1.0 } else { -1.0 });
+        let center = self.vertex(Vector3::new(0.0, 0.0, z), normal, Point2::new(0.5, 0.5));
+        for segment in 0..=segments {
+            let (sin, cos) = ((segment % segments) as f32 / segments as f32 * 2.0 * PI).sin_cos();
+            let v_sin = if facing_up { sin } else { -sin };
+            self.vertex(
+                Vector3::new(radius * cos, radius * sin, z),
+                normal,
+                Point2::new(0.5 + 0.5 * cos, 0.5 - 0.5 * v_sin),
+            );
+        }
+        for segment in 0..segments as u16 {
+            let (a, b) = (center + 1 + segment, center + 2 + segment);
+            if facing_up {
+                self.triangle(center, a, b);
+            } else {
+                self.triangle(center, b, a);
+            }
+        }
+    }
+
+    fn build(self, name: &str) -> Result<ModelData, ModelLoadError> {
+        check_vertex_count(self.vertices.len() as u64)?;
+        let base_color = [1.0, 1.0, 1.0, 1.0];
+        Ok(ModelData::new(
+            self.vertices,
+            self.indices,
+            Material::new(name.into(), base_color),
+            Texture::solid(base_color),
+        ))
+    }
+}
+
+pub fn cuboid(size: Vector3<f32>) -> Result<ModelData, ModelLoadError> {
+    let half = size * 0.5;
+    let faces = [
+        (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()),
+        (-Vector3::unit_x(), -Vector3::unit_y(), Vector3::unit_z()),
+        (Vector3::unit_y(), -Vector3::unit_x(), Vector3::unit_z()),
+        (-Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_z(), Vector3::unit_x(), Vector3::unit_y()),
+        (-Vector3::unit_z(), Vector3::unit_x(), -Vector3::unit_y()),
+    ];
+
+    let mut builder = MeshBuilder::new();
+    for &(normal, right, up) in &faces {
+        let scale = |v: Vector3<f32>| Vector3::new(v.x * half.x, v.y * half.y, v.z * half.z);
+        let (center, right, up) = (scale(normal), scale(right), scale(up));
+        let a = builder.vertex(center - right - up, normal, Point2::new(0.0, 1.0));
+        let b = builder.vertex(center + right - up, normal, Point2::new(1.0, 1.0));
+        let c = builder.vertex(center + right + up, normal, Point2::new(1.0, 0.0));
+        let d = builder.vertex(center - right + up, normal, Point2::new(0.0, 0.0));
+        builder.quad(a, b, c, d);
+    }
+    builder.build("box")
+}
+
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Result<ModelData, ModelLoadError> {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    check_vertex_count((segments as u64 + 1).saturating_mul(rings as u64 + 1))?;
+    let profile: Vec<_> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            let (sin, cos) = (v * PI).sin_cos();
+            let sin = if ring == 0 || ring == rings { 0.0 } else { sin };
+            (radius * sin, radius * cos, sin, cos, v)
+        })
+        .collect();
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&profile, segments);
+    builder.build("uv_sphere")
+}
+
+pub fn icosphere(radius: f32, subdivisions: u32) -> Result<ModelData, ModelLoadError> {
+    // Every subdivision quadruples the faces. The UV seam adds a few more vertices on top, which
+    // the builder checks.
+    check_vertex_count(4u64.saturating_pow(subdivisions).saturating_mul(10).saturating_add(2))?;
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vector3<f32>> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| Vector3::new(x, y, z).normalize())
+    .collect();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize, positions: &mut Vec<Vector3<f32>>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push((positions[a] + positions[b]).normalize());
+                positions.len() - 1
+            })
+        };
+        triangles = triangles.iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut positions);
+                let bc = midpoint(b, c, &mut positions);
+                let ca = midpoint(c, a, &mut positions);
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let tex_coord = |p: Vector3<f32>| {
+        Point2::new(0.5 + p.y.atan2(p.x) / (2.0 * PI), p.z.max(-1.0).min(1.0).acos() / PI)
+    };
+
+    let mut builder = MeshBuilder::new();
+    let mut shared: HashMap<(usize, bool), u16> = HashMap::new();
+    for triangle in &triangles {
+        let uvs: Vec<Point2<f32>> = triangle.iter().map(|&i| tex_coord(positions[i])).collect();
+        let max_u = uvs.iter().map(|uv| uv.x).fold(0.0, f32::max);
+        let wraps = uvs.iter().any(|uv| max_u - uv.x > 0.5);
+
+        let mut corners = [0u16; 3];
+        for (corner, (&i, uv)) in corners.iter_mut().zip(triangle.iter().zip(uvs)) {
+            let shifted = wraps && max_u - uv.x > 0.5;
+            *corner = *shared.entry((i, shifted)).or_insert_with(|| {
+                let u = if shifted { uv.x + 1.0 } else { uv.x };
+                builder.vertex(positions[i] * radius, positions[i], Point2::new(u, uv.y))
+            });
+        }
+        builder.triangle(corners[0], corners[1], corners[2]);
+    }
+    builder.build("icosphere")
+}
+
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> Result<ModelData, ModelLoadError> {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    check_vertex_count((columns as u64 + 1).saturating_mul(rows as u64 + 1))?;
+    let mut builder = MeshBuilder::new();
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let (u, v) = (column as f32 / columns as f32, row as f32 / rows as f32);
+            builder.vertex(
+                Vector3::new((u - 0.5) * width, (v - 0.5) * depth, 0.0),
+                Vector3::unit_z(),
+                Point2::new(u, 1.0 - v),
+            );
+        }
+    }
+
+    let stride = columns as u16 + 1;
+    for row in 0..rows as u16 {
+        for column in 0..columns as u16 {
+            let a = row * stride + column;
+            builder.quad(a, a + 1, a + 1 + stride, a + stride);
+        }
+    }
+    builder.build("plane")
+}
+
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Result<ModelData, ModelLoadError> {
+    let segments = segments.max(3);
+    check_vertex_count(4 * segments as u64 + 6)?;
+    let half = height * 0.5;
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&[(radius, half, 1.0, 0.0, 0.0), (radius, -half, 1.0, 0.0, 1.0)], segments);
+    builder.disc(radius, half, true, segments);
+    builder.disc(radius, -half, false, segments);
+    builder.build("cylinder")
+}
+
+pub fn cone(radius: f32, height: f32, segments: u32) -> Result<ModelData, ModelLoadError> {
+    let segments = segments.max(3);
+    check_vertex_count(3 * segments as u64 + 4)?;
+    let half = height * 0.5;
+    let slant = Vector3::new(height, 0.0, radius).normalize();
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&[(0.0, half, slant.x, slant.z, 0.0), (radius, -half, slant.x, slant.z, 1.0)], segments);
+    builder.disc(radius, -half, false, segments);
+    builder.build("cone")
+}
+
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Result<ModelData, ModelLoadError> {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    check_vertex_count((major_segments as u64 + 1).saturating_mul(minor_segments as u64 + 1))?;
+    let profile: Vec<_> = (0..=minor_segments)
+        .map(|segment| {
+            let v = segment as f32 / minor_segments as f32;
+            let (sin, cos) = ((segment % minor_segments) as f32 / minor_segments as f32 * 2.0 * PI).sin_cos();
+            (major_radius + minor_radius * cos, -minor_radius * sin, cos, -sin, v)
+        })
+        .collect();
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&profile, major_segments);
+    builder.build("torus")
+}
+
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Result<ModelData, ModelLoadError> {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    check_vertex_count((2 * rings as u64 + 2).saturating_mul(segments as u64 + 1))?;
+    let half = height * 0.5;
+    let length = PI * radius + height;
+    let mut profile = Vec::with_capacity(rings as usize * 2 + 2);
+    for &(center, first_angle) in &[(half, 0.0), (-half, PI * 0.5)] {
+        for ring in 0..=rings {
+            let angle = first_angle + ring as f32 / rings as f32 * PI * 0.5;
+            let (sin, cos) = angle.sin_cos();
+            let sin = if angle == 0.0 || angle == PI { 0.0 } else { sin };
+            let z = center + radius * cos;
+            profile.push((radius * sin, z, sin, cos, (half + radius - z) / length));
+        }
+    }
+
+    let mut builder = MeshBuilder::new();
+    builder.lathe(&profile, segments);
+    builder.build("capsule")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shapes() -> Vec<(&'static str, ModelData)> {
+        vec![
+            ("cuboid", cuboid(Vector3::new(1.0, 2.0, 3.0))),
+            ("uv_sphere", uv_sphere(0.5, 24, 12)),
+            ("icosphere", icosphere(0.5, 2)),
+            ("plane", plane(1.0, 2.0, 4, 3)),
+            ("cylinder", cylinder(0.5, 1.0, 24)),
+            ("cone", cone(0.5, 1.0, 24)),
+            ("torus", torus(0.4, 0.1, 24, 12)),
+            ("capsule", capsule(0.3, 1.0, 24, 6)),
+        ]
+        .into_iter()
+        .map(|(name, shape)| (name, shape.unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn windings_agree_with_vertex_normals() {
+        for (name, shape) in shapes() {
+            for triangle in shape.indices.chunks(3) {
+                let vertex = |i: usize| &shape.vertices[triangle[i] as usize];
+                let (a, b, c) = (vertex(0), vertex(1), vertex(2));
+                let face = (b.position - a.position).cross(c.position - a.position);
+                assert!(face.magnitude() > 1e-6, "{} has a degenerate triangle {:?}", name, triangle);
+                let face = face.normalize();
+                for v in &[a, b, c] {
+                    assert!(face.dot(v.normal) > 0.1, "{} triangle {:?} faces away from its normals", name, triangle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tex_coords_stay_in_the_unit_square() {
+        for (name, shape) in shapes() {
+            for vertex in &shape.vertices {
+                let uv = vertex.tex_coord;
+                // Icosphere triangles across the seam continue past u = 1, which the repeating
+                // sampler wraps around.
+                let max_u = if name == "icosphere" { 1.25 } else { 1.0 };
+                assert!(uv.x >= 0.0 && uv.x <= max_u && uv.y >= 0.0 && uv.y <= 1.0, "{} has UV {:?}", name, uv);
+            }
+        }
+    }
+
+    #[test]
+    fn segment_counts_stop_at_the_vertex_limit() {
+        let vertices = |shape: Result<ModelData, ModelLoadError>| match shape {
+            Ok(shape) => Ok(shape.vertices.len()),
+            Err(ModelLoadError::TooManyVertices { count }) => Err(count),
+            Err(err) => panic!("unexpected error {}", err),
+        };
+
+        // Lathed shapes have a column per segment plus one for the seam and a row per ring plus one.
+        assert_eq!(vertices(uv_sphere(1.0, 255, 255)), Ok(65536));
+        assert_eq!(vertices(uv_sphere(1.0, 256, 255)), Err(257 * 256));
+        assert_eq!(vertices(uv_sphere(1.0, 255, 256)), Err(256 * 257));
+        assert_eq!(vertices(torus(1.0, 0.5, 255, 255)), Ok(65536));
+        assert_eq!(vertices(torus(1.0, 0.5, 256, 255)), Err(257 * 256));
+        assert_eq!(vertices(torus(1.0, 0.5, 255, 256)), Err(256 * 257));
+        assert_eq!(vertices(plane(1.0, 1.0, 255, 255)), Ok(65536));
+        assert_eq!(vertices(plane(1.0, 1.0, 256, 255)), Err(257 * 256));
+        assert_eq!(vertices(plane(1.0, 1.0, 255, 256)), Err(256 * 257));
+        // Capsules have two rows per ring, one for each hemisphere.
+        assert_eq!(vertices(capsule(1.0, 1.0, 255, 127)), Ok(65536));
+        assert_eq!(vertices(capsule(1.0, 1.0, 256, 127)), Err(257 * 256));
+        assert_eq!(vertices(capsule(1.0, 1.0, 255, 128)), Err(256 * 258));
+
+        // Two rows for the side and a center and rim for each cap.
+        assert_eq!(vertices(cylinder(1.0, 1.0, 16382)), Ok(65534));
+        assert_eq!(vertices(cylinder(1.0, 1.0, 16383)), Err(65538));
+        assert_eq!(vertices(cone(1.0, 1.0, 21844)), Ok(65536));
+        assert_eq!(vertices(cone(1.0, 1.0, 21845)), Err(65539));
+
+        // Six subdivisions make 40962 vertices before the seam, seven 163842.
+        assert!(vertices(icosphere(1.0, 6)).unwrap() <= 65536);
+        assert_eq!(vertices(icosphere(1.0, 7)), Err(163_842));
+    }
+}
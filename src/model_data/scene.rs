@@ -0,0 +1,300 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Transform, Vector3};
+use gltf::json::Value;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+use super::extensions::LIGHTS_PUNCTUAL;
+use super::source::GltfSource;
+use super::ModelLoadError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        yfov: Rad<f32>,
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneCamera {
+    pub name: String,
+    pub projection: Projection,
+    pub transform: Matrix4<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot {
+        inner_cone_angle: Rad<f32>,
+        outer_cone_angle: Rad<f32>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SceneLight {
+    pub name: String,
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: Option<f32>,
+    pub transform: Matrix4<f32>,
+}
+
+// glTF cameras and lights look down their local -Z axis with +Y up.
+fn position(transform: &Matrix4<f32>) -> Point3<f32> {
+    transform.transform_point(Point3::new(0.0, 0.0, 0.0))
+}
+
+fn direction(transform: &Matrix4<f32>) -> Vector3<f32> {
+    transform.transform_vector(-Vector3::unit_z()).normalize()
+}
+
+fn up(transform: &Matrix4<f32>) -> Vector3<f32> {
+    transform.transform_vector(Vector3::unit_y()).normalize()
+}
+
+impl SceneCamera {
+    pub fn position(&self) -> Point3<f32> {
+        position(&self.transform)
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        direction(&self.transform)
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        up(&self.transform)
+    }
+}
+
+impl SceneLight {
+    pub fn position(&self) -> Point3<f32> {
+        position(&self.transform)
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        direction(&self.transform)
+    }
+}
+
+pub fn load_cameras(document: &gltf::Document, transforms: &[Matrix4<f32>]) -> Vec<SceneCamera> {
+    let mut cameras = Vec::new();
+    for node in document.nodes() {
+        let camera = match node.camera() {
+            Some(camera) => camera,
+            None => continue,
+        };
+        let projection = match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => Projection::Perspective {
+                yfov: Rad(perspective.yfov()),
+                aspect_ratio: perspective.aspect_ratio(),
+                znear: perspective.znear(),
+                zfar: perspective.zfar(),
+            },
+            gltf::camera::Projection::Orthographic(orthographic) => Projection::Orthographic {
+                xmag: orthographic.xmag(),
+                ymag: orthographic.ymag(),
+                znear: orthographic.znear(),
+                zfar: orthographic.zfar(),
+            },
+        };
+        cameras.push(SceneCamera {
+            name: camera.name().unwrap_or("<unknown>").to_string(),
+            projection,
+            transform: transforms[node.index()],
+        });
+    }
+    cameras
+}
+
+pub fn load_lights(source: &GltfSource, transforms: &[Matrix4<f32>]) -> Result<Vec<SceneLight>, ModelLoadError> {
+    if !source.document.extensions_used().any(|extension| extension == LIGHTS_PUNCTUAL) {
+        return Ok(Vec::new());
+    }
+
+    // gltf 0.11 has no KHR_lights_punctual support and drops the node extensions, so the lights
+    // are read from the JSON the document was parsed from. That's the GLB chunk for .glb files,
+    // the file is never read again.
+    let json = &source.json;
+    let definitions = match json["extensions"][LIGHTS_PUNCTUAL]["lights"].as_array() {
+        Some(definitions) => definitions.iter()
+            .enumerate()
+            .map(|(index, definition)| light_definition(index, definition))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+
+    let nodes = json["nodes"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+    let mut lights = Vec::new();
+    for (node, node_json) in nodes.iter().enumerate() {
+        let light = &node_json["extensions"][LIGHTS_PUNCTUAL]["light"];
+        if light.is_null() {
+            continue;
+        }
+        let index = light.as_u64().ok_or_else(|| ModelLoadError::InvalidDocument {
+            path: format!("nodes[{}].extensions.{}.light", node, LIGHTS_PUNCTUAL),
+            reason: "light must be an index",
+        })?;
+        let mut scene_light = definitions.get(index as usize)
+            .cloned()
+            .ok_or(ModelLoadError::InvalidLight { light: index as usize, reason: "node refers to a missing light" })?;
+        scene_light.transform = transforms.get(node).cloned().unwrap_or_else(Matrix4::identity);
+        lights.push(scene_light);
+    }
+    Ok(lights)
+}
+
+fn light_definition(index: usize, light: &Value) -> Result<SceneLight, ModelLoadError> {
+    let invalid = |reason| ModelLoadError::InvalidLight { light: index, reason };
+    let number = |value: &Value, default: f32| match value {
+        Value::Null => Ok(default),
+        value => value.as_f64().map(|value| value as f32).ok_or_else(|| invalid("expected a number")),
+    };
+
+    let kind = match light["type"].as_str() {
+        Some("directional") => LightKind::Directional,
+        Some("point") => LightKind::Point,
+        Some("spot") => {
+            let inner_cone_angle = number(&light["spot"]["innerConeAngle"], 0.0)?;
+            let outer_cone_angle = number(&light["spot"]["outerConeAngle"], FRAC_PI_4)?;
+            if inner_cone_angle < 0.0 || outer_cone_angle <= inner_cone_angle {
+                return Err(invalid("spot cone angles are out of order"));
+            }
+            if outer_cone_angle > FRAC_PI_2 {
+                return Err(invalid("spot outer cone angle is wider than pi / 2"));
+            }
+            LightKind::Spot {
+                inner_cone_angle: Rad(inner_cone_angle),
+                outer_cone_angle: Rad(outer_cone_angle),
+            }
+        }
+        _ => return Err(invalid("unknown light type")),
+    };
+
+    let color = match &light["color"] {
+        Value::Null => [1.0, 1.0, 1.0],
+        Value::Array(color) if color.len() == 3 => [number(&color[0], 0.0)?, number(&color[1], 0.0)?, number(&color[2], 0.0)?],
+        _ => return Err(invalid("color must have three components")),
+    };
+    let intensity = number(&light["intensity"], 1.0)?;
+    let range = match &light["range"] {
+        Value::Null => None,
+        range => Some(number(range, 0.0)?).filter(|&range| range > 0.0),
+    };
+
+    Ok(SceneLight {
+        name: light["name"].as_str().unwrap_or("<unknown>").to_string(),
+        kind,
+        color,
+        intensity,
+        range,
+        transform: Matrix4::identity(),
+    })
+}
+
+// Every node may have at most one parent and the hierarchy must not loop back on itself.
+pub fn node_parents(document: &gltf::Document) -> Result<Vec<Option<usize>>, ModelLoadError> {
+    let mut parents = vec![None; document.nodes().len()];
+    for node in document.nodes() {
+        for child in node.children() {
+            if parents[child.index()].replace(node.index()).is_some() {
+                return Err(ModelLoadError::InvalidNodeHierarchy { node: child.index() });
+            }
+        }
+    }
+
+    // Nodes in a cycle can't be reached from any root.
+    let mut reached = vec![false; parents.len()];
+    let mut stack: Vec<usize> = (0..parents.len()).filter(|&node| parents[node].is_none()).collect();
+    let nodes: Vec<gltf::Node> = document.nodes().collect();
+    while let Some(node) = stack.pop() {
+        reached[node] = true;
+        stack.extend(nodes[node].children().map(|child| child.index()));
+    }
+    match reached.iter().position(|&reached| !reached) {
+        Some(node) => Err(ModelLoadError::InvalidNodeHierarchy { node }),
+        None => Ok(parents),
+    }
+}
+
+pub fn world_transforms(document: &gltf::Document, parents: &[Option<usize>]) -> Vec<Matrix4<f32>> {
+    let mut transforms = vec![Matrix4::identity(); parents.len()];
+    let nodes: Vec<gltf::Node> = document.nodes().collect();
+    let mut stack: Vec<(usize, Matrix4<f32>)> = (0..nodes.len())
+        .filter(|&node| parents[node].is_none())
+        .map(|node| (node, Matrix4::identity()))
+        .collect();
+    while let Some((node, parent)) = stack.pop() {
+        let transform = parent * Matrix4::from(nodes[node].transform().matrix());
+        transforms[node] = transform;
+        stack.extend(nodes[node].children().map(|child| (child.index(), transform)));
+    }
+    transforms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::ModelData;
+
+    fn lights(path: &str) -> Result<Vec<SceneLight>, ModelLoadError> {
+        ModelData::load_uncached(path).map(|model| model.lights)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn imports_lights_attached_to_nodes() {
+        let lights = lights("assets/corpus/lights.gltf").unwrap();
+        let names: Vec<_> = lights.iter().map(|light| light.name.as_str()).collect();
+        assert_eq!(names, vec!["sun", "bulb", "torch", "flood"]);
+
+        let sun = &lights[0];
+        assert_eq!(sun.kind, LightKind::Directional);
+        assert_eq!((sun.color, sun.intensity, sun.range), ([1.0, 0.9, 0.8], 3.0, None));
+        // The node is turned 45 degrees about X, so the light leans from -Z towards -Y.
+        let direction = sun.direction();
+        assert_close(direction.y, -FRAC_PI_4.sin());
+        assert_close(direction.z, -FRAC_PI_4.cos());
+
+        let bulb = &lights[1];
+        assert_eq!(bulb.kind, LightKind::Point);
+        assert_eq!((bulb.color, bulb.intensity, bulb.range), ([1.0; 3], 40.0, Some(12.5)));
+        assert_eq!(bulb.position(), Point3::new(1.0, 2.0, 3.0));
+
+        assert_eq!(lights[2].kind, LightKind::Spot { inner_cone_angle: Rad(0.25), outer_cone_angle: Rad(0.5) });
+        assert_eq!((lights[2].intensity, lights[2].range), (80.0, None));
+        assert_eq!(lights[3].kind, LightKind::Spot { inner_cone_angle: Rad(0.0), outer_cone_angle: Rad(FRAC_PI_4) });
+    }
+
+    #[test]
+    fn rejects_spot_cones_wider_than_a_hemisphere() {
+        match lights("assets/corpus/light-wide-cone.gltf") {
+            Err(ModelLoadError::InvalidLight { light, reason }) => {
+                assert_eq!((light, reason), (0, "spot outer cone angle is wider than pi / 2"));
+            }
+            result => panic!("expected InvalidLight, got {:?}", result.map(|lights| lights.len())),
+        }
+    }
+
+    #[test]
+    fn reports_the_missing_light_a_node_refers_to() {
+        match lights("assets/corpus/light-missing.gltf") {
+            Err(ModelLoadError::InvalidLight { light, reason }) => {
+                assert_eq!((light, reason), (3, "node refers to a missing light"));
+            }
+            result => panic!("expected InvalidLight, got {:?}", result.map(|lights| lights.len())),
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use cgmath::{InnerSpace, Vector3};
+use hashbrown::hash_map::HashMap;
+use std::cmp::Ordering;
+use std::ops::AddAssign;
+
+use super::{ModelData, Vertex};
+
+// The cosine of the largest angle a collapse may turn a triangle from its original facing.
+const MIN_FACING: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LodSettings {
+    pub triangle_ratio: f32,
+    pub screen_size: f32,
+}
+
+pub const DEFAULT_LODS: [LodSettings; 3] = [
+    LodSettings { triangle_ratio: 0.5, screen_size: 0.5 },
+    LodSettings { triangle_ratio: 0.25, screen_size: 0.25 },
+    LodSettings { triangle_ratio: 0.125, screen_size: 0.1 },
+];
+
+#[derive(Debug, Clone)]
+pub struct Lod {
+    pub indices: Vec<u16>,
+    pub screen_size: f32,
+}
+
+impl ModelData {
+    pub fn generate_lods(&mut self, settings: &[LodSettings]) {
+        self.lods = settings.iter()
+            .map(|lod| {
+                let target = ((self.indices.len() / 3) as f32 * lod.triangle_ratio) as usize * 3;
+                let indices = simplify(&self.vertices, &self.indices, target);
+                Lod {
+                    indices: super::optimize::optimize_vertex_cache(&indices, self.vertices.len()),
+                    screen_size: lod.screen_size,
+                }
+            })
+            .filter(|lod| !lod.indices.is_empty())
+            .collect();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: Vector3<f64>, d: f64) -> Quadric {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d])
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+impl AddAssign for Quadric {
+    fn add_assign(&mut self, other: Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+}
+
+fn position(vertex: &Vertex) -> Vector3<f64> {
+    Vector3::new(vertex.position.x as f64, vertex.position.y as f64, vertex.position.z as f64)
+}
+
+fn resolve(collapse: &mut [u16], vertex: u16) -> u16 {
+    let mut root = vertex;
+    while collapse[root as usize] != root {
+        root = collapse[root as usize];
+    }
+    let mut current = vertex;
+    while collapse[current as usize] != root {
+        let next = collapse[current as usize];
+        collapse[current as usize] = root;
+        current = next;
+    }
+    root
+}
+
+// Vertices that only differ in their normals or UVs, at hard edges and UV seams, are welded into
+// one position so the seams can be simplified like the rest of the surface.
+fn weld_positions(vertices: &[Vertex]) -> (Vec<u16>, usize) {
+    let mut position_ids: HashMap<[u32; 3], u16> = HashMap::new();
+    let ids = vertices.iter()
+        .map(|vertex| {
+            let key = [vertex.position.x.to_bits(), vertex.position.y.to_bits(), vertex.position.z.to_bits()];
+            let next = position_ids.len() as u16;
+            *position_ids.entry(key).or_insert(next)
+        })
+        .collect();
+    (ids, position_ids.len())
+}
+
+// Positions on an edge that only one triangle uses, which would tear the mesh open if they moved.
+fn border_positions(triangles: &[[u16; 3]], position_count: usize) -> Vec<bool> {
+    let mut edge_uses: HashMap<(u16, u16), usize> = HashMap::new();
+    for triangle in triangles {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            *edge_uses.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+
+    let mut border = vec![false; position_count];
+    for (&(a, b), &uses) in &edge_uses {
+        if uses == 1 {
+            border[a as usize] = true;
+            border[b as usize] = true;
+        }
+    }
+    border
+}
+
+fn triangle_normal(a: Vector3<f64>, b: Vector3<f64>, c: Vector3<f64>) -> Vector3<f64> {
+    (b - a).cross(c - a)
+}
+
+// Collapses edges between welded positions. When a position moves, each of its vertices is
+// replaced with a vertex at the target that it shares an edge with, which keeps the attributes on
+// its side of a seam, or else with the target vertex whose normal is closest.
+pub fn simplify(vertices: &[Vertex], indices: &[u16], target_index_count: usize) -> Vec<u16> {
+    let (ids, position_count) = weld_positions(vertices);
+    let mut positions = vec![Vector3::new(0.0, 0.0, 0.0); position_count];
+    let mut position_vertices: Vec<Vec<u16>> = vec![Vec::new(); position_count];
+    for (i, (vertex, &id)) in vertices.iter().zip(&ids).enumerate() {
+        positions[id as usize] = position(vertex);
+        position_vertices[id as usize].push(i as u16);
+    }
+
+    let mut neighbours: Vec<Vec<u16>> = vec![Vec::new(); vertices.len()];
+    for triangle in indices.chunks(3) {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            neighbours[a as usize].push(b);
+            neighbours[b as usize].push(a);
+        }
+    }
+
+    let corners: Vec<[u16; 3]> = indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+    let mut triangles: Vec<[u16; 3]> = corners.iter()
+        .map(|t| [ids[t[0] as usize], ids[t[1] as usize], ids[t[2] as usize]])
+        .collect();
+    let locked = border_positions(&triangles, position_count);
+
+    let mut quadrics = vec![Quadric::default(); position_count];
+    for triangle in &triangles {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let normal = triangle_normal(positions[a], positions[b], positions[c]);
+        if normal.magnitude2() == 0.0 {
+            continue;
+        }
+        let normal = normal.normalize();
+        let quadric = Quadric::from_plane(normal, -normal.dot(positions[a]));
+        for &i in &[a, b, c] {
+            quadrics[i] += quadric;
+        }
+    }
+
+    // Collapses are also checked against the triangles' original facing, so a triangle can't turn
+    // over a little at a time or end up standing on its edge.
+    let original_normals: Vec<Vector3<f64>> = triangles.iter()
+        .map(|t| triangle_normal(positions[t[0] as usize], positions[t[1] as usize], positions[t[2] as usize]))
+        .collect();
+
+    let mut collapse: Vec<u16> = (0..position_count).map(|i| i as u16).collect();
+    let mut vertex_collapse: Vec<u16> = (0..vertices.len()).map(|i| i as u16).collect();
+    let mut alive = vec![true; triangles.len()];
+
+    loop {
+        for (triangle, alive) in triangles.iter_mut().zip(alive.iter_mut()) {
+            for corner in triangle.iter_mut() {
+                *corner = resolve(&mut collapse, *corner);
+            }
+            *alive = triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[0] != triangle[2];
+        }
+        let mut remaining = alive.iter().filter(|&&alive| alive).count();
+        if remaining * 3 <= target_index_count {
+            break;
+        }
+
+        let mut position_triangles: Vec<Vec<usize>> = vec![Vec::new(); position_count];
+        for (i, triangle) in triangles.iter().enumerate().filter(|&(i, _)| alive[i]) {
+            for &corner in triangle {
+                position_triangles[corner as usize].push(i);
+            }
+        }
+
+        let mut candidates: Vec<(f64, u16, u16)> = Vec::new();
+        for (_, triangle) in triangles.iter().enumerate().filter(|&(i, _)| alive[i]) {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                for &(from, to) in &[(a, b), (b, a)] {
+                    if locked[from as usize] {
+                        continue;
+                    }
+                    let mut quadric = quadrics[from as usize];
+                    quadric += quadrics[to as usize];
+                    candidates.push((quadric.error(positions[to as usize]).max(0.0), from, to));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| {
+            a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal)
+                .then(a.1.cmp(&b.1))
+                .then(a.2.cmp(&b.2))
+        });
+
+        let mut touched = vec![false; position_count];
+        let mut collapsed_any = false;
+
+        for &(_, from, to) in &candidates {
+            if remaining * 3 <= target_index_count {
+                break;
+            }
+            if touched[from as usize] || touched[to as usize] {
+                continue;
+            }
+
+            let flips = position_triangles[from as usize].iter().any(|&t| {
+                let triangle = triangles[t];
+                if triangle.contains(&to) {
+                    return false;
+                }
+                let corners: Vec<Vector3<f64>> = triangle.iter().map(|&c| positions[c as usize]).collect();
+                let moved: Vec<Vector3<f64>> = triangle.iter()
+                    .map(|&c| if c == from { positions[to as usize] } else { positions[c as usize] })
+                    .collect();
+                let before = triangle_normal(corners[0], corners[1], corners[2]);
+                let after = triangle_normal(moved[0], moved[1], moved[2]);
+                let original = original_normals[t];
+                before.dot(after) <= 0.0 || original.dot(after) <= MIN_FACING * original.magnitude() * after.magnitude()
+            });
+            if flips {
+                continue;
+            }
+
+            collapse[from as usize] = to;
+            let from_quadric = quadrics[from as usize];
+            quadrics[to as usize] += from_quadric;
+            collapsed_any = true;
+
+            for vertex in std::mem::replace(&mut position_vertices[from as usize], Vec::new()) {
+                let linked: Vec<u16> = neighbours[vertex as usize].iter()
+                    .map(|&neighbour| resolve(&mut vertex_collapse, neighbour))
+                    .collect();
+                let targets = &position_vertices[to as usize];
+                let replacement = match targets.iter().find(|target| linked.contains(target)) {
+                    Some(&target) => target,
+                    None => {
+                        let normal = vertices[vertex as usize].normal;
+                        *targets.iter()
+                            .max_by(|&&a, &&b| {
+                                let a = vertices[a as usize].normal.dot(normal);
+                                let b = vertices[b as usize].normal.dot(normal);
+                                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+                            })
+                            .unwrap()
+                    }
+                };
+                vertex_collapse[vertex as usize] = replacement;
+                let moved = std::mem::replace(&mut neighbours[vertex as usize], Vec::new());
+                neighbours[replacement as usize].extend(moved);
+            }
+
+            for &t in &position_triangles[from as usize] {
+                if triangles[t].contains(&to) {
+                    remaining -= 1;
+                }
+                for &corner in &triangles[t] {
+                    touched[corner as usize] = true;
+                }
+            }
+        }
+
+        if !collapsed_any {
+            break;
+        }
+    }
+
+    let mut indices = Vec::with_capacity(target_index_count);
+    for (triangle, _) in corners.iter().zip(&alive).filter(|&(_, &alive)| alive) {
+        for &corner in triangle {
+            indices.push(resolve(&mut vertex_collapse, corner));
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::primitives;
+
+    fn closed_shapes() -> Vec<ModelData> {
+        vec![
+            primitives::uv_sphere(1.0, 24, 12).unwrap(),
+            primitives::torus(1.0, 0.3, 32, 12).unwrap(),
+            primitives::capsule(0.5, 1.0, 16, 4).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn reaches_the_target_triangle_count() {
+        for shape in closed_shapes() {
+            let triangles = shape.indices.len() / 3;
+            for &ratio in &[0.5, 0.25, 0.1] {
+                let target = (triangles as f32 * ratio) as usize * 3;
+                let simplified = simplify(&shape.vertices, &shape.indices, target);
+                // A collapse removes the two triangles around its edge, so it can overshoot by one.
+                let count = simplified.len();
+                assert!(count <= target && count + 6 >= target, "{} indices for a target of {}", count, target);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_border_vertices_in_place() {
+        let plane = primitives::plane(2.0, 1.0, 8, 8).unwrap();
+        let simplified = simplify(&plane.vertices, &plane.indices, plane.indices.len() / 2);
+        assert!(simplified.len() < plane.indices.len());
+
+        for (i, vertex) in plane.vertices.iter().enumerate() {
+            let p = vertex.position;
+            if (p.x.abs() - 1.0).abs() < 1e-6 || (p.y.abs() - 0.5).abs() < 1e-6 {
+                assert!(simplified.contains(&(i as u16)), "border vertex {} at {:?} moved", i, p);
+            }
+        }
+        // Nothing moved off the plane's outline either, so the simplified plane covers the same area.
+        let area: f32 = simplified.chunks(3)
+            .map(|t| {
+                let p = |i: usize| plane.vertices[t[i] as usize].position;
+                (p(1) - p(0)).cross(p(2) - p(0)).z / 2.0
+            })
+            .sum();
+        assert!((area - 2.0).abs() < 1e-4, "area {}", area);
+    }
+
+    #[test]
+    fn does_not_flip_triangles() {
+        for shape in closed_shapes() {
+            let target = shape.indices.len() / 10 / 3 * 3;
+            let simplified = simplify(&shape.vertices, &shape.indices, target);
+            for t in simplified.chunks(3) {
+                let vertex = |i: usize| &shape.vertices[t[i] as usize];
+                let face = (vertex(1).position - vertex(0).position).cross(vertex(2).position - vertex(0).position);
+                let normal = vertex(0).normal + vertex(1).normal + vertex(2).normal;
+                assert!(face.dot(normal) > 0.0, "triangle {:?} is flipped", t);
+            }
+        }
+    }
+
+    #[test]
+    fn simplifies_meshes_with_every_16_bit_index() {
+        let plane = primitives::plane(1.0, 1.0, 255, 255).unwrap();
+        assert_eq!(plane.vertices.len(), 65536);
+        let simplified = simplify(&plane.vertices, &plane.indices, plane.indices.len() - 6);
+        assert_eq!(simplified.len(), plane.indices.len() - 6);
+    }
+
+    #[test]
+    fn models_loaded_for_rendering_have_lods() {
+        let mut source = String::new();
+        for y in 0..=8 {
+            for x in 0..=8 {
+                source += &format!("v {} {} 0\n", x, y);
+            }
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                let i = y * 9 + x + 1;
+                source += &format!("f {} {} {} {}\n", i, i + 1, i + 10, i + 9);
+            }
+        }
+        let path = std::env::temp_dir().join("voids-simplify-grid.obj");
+        std::fs::write(&path, source).unwrap();
+
+        let grid = ModelData::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(grid.lods.len(), DEFAULT_LODS.len());
+        for lod in &grid.lods {
+            assert!(lod.indices.len() < grid.indices.len());
+        }
+    }
+}
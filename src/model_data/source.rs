@@ -0,0 +1,324 @@
+use gltf::json::validation::Checked;
+use gltf::json::{camera, Root, Value};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use super::extensions::{self, MESHOPT_COMPRESSION};
+use super::meshopt::{self, Filter, Mode};
+use super::ModelLoadError;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const GLB_HEADER_SIZE: usize = 12;
+const CHUNK_HEADER_SIZE: usize = 8;
+const JSON_CHUNK: u32 = 0x4E4F_534A;
+const BIN_CHUNK: u32 = 0x004E_4942;
+
+pub struct Glb<'a> {
+    pub json: &'a [u8],
+    pub bin: Option<&'a [u8]>,
+}
+
+// A glTF document with all of its buffers loaded and bounds-checked against their declared lengths.
+// Compressed buffer views are decoded up front. The raw JSON is kept for extensions that gltf 0.11
+// doesn't know about.
+pub struct GltfSource {
+    pub document: gltf::Document,
+    pub json: Value,
+    buffers: Vec<Vec<u8>>,
+    decoded_views: Vec<Option<Vec<u8>>>,
+    base: PathBuf,
+}
+
+impl GltfSource {
+    pub fn load(path: &Path) -> Result<GltfSource, ModelLoadError> {
+        let bytes = ::std::fs::read(path)?;
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let (json, bin) = if bytes.starts_with(GLB_MAGIC) {
+            let glb = parse_glb(&bytes)?;
+            (glb.json.to_vec(), glb.bin.map(<[u8]>::to_vec))
+        } else {
+            (bytes, None)
+        };
+
+        let root: Root = gltf::json::deserialize::from_slice(&json).map_err(gltf::Error::Deserialize)?;
+        check_root(&root)?;
+        extensions::check_required(&root)?;
+        let document = gltf::Document::from_json(root)?;
+        let json = gltf::json::deserialize::from_slice(&json).map_err(gltf::Error::Deserialize)?;
+
+        let mut source = GltfSource {
+            document,
+            json,
+            buffers: Vec::new(),
+            decoded_views: Vec::new(),
+            base,
+        };
+        let mut bin = bin;
+        for buffer in source.document.buffers() {
+            let invalid = |reason| ModelLoadError::InvalidBuffer { buffer: buffer.index(), reason };
+            // Fallback buffers for compressed views usually have no data at all, and are never read.
+            if source.json["buffers"][buffer.index()]["extensions"][MESHOPT_COMPRESSION]["fallback"] == Value::Bool(true) {
+                source.buffers.push(Vec::new());
+                continue;
+            }
+            let mut data = match buffer.source() {
+                gltf::buffer::Source::Bin => bin.take().ok_or_else(|| invalid("binary chunk is missing"))?,
+                gltf::buffer::Source::Uri(uri) => source.read_uri(uri)?.0.into_owned(),
+            };
+            if data.len() < buffer.length() {
+                return Err(invalid("data is shorter than the declared length"));
+            }
+            data.truncate(buffer.length());
+            source.buffers.push(data);
+        }
+
+        for view in source.document.views() {
+            let decoded = source.decode_view(&view)?;
+            source.decoded_views.push(decoded);
+        }
+        Ok(source)
+    }
+
+    fn decode_view(&self, view: &gltf::buffer::View) -> Result<Option<Vec<u8>>, ModelLoadError> {
+        let json = &self.json["bufferViews"][view.index()]["extensions"][MESHOPT_COMPRESSION];
+        if json.is_null() {
+            return Ok(None);
+        }
+        let invalid = |reason| ModelLoadError::InvalidCompressedView { view: view.index(), reason };
+        let number = |field: &str| json[field].as_u64().map(|value| value as usize);
+
+        let buffer = number("buffer")
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or_else(|| invalid("compressed data refers to a missing buffer"))?;
+        let offset = match &json["byteOffset"] {
+            Value::Null => Some(0),
+            _ => number("byteOffset"),
+        };
+        let data = offset
+            .and_then(|offset| Some(offset..offset.checked_add(number("byteLength")?)?))
+            .and_then(|range| buffer.get(range))
+            .ok_or_else(|| invalid("compressed data is out of the buffer's range"))?;
+        let stride = number("byteStride").ok_or_else(|| invalid("byte stride is missing"))?;
+        let count = number("count").ok_or_else(|| invalid("element count is missing"))?;
+        let mode = json["mode"].as_str().and_then(Mode::from_name).ok_or_else(|| invalid("unknown compression mode"))?;
+        let filter = match &json["filter"] {
+            Value::Null => Some(Filter::None),
+            filter => filter.as_str().and_then(Filter::from_name),
+        }.ok_or_else(|| invalid("unknown compression filter"))?;
+        if count.checked_mul(stride) != Some(view.length()) {
+            return Err(invalid("decoded size doesn't match the buffer view length"));
+        }
+
+        meshopt::decode(data, count, stride, mode, filter).map(Some).map_err(invalid)
+    }
+
+    pub fn buffer(&self, index: usize) -> &[u8] {
+        &self.buffers[index]
+    }
+
+    pub fn view(&self, view: &gltf::buffer::View) -> Result<&[u8], ModelLoadError> {
+        if let Some(decoded) = &self.decoded_views[view.index()] {
+            return Ok(decoded);
+        }
+        let buffer = self.buffer(view.buffer().index());
+        view.offset()
+            .checked_add(view.length())
+            .and_then(|end| buffer.get(view.offset()..end))
+            .ok_or_else(|| ModelLoadError::BufferViewOutOfRange { view: view.index() })
+    }
+
+    pub fn image(&self, image: &gltf::Image) -> Result<(Cow<[u8]>, Option<String>), ModelLoadError> {
+        match image.source() {
+            gltf::image::Source::View { view, mime_type } => {
+                Ok((Cow::Borrowed(self.view(&view)?), Some(mime_type.to_string())))
+            }
+            gltf::image::Source::Uri { uri, mime_type } => {
+                let (bytes, uri_mime_type) = self.read_uri(uri)?;
+                Ok((bytes, mime_type.map(str::to_string).or(uri_mime_type)))
+            }
+        }
+    }
+
+    // Reads a data URI or a file relative to the glTF file, guessing the mime type from either.
+    fn read_uri(&self, uri: &str) -> Result<(Cow<[u8]>, Option<String>), ModelLoadError> {
+        let invalid = |reason| ModelLoadError::InvalidUri { uri: truncated(uri), reason };
+        if uri.starts_with("data:") {
+            let comma = uri.find(',').ok_or_else(|| invalid("data URI has no payload"))?;
+            let header = &uri["data:".len()..comma];
+            if !header.ends_with(";base64") {
+                return Err(invalid("only base64 data URIs are supported"));
+            }
+            let mime_type = &header[..header.len() - ";base64".len()];
+            let bytes = decode_base64(&uri[comma + 1..]).ok_or_else(|| invalid("data URI is not valid base64"))?;
+            let mime_type = Some(mime_type.to_string()).filter(|mime_type| !mime_type.is_empty());
+            return Ok((Cow::Owned(bytes), mime_type));
+        }
+
+        if uri.contains("://") || uri.starts_with('/') {
+            return Err(invalid("only relative file URIs are supported"));
+        }
+        let relative = percent_decode(uri).ok_or_else(|| invalid("URI has invalid percent encoding"))?;
+        let path = self.base.join(relative);
+        let mime_type = path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| match extension.to_ascii_lowercase().as_str() {
+                "png" => Some("image/png"),
+                "ktx2" => Some("image/ktx2"),
+                "dds" => Some("image/vnd-ms.dds"),
+                _ => None,
+            })
+            .map(str::to_string);
+        Ok((Cow::Owned(::std::fs::read(path)?), mime_type))
+    }
+}
+
+pub fn parse_glb(bytes: &[u8]) -> Result<Glb, ModelLoadError> {
+    let invalid = |reason| ModelLoadError::InvalidGlb { reason };
+    if bytes.len() < GLB_HEADER_SIZE || !bytes.starts_with(GLB_MAGIC) {
+        return Err(invalid("file is too short for a GLB header"));
+    }
+    if read_u32(bytes, 4) != GLB_VERSION {
+        return Err(invalid("only GLB version 2 is supported"));
+    }
+    let length = read_u32(bytes, 8) as usize;
+    if length < GLB_HEADER_SIZE || length > bytes.len() {
+        return Err(invalid("header length doesn't match the file size"));
+    }
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = GLB_HEADER_SIZE;
+    while offset < length {
+        if length - offset < CHUNK_HEADER_SIZE {
+            return Err(invalid("chunk header is truncated"));
+        }
+        let chunk_length = read_u32(bytes, offset) as usize;
+        let chunk_type = read_u32(bytes, offset + 4);
+        let start = offset + CHUNK_HEADER_SIZE;
+        if chunk_length > length - start {
+            return Err(invalid("chunk extends past the end of the file"));
+        }
+        let chunk = &bytes[start..start + chunk_length];
+        match chunk_type {
+            JSON_CHUNK if json.is_none() && offset == GLB_HEADER_SIZE => json = Some(chunk),
+            JSON_CHUNK => return Err(invalid("JSON chunk must come first and only once")),
+            BIN_CHUNK if json.is_some() && bin.is_none() => bin = Some(chunk),
+            BIN_CHUNK => return Err(invalid("binary chunk must follow the JSON chunk and appear only once")),
+            // Unknown chunks are skipped as the specification requires.
+            _ => (),
+        }
+        offset = start + chunk_length;
+    }
+
+    Ok(Glb {
+        json: json.ok_or_else(|| invalid("file has no JSON chunk"))?,
+        bin,
+    })
+}
+
+// gltf 0.11 validates indices but still unwraps a few fields it doesn't check.
+fn check_root(root: &Root) -> Result<(), ModelLoadError> {
+    let invalid = |path: String, reason| ModelLoadError::InvalidDocument { path, reason };
+
+    for (index, camera) in root.cameras.iter().enumerate() {
+        let valid = match camera.type_ {
+            Checked::Valid(camera::Type::Perspective) => camera.perspective.is_some(),
+            Checked::Valid(camera::Type::Orthographic) => camera.orthographic.is_some(),
+            Checked::Invalid => true,
+        };
+        if !valid {
+            return Err(invalid(format!("cameras[{}]", index), "projection doesn't match the camera type"));
+        }
+    }
+
+    // The validator indexes the POSITION accessor before it checks that the index is in range.
+    for (mesh_index, mesh) in root.meshes.iter().enumerate() {
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            if primitive.attributes.values().any(|accessor| accessor.value() >= root.accessors.len()) {
+                let path = format!("meshes[{}].primitives[{}].attributes", mesh_index, primitive_index);
+                return Err(invalid(path, "attribute refers to a missing accessor"));
+            }
+        }
+    }
+
+    for (index, image) in root.images.iter().enumerate() {
+        if image.buffer_view.is_some() && image.mime_type.is_none() {
+            return Err(invalid(format!("images[{}]", index), "image in a buffer view has no mime type"));
+        }
+        if image.buffer_view.is_none() && image.uri.is_none() {
+            return Err(invalid(format!("images[{}]", index), "image has neither a buffer view nor a URI"));
+        }
+    }
+
+    for (animation_index, animation) in root.animations.iter().enumerate() {
+        for (channel_index, channel) in animation.channels.iter().enumerate() {
+            let target = &channel.target;
+            let valid_path = match target.path {
+                Checked::Valid(_) => true,
+                Checked::Invalid => false,
+            };
+            if target.node.value() >= root.nodes.len() || !valid_path {
+                let path = format!("animations[{}].channels[{}].target", animation_index, channel_index);
+                return Err(invalid(path, "channel targets a missing node or property"));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn truncated(uri: &str) -> String {
+    match uri.char_indices().nth(64) {
+        Some((end, _)) => format!("{}...", &uri[..end]),
+        None => uri.to_string(),
+    }
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for &c in text {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    if bit_count >= 6 {
+        return None;
+    }
+    Some(bytes)
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let text = text.as_bytes();
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] == b'%' {
+            let hex = text.get(i + 1..i + 3)?;
+            let hex = ::std::str::from_utf8(hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(text[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
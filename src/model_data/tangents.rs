@@ -0,0 +1,96 @@
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use super::{Attribute, ModelData, Vertex, VertexLayout, VertexLayoutError};
+
+impl ModelData {
+    // Fills in tangents and adds them to the vertex layout, for normal mapping meshes that came
+    // without them.
+    pub fn generate_tangents(&mut self) -> Result<(), VertexLayoutError> {
+        if !self.layout.contains(Attribute::Tangent) {
+            let mut attributes: Vec<_> = self.layout.attributes().iter()
+                .map(|attribute| (attribute.attribute, attribute.format, attribute.location))
+                .collect();
+            attributes.push((Attribute::Tangent, Attribute::Tangent.default_format(), Attribute::Tangent.default_location()));
+            self.layout = VertexLayout::new(&attributes)?;
+        }
+        generate(&mut self.vertices, &self.indices);
+        Ok(())
+    }
+}
+
+// Per-vertex tangents from the UV gradients of the surrounding triangles, made orthogonal to the
+// normal. The w component is the handedness of the bitangent. Close to, but not exactly, the
+// MikkTSpace tangents that glTF asks for when a file leaves them out.
+fn generate(vertices: &mut [Vertex], indices: &[u16]) {
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let edge1 = vertices[b].position - vertices[a].position;
+        let edge2 = vertices[c].position - vertices[a].position;
+        let uv1 = vertices[b].tex_coord - vertices[a].tex_coord;
+        let uv2 = vertices[c].tex_coord - vertices[a].tex_coord;
+        let determinant = uv1.x * uv2.y - uv2.x * uv1.y;
+        if determinant.abs() < 1e-12 {
+            continue;
+        }
+        let tangent = (edge1 * uv2.y - edge2 * uv1.y) / determinant;
+        let bitangent = (edge2 * uv1.x - edge1 * uv2.x) / determinant;
+        for &i in &[a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (vertex, (tangent, bitangent)) in vertices.iter_mut().zip(tangents.into_iter().zip(bitangents)) {
+        let normal = vertex.normal;
+        let mut tangent = tangent - normal * normal.dot(tangent);
+        if tangent.magnitude2() < 1e-12 {
+            tangent = any_perpendicular(normal);
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
+
+// Vertices without usable UVs still need a valid frame.
+fn any_perpendicular(normal: Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let tangent = axis - normal * normal.dot(axis);
+    if tangent.magnitude2() > 0.0 { tangent } else { axis }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_data::primitives;
+
+    #[test]
+    fn reports_layouts_without_room_for_tangents() {
+        let mut plane = primitives::plane(1.0, 1.0, 1, 1).unwrap();
+        let attributes: Vec<_> = [Attribute::Position, Attribute::Normal, Attribute::TexCoord0].iter()
+            .map(|&attribute| (attribute, attribute.default_format(), attribute.default_location()))
+            .chain(Some((Attribute::Color, Attribute::Color.default_format(), Attribute::Tangent.default_location())))
+            .collect();
+        plane.layout = VertexLayout::new(&attributes).unwrap();
+
+        match plane.generate_tangents() {
+            Err(VertexLayoutError::DuplicateLocation { location }) => {
+                assert_eq!(location, Attribute::Tangent.default_location())
+            }
+            other => panic!("expected a duplicate location, got {:?}", other),
+        }
+        assert!(!plane.layout.contains(Attribute::Tangent));
+    }
+
+    #[test]
+    fn ignores_a_trailing_partial_triangle() {
+        let mut plane = primitives::plane(1.0, 1.0, 1, 1).unwrap();
+        plane.indices.extend_from_slice(&[0, 1]);
+        plane.generate_tangents().unwrap();
+        assert!(plane.layout.contains(Attribute::Tangent));
+        // The plane's U runs along x and its V along -y, so the bitangent is flipped.
+        assert!(plane.vertices.iter().all(|vertex| vertex.tangent == [1.0, 0.0, 0.0, -1.0]));
+    }
+}
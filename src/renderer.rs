@@ -1,41 +1,98 @@
 use crate::assets::Assets;
-use cgmath::{Matrix4, Vector3, SquareMatrix};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Vector3, SquareMatrix};
 use crate::renderer::camera::Camera;
-use crate::model_data::{Vertex, ModelData};
+use crate::renderer::lights::Lights;
+use crate::renderer::shadows::Shadows;
+use crate::renderer::targets::{RenderTargets, Resolver};
+use crate::model_data::{Bounds, ExportError, GltfExporter, Material, ModelData, Texture, TextureTransform, VertexLayout};
+use std::path::Path;
+use crate::shader::{compile_shader, ShaderCompilationError};
+use shaderc::ShaderKind;
 use crate::conversions::{AsBytes, GpuBuffer};
-use crate::model::{ModelGroup, Model};
+use crate::model::{GroupGeometry, ModelGroup, ModelGroupError, Model};
 
 mod camera;
+mod depth;
+mod lights;
+mod offscreen;
+mod shadows;
+mod targets;
+
+pub use self::lights::{Light, LightError, LightId, MAX_LIGHTS};
+pub use self::offscreen::{OffscreenTarget, ReadbackError};
+pub use self::shadows::ShadowSettings;
+pub use self::targets::{GraphicsSettings, GraphicsSettingsError, SAMPLE_COUNTS};
 
 pub struct Renderer {
     camera: Camera,
     projection_view: GpuBuffer,
     normal_view: GpuBuffer,
-    light_buf: GpuBuffer,
-    pipeline: wgpu::RenderPipeline,
+    lights: Lights,
+    pipeline_layout: wgpu::PipelineLayout,
+    shadow_pipeline_layout: wgpu::PipelineLayout,
+    shadow_fs_module: wgpu::ShaderModule,
+    vertex_shader_source: String,
+    fragment_shader_source: String,
+    color_format: wgpu::TextureFormat,
+    targets: RenderTargets,
+    resolver: Resolver,
+    pipelines: Vec<(VertexLayout, wgpu::RenderPipeline)>,
+    shadow_pipelines: Vec<(VertexLayout, wgpu::RenderPipeline)>,
+    shadows: Shadows,
     bind_group_layout: wgpu::BindGroupLayout,
+    deformation_bind_group_layout: wgpu::BindGroupLayout,
     model_groups: Vec<ModelGroup>,
 }
 
+// std140 layout of the fragment shader's Material block. Texture transforms are stored as the
+// first two rows of their UV matrix.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-struct Light {
-    position: Vector3<f32>,
-    intensities: Vector3<f32>,
+struct MaterialUniforms {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    base_color_transform: [[f32; 4]; 2],
+    occlusion_transform: [[f32; 4]; 2],
+    normal_transform: [[f32; 4]; 2],
+    // x is the normal map scale.
+    normal_scale: [f32; 4],
 }
 
-impl Renderer {
-    pub fn init(sc_desc: &wgpu::SwapChainDescriptor, device: &mut wgpu::Device, assets: &Assets) -> Renderer {
-        let light = Light {
-            position: Vector3::new(10.0, 0.0, 3.0),
-            intensities: Vector3::new(2.0, 2.0, 2.0),
+impl MaterialUniforms {
+    fn new(material: &Material) -> MaterialUniforms {
+        let rows = |transform: &TextureTransform| {
+            let [a, b] = transform.rows();
+            [[a[0], a[1], a[2], 0.0], [b[0], b[1], b[2], 0.0]]
         };
+        let [r, g, b] = material.emissive;
+        MaterialUniforms {
+            base_color: material.base_color,
+            emissive: [r, g, b, if material.unlit { 1.0 } else { 0.0 }],
+            base_color_transform: rows(&material.base_color_transform),
+            occlusion_transform: rows(&material.occlusion_transform),
+            normal_transform: rows(&material.normal_transform),
+            normal_scale: [material.normal_scale, 0.0, 0.0, 0.0],
+        }
+    }
+}
 
-        let light_buf = GpuBuffer::new(
-            device,
-            wgpu::BufferUsageFlags::UNIFORM | wgpu::BufferUsageFlags::TRANSFER_DST,
-            &[light],
-        );
+impl Renderer {
+    pub fn init(
+        sc_desc: &wgpu::SwapChainDescriptor,
+        device: &mut wgpu::Device,
+        assets: &Assets,
+        settings: GraphicsSettings,
+        shadow_settings: ShadowSettings,
+    ) -> Result<Renderer, GraphicsSettingsError> {
+        settings.check(sc_desc.width, sc_desc.height).map_err(|reason| GraphicsSettingsError::InvalidSettings { reason })?;
+        let shadows = Shadows::new(device, shadow_settings)?;
+
+        // Until a scene provides its own lights, the models are lit by a point light off to the side.
+        // Its intensity makes up for the falloff, so the origin is lit as brightly as with the old
+        // unattenuated light of intensity 2.
+        let mut lights = Lights::new(device, shadows.settings().cascade_count());
+        let position = Point3::new(10.0, 0.0, 3.0);
+        lights.add(Light::point(position, [1.0; 3], 2.0 * position.to_vec().magnitude2())).unwrap();
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             bindings: &[
@@ -64,15 +121,78 @@ impl Renderer {
                     visibility: wgpu::ShaderStageFlags::FRAGMENT,
                     ty: wgpu::BindingType::Sampler,
                 },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 5,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 6,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 7,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 8,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 9,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 10,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 11,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture,
+                },
+            ],
+        });
+
+        let deformation_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutBinding {
+                    binding: 0,
+                    visibility: wgpu::ShaderStageFlags::VERTEX,
+                    ty: wgpu::BindingType::UniformBuffer,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStageFlags::VERTEX,
+                    ty: wgpu::BindingType::StorageBuffer,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 2,
+                    visibility: wgpu::ShaderStageFlags::VERTEX,
+                    ty: wgpu::BindingType::StorageBuffer,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 3,
+                    visibility: wgpu::ShaderStageFlags::VERTEX,
+                    ty: wgpu::BindingType::StorageBuffer,
+                },
             ],
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &deformation_bind_group_layout],
+        });
+        let shadow_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[shadows.bind_group_layout(), &deformation_bind_group_layout],
         });
 
         let aspect_ratio = sc_desc.width as f32 / sc_desc.height as f32;
-        let mut camera = Camera::new(
+        let camera = Camera::new(
             cgmath::Deg(45.0),
             aspect_ratio,
             1.0,
@@ -84,7 +204,7 @@ impl Renderer {
         let projection_view = GpuBuffer::from_single(
             device,
             wgpu::BufferUsageFlags::UNIFORM | wgpu::BufferUsageFlags::TRANSFER_DST,
-            camera.projection_view(),
+            settings.depth.clip_transform() * camera.projection_view(),
         );
 
         let normal_view_buf = GpuBuffer::from_byte_slices(
@@ -96,107 +216,114 @@ impl Renderer {
             ],
         );
 
-        let vertex_shader = assets.shaders.find("vertex").unwrap();
-        let fragment_shader = assets.shaders.find("fragment").unwrap();
-
-        let vs_module = device.create_shader_module(vertex_shader);
-        let fs_module = device.create_shader_module(fragment_shader);
+        let shadow_fs_module = device.create_shader_module(assets.shaders.find("shadow_fragment").unwrap());
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &pipeline_layout,
-            vertex_stage: wgpu::PipelineStageDescriptor {
-                module: &vs_module,
-                entry_point: "main",
-            },
-            fragment_stage: wgpu::PipelineStageDescriptor {
-                module: &fs_module,
-                entry_point: "main",
-            },
-            rasterization_state: wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: wgpu::CullMode::Back,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            },
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: sc_desc.format,
-                color: wgpu::BlendDescriptor::REPLACE,
-                alpha: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWriteFlags::ALL,
-            }],
-            depth_stencil_state: None,
-            index_format: wgpu::IndexFormat::Uint16,
-            vertex_buffers: &[Vertex::buffer_descriptor(), ModelGroup::buffer_descriptor()],
-            sample_count: 1,
-        });
+        let vertex_shader_source = assets.shader_sources.find("vertex").unwrap().clone();
+        let fragment_shader_source = assets.shader_sources.find("fragment").unwrap().clone();
+        let resolver = Resolver::new(
+            device,
+            assets.shaders.find("resolve_vertex").unwrap(),
+            assets.shaders.find("resolve_fragment").unwrap(),
+            sc_desc.format,
+        );
+        let targets = RenderTargets::new(device, settings, sc_desc, &resolver);
 
-        Renderer {
+        Ok(Renderer {
             camera,
             projection_view,
             normal_view: normal_view_buf,
-            light_buf,
+            lights,
             bind_group_layout,
-            pipeline,
+            deformation_bind_group_layout,
+            pipeline_layout,
+            shadow_pipeline_layout,
+            shadow_fs_module,
+            vertex_shader_source,
+            fragment_shader_source,
+            color_format: sc_desc.format,
+            targets,
+            resolver,
+            pipelines: Vec::new(),
+            shadow_pipelines: Vec::new(),
+            shadows,
             model_groups: Vec::new(),
-        }
+        })
     }
 
     pub fn add_model(&mut self, group_name: &str, model: Model) {
-        let mut group = self.model_groups.iter_mut()
+        let group = self.model_groups.iter_mut()
             .find(|group| group.name == group_name)
             .unwrap();
 
         group.add_model(model);
     }
 
-    pub fn add_model_group(&mut self, device: &mut wgpu::Device, group_name: &str, model_data: &ModelData) {
-        let vertex_buf = GpuBuffer::new(device, wgpu::BufferUsageFlags::VERTEX, &model_data.vertices);
-        let index_buf = GpuBuffer::new(device, wgpu::BufferUsageFlags::INDEX, &model_data.indices);
-
-        let texture_extent = model_data.texture.extent();
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_extent,
-            array_size: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: wgpu::TextureUsageFlags::SAMPLED | wgpu::TextureUsageFlags::TRANSFER_DST,
-        });
-        let texture_view = texture.create_default_view();
-        let temp_buf = device
-            .create_buffer_mapped(model_data.texture.pixels.len(), wgpu::BufferUsageFlags::TRANSFER_SRC)
-            .fill_from_slice(&model_data.texture.pixels);
+    pub fn models_mut<'a>(&'a mut self, group_name: &'a str) -> impl Iterator<Item = &'a mut Model> + 'a {
+        self.model_groups.iter_mut()
+            .filter(move |group| group.name == group_name)
+            .flat_map(|group| group.models.iter_mut())
+    }
+
+    pub fn add_model_group(
+        &mut self,
+        device: &mut wgpu::Device,
+        group_name: &str,
+        model_data: &ModelData,
+    ) -> Result<(), ShaderCompilationError> {
+        self.create_pipeline(device, &model_data.layout)?;
+
+        let vertex_buf = GpuBuffer::from_bytes(
+            device,
+            wgpu::BufferUsageFlags::VERTEX,
+            &model_data.layout.pack(&model_data.vertices),
+        );
+        let mut indices = model_data.indices.clone();
+        for lod in &model_data.lods {
+            indices.extend_from_slice(&lod.indices);
+        }
+        let index_buf = GpuBuffer::new(device, wgpu::BufferUsageFlags::INDEX, &indices);
 
         let mut init_encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
 
-        init_encoder.copy_buffer_to_texture(
-            wgpu::BufferCopyView {
-                buffer: &temp_buf,
-                offset: 0,
-                row_pitch: 4 * model_data.texture.width,
-                image_height: model_data.texture.height,
-            },
-            wgpu::TextureCopyView {
-                texture: &texture,
-                level: 0,
-                slice: 0,
-                origin: wgpu::Origin3d {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-            },
-            texture_extent,
+        let mut staging_bufs = Vec::new();
+        let white = Texture::solid([1.0; 4]);
+        let black = Texture::solid([0.0, 0.0, 0.0, 1.0]);
+        let flat_normal = Texture::solid([0.5, 0.5, 1.0, 1.0]);
+        let texture_view = upload_texture(device, &mut init_encoder, &mut staging_bufs, &model_data.texture);
+        let occlusion_view = upload_texture(
+            device,
+            &mut init_encoder,
+            &mut staging_bufs,
+            model_data.occlusion.as_ref().unwrap_or(&white),
+        );
+        let lightmap_view = upload_texture(
+            device,
+            &mut init_encoder,
+            &mut staging_bufs,
+            model_data.lightmap.as_ref().unwrap_or(&black),
+        );
+        let normal_view = upload_texture(
+            device,
+            &mut init_encoder,
+            &mut staging_bufs,
+            model_data.normal_map.as_ref().unwrap_or(&flat_normal),
         );
 
-        // Create other resources
+        let material_buf = GpuBuffer::from_single(
+            device,
+            wgpu::BufferUsageFlags::UNIFORM,
+            MaterialUniforms::new(&model_data.material),
+        );
+
+        // Create other resources. glTF samplers repeat by default, which texture transforms rely on
+        // for tiling. Textures have a single level, see upload_texture, so there's nothing to
+        // filter between mips yet.
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            r_address_mode: wgpu::AddressMode::ClampToEdge,
-            s_address_mode: wgpu::AddressMode::ClampToEdge,
-            t_address_mode: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
+            r_address_mode: wgpu::AddressMode::Repeat,
+            s_address_mode: wgpu::AddressMode::Repeat,
+            t_address_mode: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             lod_min_clamp: -100.0,
@@ -206,12 +333,13 @@ impl Renderer {
             border_color: wgpu::BorderColor::TransparentBlack,
         });
 
+        let [shadow_map, shadow_sampler, shadow_uniforms] = self.shadows.bindings(8);
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &self.bind_group_layout,
             bindings: &[
                 self.projection_view.binding(0),
                 self.normal_view.binding(1),
-                self.light_buf.binding(2),
+                self.lights.binding(2),
                 wgpu::Binding {
                     binding: 3,
                     resource: wgpu::BindingResource::TextureView(&texture_view),
@@ -220,6 +348,22 @@ impl Renderer {
                     binding: 4,
                     resource: wgpu::BindingResource::Sampler(&sampler),
                 },
+                wgpu::Binding {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&occlusion_view),
+                },
+                wgpu::Binding {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&lightmap_view),
+                },
+                material_buf.binding(7),
+                shadow_map,
+                shadow_sampler,
+                shadow_uniforms,
+                wgpu::Binding {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&normal_view),
+                },
             ],
         });
 
@@ -230,13 +374,182 @@ impl Renderer {
             group_name.to_string(),
             index_buf,
             vertex_buf,
+            model_data.layout.clone(),
             bind_group,
+            GroupGeometry::new(device, model_data),
         ));
+        Ok(())
+    }
+
+    fn create_pipeline(&mut self, device: &mut wgpu::Device, layout: &VertexLayout) -> Result<(), ShaderCompilationError> {
+        if self.pipelines.iter().any(|(existing, _)| existing == layout) {
+            return Ok(());
+        }
+
+        // The fragment shader only does normal mapping when the layout has tangents.
+        let vertex_shader = compile_shader(&self.vertex_shader_source, ShaderKind::Vertex, &layout.shader_defines())?;
+        let vs_module = device.create_shader_module(&vertex_shader);
+        let fragment_shader = compile_shader(&self.fragment_shader_source, ShaderKind::Fragment, &layout.shader_defines())?;
+        let fs_module = device.create_shader_module(&fragment_shader);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &self.pipeline_layout,
+            vertex_stage: wgpu::PipelineStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: wgpu::PipelineStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            },
+            rasterization_state: wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::Back,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            },
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: self.color_format,
+                color: wgpu::BlendDescriptor::REPLACE,
+                alpha: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWriteFlags::ALL,
+            }],
+            depth_stencil_state: Some(self.targets.settings().depth.stencil_state()),
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[layout.buffer_descriptor(), ModelGroup::buffer_descriptor()],
+            // Supersampling samples are separate texels of the scene target, see RenderTargets.
+            sample_count: 1,
+        });
+        self.pipelines.push((layout.clone(), pipeline));
+
+        // Depth only, so there are no color targets and the fragment shader does nothing.
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &self.shadow_pipeline_layout,
+            vertex_stage: wgpu::PipelineStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: wgpu::PipelineStageDescriptor {
+                module: &self.shadow_fs_module,
+                entry_point: "main",
+            },
+            rasterization_state: wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: self.shadows.settings().slope_bias,
+                depth_bias_clamp: 0.0,
+            },
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[],
+            depth_stencil_state: Some(Shadows::stencil_state()),
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[layout.buffer_descriptor(), ModelGroup::buffer_descriptor()],
+            sample_count: 1,
+        });
+        self.shadow_pipelines.push((layout.clone(), shadow_pipeline));
+        Ok(())
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        for group in self.model_groups.iter_mut() {
+            group.advance_animations(delta);
+        }
     }
 
     pub fn resize(&mut self, sc_desc: &wgpu::SwapChainDescriptor, device: &mut wgpu::Device) {
         self.camera.set_aspect(sc_desc.width as f32 / sc_desc.height as f32);
         self.update_camera(device);
+        // A larger window can leave too little room for the current sample count.
+        let settings = self.targets.settings().fitted(sc_desc.width, sc_desc.height);
+        if settings.samples != self.targets.settings().samples {
+            eprintln!("Anti-aliasing samples lowered to {} to fit the window", settings.samples);
+        }
+        self.targets = RenderTargets::new(device, settings, sc_desc, &self.resolver);
+    }
+
+    pub fn graphics_settings(&self) -> &GraphicsSettings {
+        self.targets.settings()
+    }
+
+    // The pipelines are rebuilt for the new depth state, so this is too slow to do every frame.
+    pub fn set_graphics_settings(
+        &mut self,
+        device: &mut wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        settings: GraphicsSettings,
+    ) -> Result<(), GraphicsSettingsError> {
+        settings.check(sc_desc.width, sc_desc.height).map_err(|reason| GraphicsSettingsError::InvalidSettings { reason })?;
+        let previous = *self.targets.settings();
+        self.targets = RenderTargets::new(device, settings, sc_desc, &self.resolver);
+        if previous.depth != settings.depth {
+            self.update_camera(device);
+            let layouts: Vec<_> = self.pipelines.drain(..).map(|(layout, _)| layout).collect();
+            self.shadow_pipelines.clear();
+            for layout in &layouts {
+                self.create_pipeline(device, layout)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn start_from_scene(&mut self, device: &mut wgpu::Device, scene: &ModelData) {
+        if let Some(camera) = scene.cameras.first() {
+            self.camera = Camera::from_scene(camera, self.camera.aspect_ratio());
+            self.update_camera(device);
+        }
+        // Scene lights replace the default one. Any past the limit are left out.
+        if !scene.lights.is_empty() {
+            self.lights.clear();
+            for light in scene.lights.iter().take(MAX_LIGHTS) {
+                if let Err(error) = self.lights.add(Light::from_scene(light)) {
+                    eprintln!("Skipping light {}: {}", light.name, error);
+                }
+            }
+        }
+    }
+
+    // At most MAX_LIGHTS (16) lights are active at once, including the default light or the scene
+    // lights. Past that, add_light returns TooManyLights until a light is removed.
+    pub fn add_light(&mut self, light: Light) -> Result<LightId, LightError> {
+        self.lights.add(light)
+    }
+
+    pub fn update_light(&mut self, id: LightId, light: Light) -> Result<(), LightError> {
+        self.lights.update(id, light)
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(id)
+    }
+
+    pub fn light(&self, id: LightId) -> Option<&Light> {
+        self.lights.get(id)
+    }
+
+    // Writes every placed model to a .glb. The renderer only keeps GPU buffers, so the caller
+    // supplies the source data for each model group.
+    pub fn export_scene<'a>(
+        &self,
+        path: &Path,
+        model_data: impl Fn(&str) -> Option<&'a ModelData>,
+    ) -> Result<(), ExportError> {
+        let mut exporter = GltfExporter::new();
+        for group in self.model_groups.iter().filter(|group| !group.models.is_empty()) {
+            let data = model_data(&group.name).ok_or_else(|| ExportError::MissingModelData { group: group.name.clone() })?;
+            let transforms: Vec<Matrix4<f32>> = group.models.iter().map(Model::model_matrix).collect();
+            exporter.add_model(&group.name, data, &transforms)?;
+        }
+        exporter.save(path)
+    }
+
+    pub fn camera_position(&self) -> Point3<f32> {
+        self.camera.position()
+    }
+
+    pub fn camera_direction(&self) -> Vector3<f32> {
+        self.camera.direction()
     }
 
     pub fn move_camera(&mut self, device: &mut wgpu::Device, movement: Vector3<f32>) {
@@ -245,10 +558,11 @@ impl Renderer {
     }
 
     fn update_camera(&mut self, device: &mut wgpu::Device) {
+        let projection_view = self.targets.settings().depth.clip_transform() * self.camera.projection_view();
         let projection_view_src = GpuBuffer::from_bytes(
             device,
             wgpu::BufferUsageFlags::TRANSFER_SRC,
-            self.camera.projection_view().as_bytes(),
+            projection_view.as_bytes(),
         );
 
         let normal_view_src = GpuBuffer::from_byte_slices(
@@ -268,15 +582,53 @@ impl Renderer {
         device.get_queue().submit(&[encoder.finish()]);
     }
 
-    pub fn render(&mut self, frame: &wgpu::SwapChainOutput, device: &mut wgpu::Device) {
+    // The target is a swap chain frame or an OffscreenTarget's view, with the format and size the
+    // renderer was created or last resized with.
+    pub fn render(&mut self, target: &wgpu::TextureView, device: &mut wgpu::Device) -> Result<(), ModelGroupError> {
+        self.lights.upload(device);
+
+        let eye = self.camera.position();
+        let projection_scale = self.camera.projection_scale();
+        let (near, far) = self.camera.depth_range();
+        let frustum = Bounds::from_points(&self.camera.frustum_slice(near, far));
+        // Groups without models are skipped, their instance buffers would be empty.
+        for group in self.model_groups.iter_mut().filter(|group| !group.models.is_empty()) {
+            group.update_lods(eye, projection_scale, &frustum);
+            group.update_mvp_buffer(device);
+            group.update_deformation_buffers(device, &self.deformation_bind_group_layout);
+        }
+        self.shadows.update(device, &self.camera, &self.lights.shadow_casters());
 
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
 
+        // Instances are drawn with the LODs picked for the camera. Ones it culled still cast
+        // shadows.
+        for (view, bind_group) in self.shadows.active_layers() {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
+                depth_stencil_attachment: Some(Shadows::attachment(view)),
+            });
+            for group in self.model_groups.iter().filter(|group| !group.models.is_empty()) {
+                let pipeline = self.shadow_pipelines.iter()
+                    .find(|(layout, _)| *layout == group.layout)
+                    .map(|(_, pipeline)| pipeline)
+                    .unwrap();
+                rpass.set_pipeline(pipeline);
+                rpass.set_vertex_buffers(&[(group.vertex_buf.buffer(), 0), (group.mvp_buffer()?, 0)]);
+                rpass.set_bind_group(0, bind_group);
+                rpass.set_bind_group(1, group.deformation_bind_group()?);
+                rpass.set_index_buffer(&group.index_buf.buffer(), 0);
+                for (indices, instances) in group.shadow_batches() {
+                    rpass.draw_indexed(indices, 0, instances);
+                }
+            }
+        }
+
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
+                    attachment: self.targets.color_view(target),
                     load_op: wgpu::LoadOp::Clear,
                     store_op: wgpu::StoreOp::Store,
                     clear_color: wgpu::Color {
@@ -286,20 +638,72 @@ impl Renderer {
                         a: 1.0,
                     },
                 }],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(self.targets.depth_attachment()),
             });
-            rpass.set_pipeline(&self.pipeline);
 
-            for group in self.model_groups.iter_mut() {
-                let instances = group.models.len() as u32;
-                group.update_mvp_buffer(device);
-                rpass.set_vertex_buffers(&[(group.vertex_buf.buffer(), 0), (group.mvp_buffer(), 0)]);
+            for group in self.model_groups.iter().filter(|group| !group.models.is_empty()) {
+                let pipeline = self.pipelines.iter()
+                    .find(|(layout, _)| *layout == group.layout)
+                    .map(|(_, pipeline)| pipeline)
+                    .unwrap();
+                rpass.set_pipeline(pipeline);
+                rpass.set_vertex_buffers(&[(group.vertex_buf.buffer(), 0), (group.mvp_buffer()?, 0)]);
                 rpass.set_bind_group(0, &group.bind_group);
+                rpass.set_bind_group(1, group.deformation_bind_group()?);
                 rpass.set_index_buffer(&group.index_buf.buffer(), 0);
-                rpass.draw_indexed(0..group.index_buf.len, 0, 0..instances);
+                for (indices, instances) in group.lod_batches() {
+                    rpass.draw_indexed(indices, 0, instances);
+                }
             }
         }
+        self.targets.resolve(&mut encoder, &self.resolver, target);
 
         device.get_queue().submit(&[encoder.finish()]);
+        Ok(())
     }
 }
+
+fn upload_texture(
+    device: &mut wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    staging_bufs: &mut Vec<wgpu::Buffer>,
+    texture: &Texture,
+) -> wgpu::TextureView {
+    let gpu_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: texture.extent(),
+        array_size: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsageFlags::SAMPLED | wgpu::TextureUsageFlags::TRANSFER_DST,
+    });
+
+    // wgpu 0.2 creates every texture with a single mip level, so only the base level is uploaded
+    // and textures aren't mipmapped. Mip levels loaded from DDS or KTX2 files are ignored.
+    let (width, height) = (texture.width, texture.height);
+    let temp_buf = device
+        .create_buffer_mapped(texture.pixels.len(), wgpu::BufferUsageFlags::TRANSFER_SRC)
+        .fill_from_slice(&texture.pixels);
+
+    encoder.copy_buffer_to_texture(
+        wgpu::BufferCopyView {
+            buffer: &temp_buf,
+            offset: 0,
+            row_pitch: 4 * width,
+            image_height: height,
+        },
+        wgpu::TextureCopyView {
+            texture: &gpu_texture,
+            level: 0,
+            slice: 0,
+            origin: wgpu::Origin3d {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        },
+        wgpu::Extent3d { width, height, depth: 1 },
+    );
+    staging_bufs.push(temp_buf);
+
+    gpu_texture.create_default_view()
+}
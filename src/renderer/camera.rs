@@ -1,12 +1,35 @@
-use cgmath::{Deg, Matrix4, Point3, Vector3, Matrix3, Matrix, SquareMatrix};
+use cgmath::{Deg, Matrix4, Point3, Rad, Vector3, Matrix3, Matrix, SquareMatrix, InnerSpace, Transform};
+use crate::model_data::{Projection, SceneCamera};
+
+// Far plane for glTF perspective cameras that use an infinite projection.
+const DEFAULT_FAR: f32 = 1000.0;
+
+// cgmath's projections map depth to -1..1 like OpenGL, wgpu clips it to 0..1.
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+enum Lens {
+    Perspective {
+        fov_y: Deg<f32>,
+    },
+    Orthographic {
+        half_height: f32,
+    },
+}
 
 pub struct Camera {
-    fov_y: Deg<f32>,
+    lens: Lens,
     aspect_ratio: f32,
     near: f32,
     far: f32,
     position: Point3<f32>,
     target: Point3<f32>,
+    up: Vector3<f32>,
 }
 
 impl Camera {
@@ -19,28 +42,64 @@ impl Camera {
         target: Point3<f32>,
     ) -> Camera {
         Camera {
-            fov_y,
+            lens: Lens::Perspective { fov_y },
             aspect_ratio,
             near,
             far,
             position,
             target,
+            up: Vector3::unit_z(),
+        }
+    }
+
+    pub fn from_scene(camera: &SceneCamera, aspect_ratio: f32) -> Camera {
+        let (lens, near, far) = match camera.projection {
+            Projection::Perspective { yfov, znear, zfar, .. } => {
+                (Lens::Perspective { fov_y: yfov.into() }, znear, zfar.unwrap_or(DEFAULT_FAR))
+            }
+            Projection::Orthographic { ymag, znear, zfar, .. } => {
+                (Lens::Orthographic { half_height: ymag }, znear, zfar)
+            }
+        };
+        // The target sits in the middle of the view volume, which is where orthographic cameras
+        // measure screen size for LOD selection.
+        let position = camera.position();
+        Camera {
+            lens,
+            aspect_ratio,
+            near,
+            far,
+            position,
+            target: position + camera.direction() * (near + far) * 0.5,
+            up: camera.up(),
         }
     }
 
+    pub fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
     pub fn set_aspect(&mut self, aspect_ratio: f32) {
         self.aspect_ratio = aspect_ratio;
     }
 
+    // Depth runs from 0 at the near plane to 1 at the far plane.
     pub fn projection(&self) -> Matrix4<f32> {
-        cgmath::perspective(self.fov_y, self.aspect_ratio, self.near, self.far)
+        let projection = match self.lens {
+            Lens::Perspective { fov_y } => cgmath::perspective(fov_y, self.aspect_ratio, self.near, self.far),
+            Lens::Orthographic { half_height } => {
+                let half_width = half_height * self.aspect_ratio;
+                cgmath::ortho(-half_width, half_width, -half_height, half_height, self.near, self.far)
+            }
+        };
+        OPENGL_TO_WGPU_MATRIX * projection
     }
 
     pub fn view(&self) -> Matrix4<f32> {
         Matrix4::look_at(
             self.position,
             self.target,
-            Vector3::unit_z(),
+            self.up,
         )
     }
 
@@ -53,6 +112,43 @@ impl Camera {
         ).invert().unwrap().transpose()
     }
 
+    pub fn position(&self) -> Point3<f32> {
+        self.position
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        (self.target - self.position).normalize()
+    }
+
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.near, self.far)
+    }
+
+    // World space corners of the part of the view volume between two view distances. Points at a
+    // fixed view distance are a linear blend between the near and far plane corners.
+    pub fn frustum_slice(&self, near: f32, far: f32) -> [Point3<f32>; 8] {
+        let inverse = self.projection_view().invert().unwrap();
+        let mut corners = [Point3::new(0.0, 0.0, 0.0); 8];
+        for (i, &(x, y)) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter().enumerate() {
+            let near_corner = inverse.transform_point(Point3::new(x, y, 0.0));
+            let far_corner = inverse.transform_point(Point3::new(x, y, 1.0));
+            let blend = |distance: f32| {
+                let t = (distance - self.near) / (self.far - self.near);
+                near_corner + (far_corner - near_corner) * t
+            };
+            corners[i] = blend(near);
+            corners[i + 4] = blend(far);
+        }
+        corners
+    }
+
+    pub fn projection_scale(&self) -> f32 {
+        match self.lens {
+            Lens::Perspective { fov_y } => 1.0 / (Rad::from(fov_y).0 * 0.5).tan(),
+            Lens::Orthographic { half_height } => (self.target - self.position).magnitude() / half_height,
+        }
+    }
+
     pub fn translate(&mut self, movement: Vector3<f32>) {
         self.position += movement;
     }
@@ -60,4 +156,36 @@ impl Camera {
     pub fn projection_view(&self) -> Matrix4<f32> {
         self.projection() * self.view()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, EuclideanSpace, InnerSpace, Point3, Transform};
+    use super::Camera;
+
+    fn camera() -> Camera {
+        Camera::new(Deg(45.0), 1.5, 1.0, 10.0, Point3::new(1.5, -5.0, 3.0), Point3::origin())
+    }
+
+    #[test]
+    fn projection_maps_the_depth_range_to_zero_to_one() {
+        let camera = camera();
+        let depth = |distance: f32| {
+            let point = camera.position() + camera.direction() * distance;
+            camera.projection_view().transform_point(point).z
+        };
+        assert!(depth(1.0).abs() < 1e-5);
+        assert!((depth(10.0) - 1.0).abs() < 1e-5);
+        assert!(depth(1.2) > 0.0 && depth(1.2) < depth(2.0));
+    }
+
+    #[test]
+    fn frustum_slices_end_at_their_view_distances() {
+        let camera = camera();
+        let corners = camera.frustum_slice(2.0, 6.0);
+        for (i, corner) in corners.iter().enumerate() {
+            let distance = (corner - camera.position()).dot(camera.direction());
+            let expected = if i < 4 { 2.0 } else { 6.0 };
+            assert!((distance - expected).abs() < 1e-4, "corner {} is at {}", i, distance);
+        }
+    }
+}
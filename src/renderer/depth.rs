@@ -0,0 +1,113 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+// Flips clip space depth from 0..1 to 1..0.
+#[rustfmt::skip]
+const REVERSE_DEPTH_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthSettings {
+    // One of the D* texture formats.
+    pub format: wgpu::TextureFormat,
+    pub compare: wgpu::CompareFunction,
+}
+
+impl Default for DepthSettings {
+    fn default() -> DepthSettings {
+        DepthSettings {
+            format: wgpu::TextureFormat::D32Float,
+            compare: wgpu::CompareFunction::Less,
+        }
+    }
+}
+
+impl DepthSettings {
+    pub fn stencil_state(&self) -> wgpu::DepthStencilStateDescriptor {
+        wgpu::DepthStencilStateDescriptor {
+            format: self.format,
+            depth_write_enabled: true,
+            depth_compare: self.compare,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }
+    }
+
+    // Greater and GreaterEqual keep the nearest fragment with the largest value, so they're used
+    // with a reversed depth range that puts the near plane at 1 and starts from 0.
+    fn reversed(&self) -> bool {
+        match self.compare {
+            wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual => true,
+            _ => false,
+        }
+    }
+
+    // Applied on top of the camera's projection, which maps depth to 0..1.
+    pub fn clip_transform(&self) -> Matrix4<f32> {
+        if self.reversed() { REVERSE_DEPTH_MATRIX } else { Matrix4::identity() }
+    }
+
+    fn clear_depth(&self) -> f32 {
+        if self.reversed() { 0.0 } else { 1.0 }
+    }
+}
+
+// The depth attachment of the main pass. It has to match the size of the color target it's
+// drawn with.
+pub struct DepthBuffer {
+    settings: DepthSettings,
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl DepthBuffer {
+    pub fn new(device: &mut wgpu::Device, settings: DepthSettings, width: u32, height: u32) -> DepthBuffer {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d { width, height, depth: 1 },
+            array_size: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: settings.format,
+            usage: wgpu::TextureUsageFlags::OUTPUT_ATTACHMENT,
+        });
+        let view = texture.create_default_view();
+        DepthBuffer {
+            settings,
+            _texture: texture,
+            view,
+        }
+    }
+
+    pub fn attachment(&self) -> wgpu::RenderPassDepthStencilAttachmentDescriptor<&wgpu::TextureView> {
+        wgpu::RenderPassDepthStencilAttachmentDescriptor {
+            attachment: &self.view,
+            depth_load_op: wgpu::LoadOp::Clear,
+            depth_store_op: wgpu::StoreOp::Store,
+            clear_depth: self.settings.clear_depth(),
+            stencil_load_op: wgpu::LoadOp::Clear,
+            stencil_store_op: wgpu::StoreOp::Store,
+            clear_stencil: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Transform};
+    use super::DepthSettings;
+
+    #[test]
+    fn reversed_compares_flip_the_depth_range() {
+        let reversed = DepthSettings { compare: wgpu::CompareFunction::Greater, ..DepthSettings::default() };
+        let flip = |settings: DepthSettings, depth| {
+            settings.clip_transform().transform_point(Point3::new(0.0, 0.0, depth)).z
+        };
+        assert_eq!((flip(reversed, 0.0), flip(reversed, 1.0)), (1.0, 0.0));
+        assert_eq!(reversed.clear_depth(), 0.0);
+        assert_eq!((flip(DepthSettings::default(), 0.25), DepthSettings::default().clear_depth()), (0.25, 1.0));
+    }
+}
@@ -0,0 +1,458 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Rad, Vector3};
+use std::ops::Range;
+use crate::conversions::GpuBuffer;
+use crate::model_data::{LightKind, SceneLight};
+use super::shadows::MAX_SHADOW_LAYERS;
+
+// Must match MAX_LIGHTS in cube.frag.glsl. The whole array lives in one uniform block, so raising
+// it costs 80 bytes per light in every draw's bind group.
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum LightError {
+    #[error(display = "at most {} lights can be active at once", limit)]
+    TooManyLights {
+        limit: usize,
+    },
+    #[error(display = "the light was removed")]
+    UnknownLight,
+    #[error(display = "invalid light: {}", reason)]
+    InvalidLight {
+        reason: &'static str,
+    },
+}
+
+// Lights follow the KHR_lights_punctual conventions: point and spot intensities are in candela
+// and fall off with the inverse square of the distance, directional intensities are in lux.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Point3<f32>,
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Distance at which the light is cut off. None means unlimited.
+    pub range: Option<f32>,
+    // Point lights never cast shadows.
+    pub casts_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vector3<f32>, color: [f32; 3], intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            position: Point3::origin(),
+            direction,
+            color,
+            intensity,
+            range: None,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn point(position: Point3<f32>, color: [f32; 3], intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: -Vector3::unit_z(),
+            color,
+            intensity,
+            range: None,
+            casts_shadows: false,
+        }
+    }
+
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        inner_cone_angle: Rad<f32>,
+        outer_cone_angle: Rad<f32>,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Light {
+        Light {
+            kind: LightKind::Spot { inner_cone_angle, outer_cone_angle },
+            position,
+            direction,
+            color,
+            intensity,
+            range: None,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn with_range(self, range: f32) -> Light {
+        Light { range: Some(range), ..self }
+    }
+
+    pub fn with_shadows(self, casts_shadows: bool) -> Light {
+        Light { casts_shadows, ..self }
+    }
+
+    pub fn from_scene(light: &SceneLight) -> Light {
+        let scene_light = match light.kind {
+            LightKind::Directional => Light::directional(light.direction(), light.color, light.intensity),
+            LightKind::Point => Light::point(light.position(), light.color, light.intensity),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => Light::spot(
+                light.position(),
+                light.direction(),
+                inner_cone_angle,
+                outer_cone_angle,
+                light.color,
+                light.intensity,
+            ),
+        };
+        Light { range: light.range, ..scene_light }
+    }
+
+    fn validate(&self) -> Result<(), LightError> {
+        let invalid = |reason| Err(LightError::InvalidLight { reason });
+        if !(self.intensity >= 0.0) || !self.intensity.is_finite() {
+            return invalid("intensity must be a non-negative number");
+        }
+        if self.color.iter().any(|channel| !(*channel >= 0.0)) {
+            return invalid("color channels must be non-negative");
+        }
+        if self.range.map_or(false, |range| !(range > 0.0)) {
+            return invalid("range must be positive");
+        }
+        if self.kind != LightKind::Point && !(self.direction.magnitude2() > 0.0) {
+            return invalid("direction must not be zero");
+        }
+        if let LightKind::Spot { inner_cone_angle, outer_cone_angle } = self.kind {
+            if !(inner_cone_angle.0 >= 0.0 && inner_cone_angle < outer_cone_angle && outer_cone_angle <= Rad(std::f32::consts::FRAC_PI_2)) {
+                return invalid("cone angles must satisfy 0 <= inner < outer <= pi / 2");
+            }
+        }
+        Ok(())
+    }
+
+    // Directional lights get one shadow map per cascade, spot lights a single one.
+    fn shadow_layers(&self, cascade_count: usize) -> usize {
+        match self.kind {
+            _ if !self.casts_shadows => 0,
+            LightKind::Directional => cascade_count,
+            LightKind::Spot { .. } => 1,
+            LightKind::Point => 0,
+        }
+    }
+
+    fn uniform(&self, shadow_layers: Range<u32>) -> LightUniform {
+        let [r, g, b] = self.color;
+        let (kind, cone) = match self.kind {
+            LightKind::Directional => (0.0, [0.0; 2]),
+            LightKind::Point => (1.0, [0.0; 2]),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                let cos_outer = outer_cone_angle.0.cos();
+                (2.0, [cos_outer, 1.0 / (inner_cone_angle.0.cos() - cos_outer).max(0.001)])
+            }
+        };
+        let direction = if self.direction.magnitude2() > 0.0 { self.direction.normalize() } else { self.direction };
+        LightUniform {
+            position: [self.position.x, self.position.y, self.position.z, kind],
+            direction: [direction.x, direction.y, direction.z, self.range.unwrap_or(0.0)],
+            color: [r * self.intensity, g * self.intensity, b * self.intensity, 0.0],
+            cone: [cone[0], cone[1], 0.0, 0.0],
+            shadow: [shadow_layers.start as f32, shadow_layers.len() as f32, 0.0, 0.0],
+        }
+    }
+}
+
+// std140 layout of one entry in the fragment shader's Lights block.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct LightUniform {
+    // w is the kind: 0 directional, 1 point, 2 spot.
+    position: [f32; 4],
+    // w is the range, or 0 for unlimited.
+    direction: [f32; 4],
+    // Color premultiplied by intensity.
+    color: [f32; 4],
+    // Cosine of the outer cone angle and 1 / (cos inner - cos outer), for spot lights.
+    cone: [f32; 4],
+    // First shadow map layer and the number of layers, 0 for unshadowed lights.
+    shadow: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct LightsUniform {
+    count: [u32; 4],
+    lights: [LightUniform; MAX_LIGHTS],
+}
+
+impl LightsUniform {
+    fn empty() -> LightsUniform {
+        LightsUniform {
+            count: [0; 4],
+            lights: [Light::point(Point3::origin(), [0.0; 3], 0.0).uniform(0..0); MAX_LIGHTS],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightId(usize);
+
+// Slots are reused after a light is removed, so a stale LightId may refer to a newer light.
+#[derive(Default)]
+struct LightSlots {
+    slots: Vec<Option<Light>>,
+}
+
+impl LightSlots {
+    fn add(&mut self, light: Light) -> Result<LightId, LightError> {
+        light.validate()?;
+        let slot = match self.slots.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None if self.slots.len() < MAX_LIGHTS => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+            None => return Err(LightError::TooManyLights { limit: MAX_LIGHTS }),
+        };
+        self.slots[slot] = Some(light);
+        Ok(LightId(slot))
+    }
+
+    fn update(&mut self, id: LightId, light: Light) -> Result<(), LightError> {
+        light.validate()?;
+        let slot = self.slots.get_mut(id.0)
+            .and_then(Option::as_mut)
+            .ok_or(LightError::UnknownLight)?;
+        *slot = light;
+        Ok(())
+    }
+
+    fn remove(&mut self, id: LightId) -> Option<Light> {
+        self.slots.get_mut(id.0).and_then(Option::take)
+    }
+
+    fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id.0).and_then(Option::as_ref)
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Light> {
+        self.slots.iter().flatten()
+    }
+}
+
+pub struct Lights {
+    slots: LightSlots,
+    cascade_count: usize,
+    buffer: GpuBuffer,
+    dirty: bool,
+}
+
+impl Lights {
+    pub fn new(device: &wgpu::Device, cascade_count: usize) -> Lights {
+        Lights {
+            slots: LightSlots::default(),
+            cascade_count,
+            buffer: GpuBuffer::from_single(
+                device,
+                wgpu::BufferUsageFlags::UNIFORM | wgpu::BufferUsageFlags::TRANSFER_DST,
+                LightsUniform::empty(),
+            ),
+            dirty: false,
+        }
+    }
+
+    pub fn add(&mut self, light: Light) -> Result<LightId, LightError> {
+        let id = self.slots.add(light)?;
+        self.dirty = true;
+        Ok(id)
+    }
+
+    pub fn update(&mut self, id: LightId, light: Light) -> Result<(), LightError> {
+        self.slots.update(id, light)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let light = self.slots.remove(id);
+        self.dirty |= light.is_some();
+        light
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots.get(id)
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.dirty = true;
+    }
+
+    pub fn shadow_casters(&self) -> Vec<(&Light, Range<u32>)> {
+        shadow_casters(self.slots.iter(), self.cascade_count)
+    }
+
+    pub fn binding(&self, binding_index: u32) -> wgpu::Binding {
+        self.buffer.binding(binding_index)
+    }
+
+    pub fn upload(&mut self, device: &mut wgpu::Device) {
+        if !self.dirty {
+            return;
+        }
+        let mut uniform = LightsUniform::empty();
+        let casters = self.shadow_casters();
+        for (index, light) in self.slots.iter().enumerate() {
+            let layers = casters.iter()
+                .find(|(caster, _)| std::ptr::eq(*caster, light))
+                .map_or(0..0, |(_, layers)| layers.clone());
+            uniform.lights[index] = light.uniform(layers);
+            uniform.count[0] += 1;
+        }
+
+        let source = GpuBuffer::from_single(device, wgpu::BufferUsageFlags::TRANSFER_SRC, uniform);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        source.copy_to_buffer(&mut encoder, &self.buffer);
+        device.get_queue().submit(&[encoder.finish()]);
+        self.dirty = false;
+    }
+}
+
+// Shadow map layers are handed out in light order until they run out, and later lights are left
+// unshadowed.
+fn shadow_casters<'a>(lights: impl Iterator<Item = &'a Light>, cascade_count: usize) -> Vec<(&'a Light, Range<u32>)> {
+    let mut next_layer = 0;
+    let mut casters = Vec::new();
+    for light in lights {
+        let layers = light.shadow_layers(cascade_count);
+        if layers > 0 && next_layer + layers <= MAX_SHADOW_LAYERS {
+            casters.push((light, next_layer as u32..(next_layer + layers) as u32));
+            next_layer += layers;
+        }
+    }
+    casters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sun() -> Light {
+        Light::directional(-Vector3::unit_z(), [1.0; 3], 3.0)
+    }
+
+    fn lamp(x: f32) -> Light {
+        Light::point(Point3::new(x, 0.0, 2.0), [1.0; 3], 10.0)
+    }
+
+    fn spot(inner: f32, outer: f32) -> Light {
+        Light::spot(Point3::new(0.0, 0.0, 3.0), -Vector3::unit_z(), Rad(inner), Rad(outer), [1.0; 3], 50.0)
+    }
+
+    fn reason(light: Light) -> &'static str {
+        match light.validate() {
+            Err(LightError::InvalidLight { reason }) => reason,
+            result => panic!("expected an invalid light, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn accepts_well_formed_lights() {
+        for light in &[sun(), lamp(0.0).with_range(5.0), spot(0.0, 0.5), spot(0.2, std::f32::consts::FRAC_PI_2)] {
+            assert!(light.validate().is_ok(), "{:?}", light);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_intensities_colors_and_ranges() {
+        assert_eq!(reason(Light { intensity: -1.0, ..sun() }), "intensity must be a non-negative number");
+        assert_eq!(reason(Light { intensity: std::f32::NAN, ..sun() }), "intensity must be a non-negative number");
+        assert_eq!(
+            reason(Light { intensity: std::f32::INFINITY, ..sun() }),
+            "intensity must be a non-negative number"
+        );
+        assert_eq!(reason(Light { color: [1.0, -0.5, 1.0], ..sun() }), "color channels must be non-negative");
+        assert_eq!(reason(Light { color: [std::f32::NAN; 3], ..sun() }), "color channels must be non-negative");
+        assert_eq!(reason(lamp(0.0).with_range(0.0)), "range must be positive");
+    }
+
+    #[test]
+    fn only_point_lights_may_have_no_direction() {
+        assert_eq!(reason(Light { direction: Vector3::new(0.0, 0.0, 0.0), ..sun() }), "direction must not be zero");
+        assert!(Light { direction: Vector3::new(0.0, 0.0, 0.0), ..lamp(0.0) }.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_spot_cones_out_of_order_or_wider_than_a_hemisphere() {
+        let expected = "cone angles must satisfy 0 <= inner < outer <= pi / 2";
+        assert_eq!(reason(spot(-0.1, 0.5)), expected);
+        assert_eq!(reason(spot(0.5, 0.5)), expected);
+        assert_eq!(reason(spot(0.6, 0.5)), expected);
+        assert_eq!(reason(spot(0.2, 1.6)), expected);
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut slots = LightSlots::default();
+        let first = slots.add(lamp(1.0)).unwrap();
+        let second = slots.add(lamp(2.0)).unwrap();
+        assert_eq!(slots.remove(first), Some(lamp(1.0)));
+        assert_eq!(slots.remove(first), None);
+        assert_eq!(slots.get(first), None);
+
+        let third = slots.add(lamp(3.0)).unwrap();
+        assert_eq!(third, first);
+        assert_eq!(slots.iter().cloned().collect::<Vec<_>>(), vec![lamp(3.0), lamp(2.0)]);
+        assert_eq!(slots.get(second), Some(&lamp(2.0)));
+    }
+
+    #[test]
+    fn stale_ids_refer_to_the_light_that_reused_their_slot() {
+        let mut slots = LightSlots::default();
+        let stale = slots.add(lamp(1.0)).unwrap();
+        slots.remove(stale);
+        match slots.update(stale, lamp(2.0)) {
+            Err(LightError::UnknownLight) => (),
+            result => panic!("expected UnknownLight, got {:?}", result),
+        }
+
+        slots.add(lamp(3.0)).unwrap();
+        slots.update(stale, lamp(4.0)).unwrap();
+        assert_eq!(slots.get(stale), Some(&lamp(4.0)));
+    }
+
+    #[test]
+    fn invalid_lights_leave_the_slots_unchanged() {
+        let mut slots = LightSlots::default();
+        let id = slots.add(lamp(1.0)).unwrap();
+        assert!(slots.add(lamp(2.0).with_range(-1.0)).is_err());
+        assert!(slots.update(id, lamp(2.0).with_range(-1.0)).is_err());
+        assert_eq!(slots.iter().cloned().collect::<Vec<_>>(), vec![lamp(1.0)]);
+    }
+
+    #[test]
+    fn refuses_lights_past_the_limit_until_one_is_removed() {
+        let mut slots = LightSlots::default();
+        let ids: Vec<_> = (0..MAX_LIGHTS).map(|index| slots.add(lamp(index as f32)).unwrap()).collect();
+        match slots.add(lamp(-1.0)) {
+            Err(LightError::TooManyLights { limit }) => assert_eq!(limit, MAX_LIGHTS),
+            result => panic!("expected TooManyLights, got {:?}", result),
+        }
+        slots.remove(ids[5]);
+        assert_eq!(slots.add(lamp(-1.0)).unwrap(), ids[5]);
+    }
+
+    #[test]
+    fn shadow_layers_go_to_the_first_casters() {
+        let lights = vec![lamp(0.0), sun(), spot(0.2, 0.5).with_shadows(false), spot(0.2, 0.5)];
+        let casters = shadow_casters(lights.iter(), 3);
+        let layers: Vec<_> = casters.iter().map(|(_, layers)| layers.clone()).collect();
+        assert_eq!(layers, vec![0..3, 3..4]);
+        assert!(std::ptr::eq(casters[1].0, &lights[3]));
+
+        let suns = vec![sun(); MAX_SHADOW_LAYERS];
+        let casters = shadow_casters(suns.iter(), 3);
+        assert_eq!(casters.len(), MAX_SHADOW_LAYERS / 3);
+    }
+}
@@ -0,0 +1,164 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// D3D12 requires each row of a texture to buffer copy to start at a multiple of 256 bytes, so
+// rows are padded in the readback buffer and the padding is dropped afterwards.
+const ROW_PITCH_ALIGNMENT: u32 = 256;
+// A lost device never runs the mapping callback, so the readback gives up after this long.
+const READBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum ReadbackError {
+    #[error(display = "could not map the readback buffer")]
+    MapFailed,
+    #[error(display = "the readback buffer was not mapped within {} seconds", seconds)]
+    TimedOut {
+        seconds: u64,
+    },
+}
+
+// A color target that isn't shown anywhere, for rendering without a window. Its pixels are RGBA8
+// so they can be read back and saved as they are.
+pub struct OffscreenTarget {
+    desc: wgpu::SwapChainDescriptor,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &mut wgpu::Device, width: u32, height: u32) -> OffscreenTarget {
+        let desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsageFlags::OUTPUT_ATTACHMENT | wgpu::TextureUsageFlags::TRANSFER_SRC,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            width,
+            height,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d { width, height, depth: 1 },
+            array_size: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format,
+            usage: desc.usage,
+        });
+        let view = texture.create_default_view();
+        OffscreenTarget {
+            desc,
+            texture,
+            view,
+        }
+    }
+
+    // Stands in for the swap chain's descriptor when the renderer is created or resized.
+    pub fn descriptor(&self) -> &wgpu::SwapChainDescriptor {
+        &self.desc
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // Rows are tightly packed, top row first.
+    pub fn read_pixels(&self, device: &mut wgpu::Device) -> Result<Vec<u8>, ReadbackError> {
+        let (width, height) = (self.desc.width, self.desc.height);
+        let row_pitch = padded_row_pitch(width);
+        let size = row_pitch * height;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size,
+            usage: wgpu::BufferUsageFlags::MAP_READ | wgpu::BufferUsageFlags::TRANSFER_DST,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                level: 0,
+                slice: 0,
+                origin: wgpu::Origin3d { x: 0.0, y: 0.0, z: 0.0 },
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                row_pitch,
+                image_height: height,
+            },
+            wgpu::Extent3d { width, height, depth: 1 },
+        );
+        device.get_queue().submit(&[encoder.finish()]);
+
+        // wgpu 0.2 has no way to wait for the device. Mapping callbacks only run when a later
+        // submission finds the copy finished, so empty command buffers are submitted until then.
+        let result = Rc::new(RefCell::new(None));
+        let callback_result = result.clone();
+        buffer.map_read_async(0, size, move |mapped: wgpu::BufferMapAsyncResult<&[u8]>| {
+            *callback_result.borrow_mut() = Some(match mapped {
+                wgpu::BufferMapAsyncResult::Success(pixels) => Ok(unpad_rows(pixels, width, height, row_pitch)),
+                wgpu::BufferMapAsyncResult::Error => Err(ReadbackError::MapFailed),
+            });
+        });
+        let deadline = Instant::now() + READBACK_TIMEOUT;
+        loop {
+            match result.borrow_mut().take() {
+                Some(Ok(pixels)) => {
+                    buffer.unmap();
+                    return Ok(pixels);
+                }
+                Some(Err(err)) => return Err(err),
+                None if Instant::now() >= deadline => {
+                    return Err(ReadbackError::TimedOut { seconds: READBACK_TIMEOUT.as_secs() });
+                }
+                None => (),
+            }
+            let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+            device.get_queue().submit(&[encoder.finish()]);
+            std::thread::yield_now();
+        }
+    }
+}
+
+fn padded_row_pitch(width: u32) -> u32 {
+    let unpadded = 4 * width;
+    (unpadded + ROW_PITCH_ALIGNMENT - 1) / ROW_PITCH_ALIGNMENT * ROW_PITCH_ALIGNMENT
+}
+
+fn unpad_rows(padded: &[u8], width: u32, height: u32, row_pitch: u32) -> Vec<u8> {
+    let row_size = 4 * width as usize;
+    padded.chunks(row_pitch as usize)
+        .take(height as usize)
+        .flat_map(|row| row[..row_size].iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_pitches_are_aligned_for_every_backend() {
+        assert_eq!(padded_row_pitch(1), 256);
+        assert_eq!(padded_row_pitch(64), 256);
+        assert_eq!(padded_row_pitch(65), 512);
+        assert_eq!(padded_row_pitch(256), 1024);
+    }
+
+    #[test]
+    fn padding_is_dropped_from_every_row() {
+        let (width, height) = (3, 2);
+        let row_pitch = padded_row_pitch(width);
+        let mut padded = vec![0xAA; (row_pitch * height) as usize];
+        for row in 0..height {
+            for byte in 0..4 * width {
+                padded[(row * row_pitch + byte) as usize] = (row * 4 * width + byte) as u8;
+            }
+        }
+        let pixels = unpad_rows(&padded, width, height, row_pitch);
+        assert_eq!(pixels, (0..4 * width * height).map(|byte| byte as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tightly_packed_rows_are_kept_as_they_are() {
+        let pixels: Vec<u8> = (0..=255).collect();
+        assert_eq!(unpad_rows(&pixels, 64, 1, 256), pixels);
+    }
+}
@@ -0,0 +1,406 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Transform, Vector3};
+use std::ops::Range;
+use crate::conversions::GpuBuffer;
+use crate::model_data::LightKind;
+use super::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+use super::lights::Light;
+use super::targets::GraphicsSettingsError;
+
+// Must match MAX_SHADOW_LAYERS in cube.frag.glsl. A directional light takes one layer per cascade
+// and a spot light takes one.
+pub const MAX_SHADOW_LAYERS: usize = 8;
+pub const MAX_CASCADES: usize = 4;
+
+const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::D32Float;
+
+// Objects this far outside a cascade towards the light still cast shadows into it.
+const CASTER_MARGIN: f32 = 100.0;
+const SPOT_NEAR: f32 = 0.05;
+// Far plane for spot lights without a range.
+const SPOT_FAR: f32 = 100.0;
+
+#[derive(Debug, Clone)]
+pub struct ShadowSettings {
+    // Width and height of every shadow map.
+    pub resolution: u32,
+    // Subtracted from a fragment's depth as seen from the light before it's compared.
+    pub depth_bias: f32,
+    // Slope scaled bias applied while rendering the shadow maps.
+    pub slope_bias: f32,
+    // Far end of each directional light cascade as a fraction of the camera's depth range. Up to
+    // MAX_CASCADES increasing values in (0, 1].
+    pub cascade_splits: Vec<f32>,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings {
+            resolution: 2048,
+            depth_bias: 0.002,
+            slope_bias: 2.0,
+            cascade_splits: vec![0.1, 0.3, 1.0],
+        }
+    }
+}
+
+impl ShadowSettings {
+    pub fn cascade_count(&self) -> usize {
+        self.cascade_splits.len()
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        if self.resolution == 0 {
+            return Err("resolution must be positive");
+        }
+        if self.cascade_splits.is_empty() || self.cascade_splits.len() > MAX_CASCADES.min(MAX_SHADOW_LAYERS) {
+            return Err("there must be between one and four cascades");
+        }
+        let mut previous = 0.0;
+        for &split in &self.cascade_splits {
+            if !(split > previous && split <= 1.0) {
+                return Err("cascade splits must be increasing fractions of the depth range");
+            }
+            previous = split;
+        }
+        Ok(())
+    }
+}
+
+// std140 layout of the fragment shader's Shadows block.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ShadowsUniform {
+    matrices: [Matrix4<f32>; MAX_SHADOW_LAYERS],
+    // View distance at the far end of each cascade.
+    cascade_splits: [f32; 4],
+    // Depth bias and the size of a texel in UV units.
+    params: [f32; 4],
+}
+
+// Depth maps for every shadow casting light, stored as layers of a single texture array so the
+// main pass can sample any of them.
+pub struct Shadows {
+    settings: ShadowSettings,
+    _texture: wgpu::Texture,
+    array_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    uniform_buf: GpuBuffer,
+    layer_views: Vec<wgpu::TextureView>,
+    layer_buffers: Vec<GpuBuffer>,
+    layer_bind_groups: Vec<wgpu::BindGroup>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    active_layers: usize,
+}
+
+impl Shadows {
+    pub fn new(device: &mut wgpu::Device, settings: ShadowSettings) -> Result<Shadows, GraphicsSettingsError> {
+        settings.check().map_err(|reason| GraphicsSettingsError::InvalidShadowSettings { reason })?;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: settings.resolution,
+                height: settings.resolution,
+                depth: 1,
+            },
+            array_size: MAX_SHADOW_LAYERS as u32,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsageFlags::OUTPUT_ATTACHMENT | wgpu::TextureUsageFlags::SAMPLED,
+        });
+        let view = |dimension, base_array_layer, array_count| texture.create_view(&wgpu::TextureViewDescriptor {
+            format: SHADOW_FORMAT,
+            dimension,
+            aspect: wgpu::TextureAspectFlags::DEPTH,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer,
+            array_count,
+        });
+        let array_view = view(wgpu::TextureViewDimension::D2Array, 0, MAX_SHADOW_LAYERS as u32);
+        let layer_views = (0..MAX_SHADOW_LAYERS as u32)
+            .map(|layer| view(wgpu::TextureViewDimension::D2, layer, 1))
+            .collect();
+
+        // Linear filtering on a comparison sampler blends four comparisons, which smooths the
+        // PCF kernel further.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            r_address_mode: wgpu::AddressMode::ClampToEdge,
+            s_address_mode: wgpu::AddressMode::ClampToEdge,
+            t_address_mode: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            max_anisotropy: 0,
+            compare_function: wgpu::CompareFunction::LessEqual,
+            border_color: wgpu::BorderColor::OpaqueWhite,
+        });
+
+        let uniform_buf = GpuBuffer::from_single(
+            device,
+            wgpu::BufferUsageFlags::UNIFORM | wgpu::BufferUsageFlags::TRANSFER_DST,
+            ShadowsUniform {
+                matrices: [Matrix4::identity(); MAX_SHADOW_LAYERS],
+                cascade_splits: [0.0; 4],
+                params: [settings.depth_bias, 1.0 / settings.resolution as f32, 0.0, 0.0],
+            },
+        );
+
+        // The shadow pass reuses the main vertex shader, which only reads the matrix at binding 0.
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutBinding {
+                binding: 0,
+                visibility: wgpu::ShaderStageFlags::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer,
+            }],
+        });
+        let layer_buffers: Vec<GpuBuffer> = (0..MAX_SHADOW_LAYERS)
+            .map(|_| GpuBuffer::from_single(
+                device,
+                wgpu::BufferUsageFlags::UNIFORM | wgpu::BufferUsageFlags::TRANSFER_DST,
+                Matrix4::<f32>::identity(),
+            ))
+            .collect();
+        let layer_bind_groups = layer_buffers.iter()
+            .map(|buffer| device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                bindings: &[buffer.binding(0)],
+            }))
+            .collect();
+
+        Ok(Shadows {
+            settings,
+            _texture: texture,
+            array_view,
+            sampler,
+            uniform_buf,
+            layer_views,
+            layer_buffers,
+            layer_bind_groups,
+            bind_group_layout,
+            active_layers: 0,
+        })
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    // The shadow map array, its comparison sampler and the Shadows block, in that order.
+    pub fn bindings(&self, first_binding: u32) -> [wgpu::Binding; 3] {
+        [
+            wgpu::Binding {
+                binding: first_binding,
+                resource: wgpu::BindingResource::TextureView(&self.array_view),
+            },
+            wgpu::Binding {
+                binding: first_binding + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            self.uniform_buf.binding(first_binding + 2),
+        ]
+    }
+
+    pub fn stencil_state() -> wgpu::DepthStencilStateDescriptor {
+        wgpu::DepthStencilStateDescriptor {
+            format: SHADOW_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }
+    }
+
+    // Fits a light space projection to every shadow map layer in use and uploads them.
+    pub fn update(&mut self, device: &mut wgpu::Device, camera: &Camera, casters: &[(&Light, Range<u32>)]) {
+        let (near, far) = camera.depth_range();
+        let splits = cascade_distances(near, far, &self.settings.cascade_splits);
+
+        let mut matrices = [Matrix4::identity(); MAX_SHADOW_LAYERS];
+        self.active_layers = 0;
+        for (light, layers) in casters {
+            for (cascade, layer) in layers.clone().enumerate() {
+                matrices[layer as usize] = match light.kind {
+                    LightKind::Directional => {
+                        let slice_near = if cascade == 0 { near } else { splits[cascade - 1] };
+                        let corners = camera.frustum_slice(slice_near, splits[cascade]);
+                        cascade_matrix(&corners, light.direction.normalize(), self.settings.resolution)
+                    }
+                    LightKind::Spot { outer_cone_angle, .. } => spot_matrix(light, outer_cone_angle),
+                    LightKind::Point => continue,
+                };
+            }
+            self.active_layers = self.active_layers.max(layers.end as usize);
+        }
+
+        let uniform = ShadowsUniform {
+            matrices,
+            cascade_splits: splits,
+            params: [self.settings.depth_bias, 1.0 / self.settings.resolution as f32, 0.0, 0.0],
+        };
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        let uniform_src = GpuBuffer::from_single(device, wgpu::BufferUsageFlags::TRANSFER_SRC, uniform);
+        uniform_src.copy_to_buffer(&mut encoder, &self.uniform_buf);
+        let mut staging_bufs = Vec::new();
+        for (matrix, buffer) in matrices.iter().zip(&self.layer_buffers).take(self.active_layers) {
+            let matrix_src = GpuBuffer::from_single(device, wgpu::BufferUsageFlags::TRANSFER_SRC, *matrix);
+            matrix_src.copy_to_buffer(&mut encoder, buffer);
+            staging_bufs.push(matrix_src);
+        }
+        device.get_queue().submit(&[encoder.finish()]);
+    }
+
+    // Depth attachments and bind groups of the layers that need rendering this frame.
+    pub fn active_layers(&self) -> impl Iterator<Item = (&wgpu::TextureView, &wgpu::BindGroup)> {
+        self.layer_views.iter().zip(&self.layer_bind_groups).take(self.active_layers)
+    }
+
+    pub fn attachment(view: &wgpu::TextureView) -> wgpu::RenderPassDepthStencilAttachmentDescriptor<&wgpu::TextureView> {
+        wgpu::RenderPassDepthStencilAttachmentDescriptor {
+            attachment: view,
+            depth_load_op: wgpu::LoadOp::Clear,
+            depth_store_op: wgpu::StoreOp::Store,
+            clear_depth: 1.0,
+            stencil_load_op: wgpu::LoadOp::Clear,
+            stencil_store_op: wgpu::StoreOp::Store,
+            clear_stencil: 0,
+        }
+    }
+}
+
+// View distance at the far end of each cascade.
+fn cascade_distances(near: f32, far: f32, fractions: &[f32]) -> [f32; 4] {
+    let mut splits = [0.0; 4];
+    for (split, fraction) in splits.iter_mut().zip(fractions) {
+        *split = near + (far - near) * fraction;
+    }
+    splits
+}
+
+fn up_for(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.z.abs() > 0.99 { Vector3::unit_y() } else { Vector3::unit_z() }
+}
+
+fn spot_matrix(light: &Light, outer_cone_angle: Rad<f32>) -> Matrix4<f32> {
+    let direction = light.direction.normalize();
+    let projection = cgmath::perspective(outer_cone_angle * 2.0, 1.0, SPOT_NEAR, light.range.unwrap_or(SPOT_FAR));
+    let view = Matrix4::look_at(light.position, light.position + direction, up_for(direction));
+    OPENGL_TO_WGPU_MATRIX * projection * view
+}
+
+// Fits an orthographic projection around the bounding sphere of a cascade's slice of the view
+// volume. The sphere doesn't change size as the camera turns, and snapping it to whole texels
+// keeps shadow edges from shimmering as the camera moves.
+fn cascade_matrix(corners: &[Point3<f32>; 8], direction: Vector3<f32>, resolution: u32) -> Matrix4<f32> {
+    let center = Point3::centroid(corners);
+    let radius = corners.iter()
+        .map(|corner| (corner - center).magnitude())
+        .fold(0.0f32, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let eye = center - direction * (radius + CASTER_MARGIN);
+    let view = Matrix4::look_at(eye, center, up_for(direction));
+    let projection = cgmath::ortho(-radius, radius, -radius, radius, 0.0, radius * 2.0 + CASTER_MARGIN);
+
+    let texels = resolution as f32 * 0.5;
+    let origin = (projection * view).transform_point(Point3::origin());
+    let snap = |value: f32| ((value * texels).round() - value * texels) / texels;
+    let offset = Matrix4::from_translation(Vector3::new(snap(origin.x), snap(origin.y), 0.0));
+    OPENGL_TO_WGPU_MATRIX * offset * projection * view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Deg;
+
+    fn settings(cascade_splits: Vec<f32>) -> ShadowSettings {
+        ShadowSettings { cascade_splits, ..ShadowSettings::default() }
+    }
+
+    fn camera() -> Camera {
+        Camera::new(Deg(60.0), 16.0 / 9.0, 0.5, 80.0, Point3::new(3.0, -12.0, 5.0), Point3::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn accepts_the_default_settings() {
+        assert_eq!(ShadowSettings::default().check(), Ok(()));
+        assert_eq!(settings(vec![1.0]).check(), Ok(()));
+        assert_eq!(settings(vec![0.1, 0.2, 0.5, 1.0]).check(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let no_resolution = ShadowSettings { resolution: 0, ..ShadowSettings::default() };
+        assert_eq!(no_resolution.check(), Err("resolution must be positive"));
+        let cascade_count = Err("there must be between one and four cascades");
+        assert_eq!(settings(vec![]).check(), cascade_count);
+        assert_eq!(settings(vec![0.1, 0.2, 0.3, 0.4, 1.0]).check(), cascade_count);
+        let order = Err("cascade splits must be increasing fractions of the depth range");
+        for splits in vec![vec![0.0, 1.0], vec![0.5, 0.5], vec![0.6, 0.3], vec![0.5, 1.5], vec![std::f32::NAN]] {
+            assert_eq!(settings(splits).check(), order);
+        }
+    }
+
+    #[test]
+    fn cascades_end_at_fractions_of_the_depth_range() {
+        assert_eq!(cascade_distances(1.0, 101.0, &[0.25, 0.5, 1.0]), [26.0, 51.0, 101.0, 0.0]);
+        assert_eq!(cascade_distances(0.5, 10.5, &[0.25, 0.5, 0.75, 1.0]), [3.0, 5.5, 8.0, 10.5]);
+    }
+
+    #[test]
+    fn cascades_cover_their_slice_of_the_view_volume() {
+        let camera = camera();
+        let (near, far) = camera.depth_range();
+        let splits = cascade_distances(near, far, &ShadowSettings::default().cascade_splits);
+        let direction = Vector3::new(-0.3, 0.5, -1.0).normalize();
+        for cascade in 0..3 {
+            let slice_near = if cascade == 0 { near } else { splits[cascade - 1] };
+            let corners = camera.frustum_slice(slice_near, splits[cascade]);
+            let matrix = cascade_matrix(&corners, direction, 2048);
+            for corner in &corners {
+                let clip = matrix.transform_point(*corner);
+                assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "cascade {} misses {:?}", cascade, corner);
+                assert!(clip.z >= 0.0 && clip.z <= 1.0, "cascade {} clips {:?} at depth {}", cascade, corner, clip.z);
+            }
+        }
+    }
+
+    #[test]
+    fn cascades_are_snapped_to_whole_texels() {
+        let resolution = 1024;
+        let texels = resolution as f32 * 0.5;
+        let direction = Vector3::new(0.2, 0.4, -1.0).normalize();
+        let mut camera = camera();
+        for step in 0..10 {
+            let corners = camera.frustum_slice(0.5, 8.0);
+            let origin = cascade_matrix(&corners, direction, resolution).transform_point(Point3::origin());
+            for &value in &[origin.x, origin.y] {
+                let offset = value * texels - (value * texels).round();
+                assert!(offset.abs() < 1e-2, "step {} is {} texels off the grid", step, offset);
+            }
+            camera.translate(Vector3::new(0.137, 0.071, 0.0));
+        }
+    }
+
+    #[test]
+    fn cascade_size_does_not_change_as_the_camera_turns() {
+        let direction = -Vector3::unit_z();
+        let size = |target: Point3<f32>| {
+            let camera = Camera::new(Deg(60.0), 16.0 / 9.0, 0.5, 80.0, Point3::new(0.0, 0.0, 2.0), target);
+            let matrix = cascade_matrix(&camera.frustum_slice(0.5, 8.0), direction, 2048);
+            matrix.x.x
+        };
+        let ahead = size(Point3::new(0.0, 10.0, 2.0));
+        let aside = size(Point3::new(7.0, 7.0, 1.0));
+        assert!((ahead - aside).abs() < 1e-6, "{} != {}", ahead, aside);
+    }
+}
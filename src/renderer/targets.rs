@@ -0,0 +1,291 @@
+use crate::conversions::GpuBuffer;
+use crate::shader::ShaderCompilationError;
+use super::depth::{DepthBuffer, DepthSettings};
+
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+// wgpu 0.2 doesn't report the adapter's limits, so the scene target is kept within the 2D texture
+// size every adapter we run on supports.
+pub const MAX_TARGET_SIZE: u32 = 8192;
+
+#[derive(Debug, Error)]
+pub enum GraphicsSettingsError {
+    #[error(display = "invalid graphics settings: {}", reason)]
+    InvalidSettings {
+        reason: &'static str,
+    },
+    #[error(display = "invalid shadow settings: {}", reason)]
+    InvalidShadowSettings {
+        reason: &'static str,
+    },
+    #[error(display = "could not rebuild the pipelines")]
+    PipelineFailed(#[error(cause)] ShaderCompilationError),
+}
+
+impl From<ShaderCompilationError> for GraphicsSettingsError {
+    fn from(err: ShaderCompilationError) -> Self {
+        GraphicsSettingsError::PipelineFailed(err)
+    }
+}
+
+// Settings that only affect the main pass, so they can be changed while models are loaded.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphicsSettings {
+    // Supersampling (SSAA) samples per pixel, one of SAMPLE_COUNTS. 1 turns anti-aliasing off.
+    // Every sample is shaded, so fill cost and target memory grow with the sample count.
+    pub samples: u32,
+    pub depth: DepthSettings,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> GraphicsSettings {
+        GraphicsSettings {
+            samples: 1,
+            depth: DepthSettings::default(),
+        }
+    }
+}
+
+impl GraphicsSettings {
+    // Width and height are the size of the frame the settings are used for.
+    pub fn check(&self, width: u32, height: u32) -> Result<(), &'static str> {
+        if !SAMPLE_COUNTS.contains(&self.samples) {
+            return Err("samples must be 1, 2, 4 or 8");
+        }
+        let (columns, rows) = self.sample_grid();
+        if width.saturating_mul(columns) > MAX_TARGET_SIZE || height.saturating_mul(rows) > MAX_TARGET_SIZE {
+            return Err("the frame is too large for this many samples");
+        }
+        match self.depth.format {
+            wgpu::TextureFormat::D16Unorm
+            | wgpu::TextureFormat::D32Float
+            | wgpu::TextureFormat::D24UnormS8Uint
+            | wgpu::TextureFormat::D32FloatS8Uint => Ok(()),
+            _ => Err("the depth format must be a depth texture format"),
+        }
+    }
+
+    // The same settings with as many samples as fit a frame of this size.
+    pub fn fitted(&self, width: u32, height: u32) -> GraphicsSettings {
+        let mut settings = *self;
+        while settings.samples > 1 && settings.check(width, height).is_err() {
+            settings.samples /= 2;
+        }
+        settings
+    }
+
+    // wgpu 0.2 can't create multisampled textures or resolve them, so this is supersampling: the
+    // samples are laid out as a grid of texels in a larger scene target instead.
+    fn sample_grid(&self) -> (u32, u32) {
+        match self.samples {
+            2 => (2, 1),
+            4 => (2, 2),
+            8 => (4, 2),
+            _ => (1, 1),
+        }
+    }
+}
+
+// The attachments the main pass draws into. With anti-aliasing on, the scene is rendered into a
+// color target that holds every sample and then downsampled into the frame. Both targets follow the
+// swap chain's size, so they're recreated on resize.
+pub struct RenderTargets {
+    settings: GraphicsSettings,
+    depth_buffer: DepthBuffer,
+    scene: Option<SceneTarget>,
+}
+
+struct SceneTarget {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    _grid_buf: GpuBuffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl RenderTargets {
+    pub fn new(
+        device: &mut wgpu::Device,
+        settings: GraphicsSettings,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        resolver: &Resolver,
+    ) -> RenderTargets {
+        let (columns, rows) = settings.sample_grid();
+        let (width, height) = (sc_desc.width * columns, sc_desc.height * rows);
+        let depth_buffer = DepthBuffer::new(device, settings.depth, width, height);
+
+        let scene = if settings.samples > 1 {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d { width, height, depth: 1 },
+                array_size: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: sc_desc.format,
+                usage: wgpu::TextureUsageFlags::OUTPUT_ATTACHMENT | wgpu::TextureUsageFlags::SAMPLED,
+            });
+            let view = texture.create_default_view();
+            let grid_buf = GpuBuffer::from_single(device, wgpu::BufferUsageFlags::UNIFORM, [columns, rows, 0, 0]);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &resolver.bind_group_layout,
+                bindings: &[
+                    wgpu::Binding {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::Binding {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&resolver.sampler),
+                    },
+                    grid_buf.binding(2),
+                ],
+            });
+            Some(SceneTarget {
+                _texture: texture,
+                view,
+                _grid_buf: grid_buf,
+                bind_group,
+            })
+        } else {
+            None
+        };
+
+        RenderTargets {
+            settings,
+            depth_buffer,
+            scene,
+        }
+    }
+
+    pub fn settings(&self) -> &GraphicsSettings {
+        &self.settings
+    }
+
+    // Without anti-aliasing the scene is drawn straight into the frame.
+    pub fn color_view<'a>(&'a self, frame: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        self.scene.as_ref().map_or(frame, |scene| &scene.view)
+    }
+
+    pub fn depth_attachment(&self) -> wgpu::RenderPassDepthStencilAttachmentDescriptor<&wgpu::TextureView> {
+        self.depth_buffer.attachment()
+    }
+
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, resolver: &Resolver, frame: &wgpu::TextureView) {
+        let scene = match self.scene {
+            Some(ref scene) => scene,
+            None => return,
+        };
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: frame,
+                load_op: wgpu::LoadOp::Clear,
+                store_op: wgpu::StoreOp::Store,
+                clear_color: wgpu::Color::BLACK,
+            }],
+            depth_stencil_attachment: None,
+        });
+        rpass.set_pipeline(&resolver.pipeline);
+        rpass.set_bind_group(0, &scene.bind_group);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+// Averages the samples in the scene target into the frame.
+pub struct Resolver {
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Resolver {
+    pub fn new(device: &mut wgpu::Device, vertex_shader: &[u8], fragment_shader: &[u8], color_format: wgpu::TextureFormat) -> Resolver {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutBinding {
+                    binding: 0,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 2,
+                    visibility: wgpu::ShaderStageFlags::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+
+        // The shader fetches texels directly, so filtering never applies.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            r_address_mode: wgpu::AddressMode::ClampToEdge,
+            s_address_mode: wgpu::AddressMode::ClampToEdge,
+            t_address_mode: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            max_anisotropy: 0,
+            compare_function: wgpu::CompareFunction::Always,
+            border_color: wgpu::BorderColor::TransparentBlack,
+        });
+
+        let vs_module = device.create_shader_module(vertex_shader);
+        let fs_module = device.create_shader_module(fragment_shader);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::PipelineStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: wgpu::PipelineStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            },
+            rasterization_state: wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            },
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: color_format,
+                color: wgpu::BlendDescriptor::REPLACE,
+                alpha: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWriteFlags::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[],
+            sample_count: 1,
+        });
+
+        Resolver {
+            bind_group_layout,
+            sampler,
+            pipeline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GraphicsSettings;
+
+    #[test]
+    fn rejects_sample_grids_larger_than_a_texture() {
+        let settings = GraphicsSettings { samples: 8, ..GraphicsSettings::default() };
+        assert!(settings.check(1920, 1080).is_ok());
+        // Eight samples are four texels wide, so 2560 pixels need a 10240 texel wide target.
+        assert!(settings.check(2560, 1440).is_err());
+        assert_eq!(settings.fitted(2560, 1440).samples, 4);
+        assert_eq!(settings.fitted(8192, 8192).samples, 1);
+        assert!(GraphicsSettings { samples: 3, ..settings }.check(64, 64).is_err());
+    }
+}
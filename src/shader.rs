@@ -28,18 +28,33 @@ impl From<io::Error> for ShaderCompilationError {
     }
 }
 
-fn glsl_to_spirv(source: &str, shader_kind: ShaderKind) -> Result<CompilationArtifact, ShaderCompilationError> {
+fn glsl_to_spirv(
+    source: &str,
+    shader_kind: ShaderKind,
+    defines: &[(String, String)],
+) -> Result<CompilationArtifact, ShaderCompilationError> {
     use self::ShaderCompilationError::*;
 
     let mut compiler = Compiler::new().ok_or_else(|| NullCompiler)?;
     let mut options = shaderc::CompileOptions::new().ok_or_else(|| NullOptions)?;
     options.add_macro_definition("EP", Some("main"));
+    for (name, value) in defines {
+        options.add_macro_definition(name, Some(value));
+    }
     let artifact = compiler.compile_into_spirv(source, shader_kind, "shader.glsl", "main", Some(&options))?;
     Ok(artifact)
 }
 
 pub fn load_shader(path: &str, shader_kind: ShaderKind) -> Result<Vec<u8>, ShaderCompilationError> {
     let shader_source = ::std::fs::read_to_string(path)?;
-    let artifact = glsl_to_spirv(&shader_source, shader_kind)?;
+    compile_shader(&shader_source, shader_kind, &[])
+}
+
+pub fn compile_shader(
+    source: &str,
+    shader_kind: ShaderKind,
+    defines: &[(String, String)],
+) -> Result<Vec<u8>, ShaderCompilationError> {
+    let artifact = glsl_to_spirv(source, shader_kind, defines)?;
     Ok(artifact.as_binary_u8().to_vec())
 }
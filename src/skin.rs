@@ -0,0 +1,89 @@
+use cgmath::{Matrix4, One, Quaternion, Vector3};
+
+#[derive(Debug, Clone, Copy)]
+pub struct JointTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl JointTransform {
+    pub fn identity() -> JointTransform {
+        JointTransform {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    pub node: usize,
+    pub parent: Option<usize>,
+    // Transforms of the nodes between this joint and its parent joint that aren't joints
+    // themselves, or of all its ancestors for a root joint, like a Blender armature node.
+    pub parent_transform: Matrix4<f32>,
+    pub inverse_bind_matrix: Matrix4<f32>,
+    pub rest: JointTransform,
+}
+
+#[derive(Debug, Clone)]
+pub struct Skin {
+    pub joints: Vec<Joint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pose {
+    pub local: Vec<JointTransform>,
+}
+
+impl Skin {
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn bind_pose(&self) -> Pose {
+        Pose {
+            local: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    pub fn global_transforms(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        let mut globals: Vec<Option<Matrix4<f32>>> = vec![None; self.joints.len()];
+        for i in 0..self.joints.len() {
+            self.resolve_global(i, pose, &mut globals);
+        }
+        globals.into_iter().map(|global| global.unwrap()).collect()
+    }
+
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        self.global_transforms(pose)
+            .into_iter()
+            .zip(self.joints.iter())
+            .map(|(global, joint)| global * joint.inverse_bind_matrix)
+            .collect()
+    }
+
+    fn resolve_global(&self, i: usize, pose: &Pose, globals: &mut [Option<Matrix4<f32>>]) -> Matrix4<f32> {
+        if let Some(global) = globals[i] {
+            return global;
+        }
+
+        let joint = &self.joints[i];
+        let local = joint.parent_transform * pose.local.get(i).unwrap_or(&joint.rest).matrix();
+        let global = match joint.parent {
+            Some(parent) => self.resolve_global(parent, pose, globals) * local,
+            None => local,
+        };
+        globals[i] = Some(global);
+        global
+    }
+}